@@ -0,0 +1,106 @@
+//! Contains the logic to analyze each text file inside a tar or zip archive as a separate input.
+//!
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+
+/// Number of bytes at the start of an entry which are checked for a NUL byte to detect binary
+/// entries.
+const SNIFF_LEN: usize = 8 * 1024;
+
+/// Formats of the supported archives, detected from the file's extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveKind {
+    /// Returns the kind of archive the path names, or none if it is not an archive.
+    pub fn detect<P>(path: P) -> Option<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let name = path.as_ref().file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Calls `each` with the name and a reader of every regular file inside the archive, in the order
+/// they are stored.
+///
+/// Entries which look like binary data, since they contain a NUL byte near their start, are
+/// skipped with a warning.
+pub fn for_each_entry<P, F>(path: P, kind: ArchiveKind, mut each: F) -> Result<(), String>
+where
+    P: AsRef<Path>,
+    F: FnMut(&str, &mut dyn BufRead),
+{
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| format!("{}", e))?;
+
+    let mut analyze = |name: &str, reader: &mut dyn Read| {
+        let mut reader = BufReader::with_capacity(SNIFF_LEN, reader);
+        match reader.fill_buf() {
+            Ok(start) if start.contains(&0) => {
+                eprintln!("skipping {}!{}: not a text file", path.display(), name);
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("skipping {}!{}: {}", path.display(), name, e);
+                return;
+            }
+        }
+
+        each(name, &mut reader);
+    };
+
+    match kind {
+        ArchiveKind::Tar => for_each_tar_entry(tar::Archive::new(file), &mut analyze),
+        ArchiveKind::TarGz => {
+            for_each_tar_entry(tar::Archive::new(GzDecoder::new(file)), &mut analyze)
+        }
+        ArchiveKind::Zip => {
+            let mut archive = zip::ZipArchive::new(file).map_err(|e| format!("{}", e))?;
+            for index in 0..archive.len() {
+                let mut entry = archive.by_index(index).map_err(|e| format!("{}", e))?;
+                if entry.is_file() {
+                    let name = entry.name().to_string();
+                    analyze(&name, &mut entry);
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+fn for_each_tar_entry<R, F>(mut archive: tar::Archive<R>, analyze: &mut F) -> Result<(), String>
+where
+    R: Read,
+    F: FnMut(&str, &mut dyn Read),
+{
+    for entry in archive.entries().map_err(|e| format!("{}", e))? {
+        let mut entry = entry.map_err(|e| format!("{}", e))?;
+        if entry.header().entry_type().is_file() {
+            let name = entry
+                .path()
+                .map_err(|e| format!("{}", e))?
+                .display()
+                .to_string();
+            analyze(&name, &mut entry);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,131 @@
+//! Contains the decoders which turn encoded payloads inside the records back into text before
+//! they are analyzed.
+//!
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use regex::Regex;
+
+/// Encoding of a payload inside a record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Standard base64 with the `+` and `/` alphabet, with or without padding.
+    Base64,
+
+    /// Hexadecimal digits, two per byte, in either case.
+    Hex,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "base64" => Ok(Encoding::Base64),
+            "hex" => Ok(Encoding::Hex),
+            _ => Err(format!("Invalid encoding '{}', expected base64 or hex", s)),
+        }
+    }
+}
+
+impl Encoding {
+    /// Decodes the text into bytes, or returns `None` if it is not validly encoded.
+    pub fn decode(&self, text: &str) -> Option<Vec<u8>> {
+        match self {
+            Encoding::Base64 => decode_base64(text),
+            Encoding::Hex => decode_hex(text),
+        }
+    }
+}
+
+/// Decodes the payload of each record, which is either the whole record or the text captured by
+/// the first capture group of the field regex, or its whole match if it has none.
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    encoding: Encoding,
+    field: Option<Regex>,
+
+    /// Number of records whose payload could not be decoded, shared by all clones.
+    failures: Arc<AtomicU64>,
+}
+
+impl Decoder {
+    pub fn new(encoding: Encoding, field: Option<Regex>) -> Self {
+        Decoder {
+            encoding,
+            field,
+            failures: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Returns the decoded payload of the record, replacing invalid UTF-8 sequences. Returns
+    /// `None` if the field regex does not match the record, or if the payload is not validly
+    /// encoded, which is counted as a failure.
+    pub fn decode(&self, record: &str) -> Option<String> {
+        let payload = match &self.field {
+            Some(field) => {
+                let captures = field.captures(record)?;
+                captures.get(1).or_else(|| captures.get(0))?.as_str()
+            }
+            None => record,
+        };
+
+        match self.encoding.decode(payload.trim()) {
+            Some(bytes) => Some(String::from_utf8_lossy(&bytes).into_owned()),
+            None => {
+                self.failures.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Returns the number of records whose payload could not be decoded so far.
+    pub fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let digits = text.trim_end_matches('=').as_bytes();
+    if digits.len() % 4 == 1 || text.len() - digits.len() > 2 {
+        return None;
+    }
+
+    let value = |byte: u8| -> Option<u32> {
+        match byte {
+            b'A'..=b'Z' => Some((byte - b'A') as u32),
+            b'a'..=b'z' => Some((byte - b'a' + 26) as u32),
+            b'0'..=b'9' => Some((byte - b'0' + 52) as u32),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    };
+
+    let mut bytes = Vec::with_capacity(digits.len() * 3 / 4);
+    for chunk in digits.chunks(4) {
+        let mut group = 0;
+        for (i, &digit) in chunk.iter().enumerate() {
+            group |= value(digit)? << (18 - 6 * i);
+        }
+        // A chunk of n digits holds n - 1 whole bytes.
+        for i in 0..chunk.len() - 1 {
+            bytes.push((group >> (16 - 8 * i)) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).ok()?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
@@ -1,120 +1,1547 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
 use std::path::Path;
+use std::time::Instant;
 
-use regex::Regex;
+use indexmap::IndexMap;
+use lazy_static::lazy_static;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use regex::{Regex, RegexBuilder};
+use schemars::schema::Schema;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::archive::ArchiveKind;
+use crate::meter::Metered;
+use crate::pattern::alternation;
+use crate::read::Records;
+
+pub mod archive;
+pub mod decode;
+pub mod meter;
 pub mod pattern;
+pub mod progress;
+pub mod read;
+pub mod watch;
 
-pub use crate::pattern::counter::PatternCounter;
-pub use crate::pattern::group::GroupTree;
-pub use crate::pattern::matcher::PatternMatcher;
+pub use crate::decode::{Decoder, Encoding};
+pub use crate::meter::{Summary, Throughput};
+pub use crate::pattern::bytes::{BytePattern, BytePatternMatcher};
+pub use crate::pattern::clean::{OverlapPolicy, PatternCleaner};
+pub use crate::pattern::cooccur::{CooccurrenceCounter, OverlapCounter};
+pub use crate::pattern::counter::{
+    CountMode, CountReport, PatternCounter, ReportTree, SyncPatternCounter,
+};
+pub use crate::pattern::extract::PatternExtractor;
+pub use crate::pattern::group::{
+    filter_tree, find_leaf, leaves, merge_trees, GroupTree, Named, SortMode,
+};
+pub use crate::pattern::matcher::{
+    CaptureReport, Cardinality, MatchCount, MatchReport, MatchReportTree, PatternMatcher, SortSpec,
+    Template,
+};
+pub use crate::pattern::sample::LineSampler;
+pub use crate::pattern::suggest::ShapeSuggester;
+pub use crate::pattern::timeline::TimelineCounter;
 pub use crate::pattern::vocab::Vocabulizer;
-pub use crate::pattern::{Analyzer, Pattern};
+pub use crate::pattern::{Analyzer, Merge, NumberFormat, Pattern};
+pub use crate::progress::Progress;
+pub use crate::read::{JsonField, LineLimit, ReadOptions, RecordSeparator};
+
+/// Path which refers to the standard input instead of a file.
+pub const STDIN: &str = "-";
 
-pub fn count_file<P>(path: P, tree: Vec<GroupTree<Pattern>>) -> Result<PatternCounter, String>
+/// Opens the file at `path` for reading, or the standard input if the path is `-`.
+pub fn open_input<P>(path: P) -> Result<Box<dyn BufRead>, String>
 where
     P: AsRef<Path>,
 {
-    let mut counter = PatternCounter::new(tree);
+    if path.as_ref() == Path::new(STDIN) {
+        Ok(Box::new(BufReader::new(io::stdin())))
+    } else {
+        let file = File::open(path).map_err(|e| format!("{}", e))?;
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+/// Reads the whole file at `path`, or the standard input if the path is `-`, into a single
+/// record, replacing invalid UTF-8 sequences.
+pub fn read_input<P>(path: P) -> Result<String, String>
+where
+    P: AsRef<Path>,
+{
+    let mut data = Vec::new();
+    open_input(path)?
+        .read_to_end(&mut data)
+        .map_err(|e| format!("{}", e))?;
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+/// Feeds every record of the file at `path`, or of the standard input if the path is `-`, into
+/// the analyzer.
+pub fn analyze_file<A, T, P>(path: P, analyzer: A, options: &ReadOptions) -> Result<A, String>
+where
+    A: Analyzer<T>,
+    P: AsRef<Path>,
+{
+    analyze_reader(open_input(path)?, analyzer, options)
+}
+
+/// Splits the file at `path` into `jobs` chunks at record boundaries, analyzes each chunk with a
+/// separate analyzer on its own thread, and merges the results.
+///
+/// Falls back to [`analyze_file`] for the standard input, which cannot be split.
+pub fn analyze_file_parallel<A, T, P, F>(
+    path: P,
+    jobs: usize,
+    new_analyzer: F,
+    options: &ReadOptions,
+) -> Result<A, String>
+where
+    A: Analyzer<T> + Merge + Send,
+    P: AsRef<Path>,
+    F: Fn() -> A + Sync,
+{
+    let path = path.as_ref();
+    if jobs <= 1 || path == Path::new(STDIN) {
+        return analyze_file(path, new_analyzer(), options);
+    }
+
+    let chunks = read::chunks(path, jobs, options.separator).map_err(|e| format!("{}", e))?;
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .map_err(|e| format!("{}", e))?;
 
-    let file = File::open(path).map_err(|e| format!("{}", e))?;
-    let reader = BufReader::new(file);
+    let analyzers = pool.install(|| {
+        chunks
+            .into_par_iter()
+            .map(|(start, end)| {
+                let mut file = File::open(path).map_err(|e| format!("{}", e))?;
+                file.seek(SeekFrom::Start(start))
+                    .map_err(|e| format!("{}", e))?;
+                let reader = BufReader::new(file.take(end - start));
+                analyze_reader(reader, new_analyzer(), options)
+            })
+            .collect::<Result<Vec<_>, _>>()
+    })?;
 
-    for line in reader.lines() {
-        let line = line.unwrap();
-        counter.analyze(line);
+    let mut analyzers = analyzers.into_iter();
+    let mut analyzer = analyzers.next().unwrap_or_else(&new_analyzer);
+    for other in analyzers {
+        analyzer.merge(other);
     }
+    analyzer.status()?;
 
-    Ok(counter)
+    Ok(analyzer)
+}
+
+/// Analyzes the file like [`analyze_file_parallel`], and measures how fast it was analyzed.
+pub fn analyze_file_metered<A, T, P, F>(
+    path: P,
+    jobs: usize,
+    new_analyzer: F,
+    options: &ReadOptions,
+) -> Result<(A, Throughput), String>
+where
+    A: Analyzer<T> + Merge + Send,
+    P: AsRef<Path>,
+    F: Fn() -> A + Sync,
+{
+    let start = Instant::now();
+    let metered = analyze_file_parallel(path, jobs, || Metered::new(new_analyzer()), options)?;
+    let (analyzer, lines, bytes) = metered.into_inner();
+    let throughput = Throughput {
+        lines,
+        bytes,
+        elapsed: start.elapsed(),
+    };
+    Ok((analyzer, throughput))
+}
+
+/// Analyzes the file like [`analyze_file_metered`] or, if it is a tar or zip archive, each text
+/// file inside it with a separate analyzer. Calls `each` with the name of the archive entry, if
+/// any, and the results of the file or entry. The progress of the options is cleared before, so
+/// that `each` can write the results.
+pub fn analyze_input_metered<A, T, P, F, G>(
+    path: P,
+    jobs: usize,
+    new_analyzer: F,
+    options: &ReadOptions,
+    mut each: G,
+) -> Result<(), String>
+where
+    A: Analyzer<T> + Merge + Send,
+    P: AsRef<Path>,
+    F: Fn() -> A + Sync,
+    G: FnMut(Option<&str>, Result<(A, Throughput), String>),
+{
+    let kind = match ArchiveKind::detect(&path) {
+        Some(kind) => kind,
+        None => {
+            let result = analyze_file_metered(path, jobs, new_analyzer, options);
+            if let Some(progress) = &options.progress {
+                progress.clear();
+            }
+            each(None, result);
+            return Ok(());
+        }
+    };
+
+    archive::for_each_entry(path, kind, |name, reader| {
+        let start = Instant::now();
+        let result = analyze_reader(reader, Metered::new(new_analyzer()), options).map(|metered| {
+            let (analyzer, lines, bytes) = metered.into_inner();
+            let throughput = Throughput {
+                lines,
+                bytes,
+                elapsed: start.elapsed(),
+            };
+            (analyzer, throughput)
+        });
+        if let Some(progress) = &options.progress {
+            progress.clear();
+        }
+        each(Some(name), result);
+        if let Some(progress) = &options.progress {
+            progress.restart();
+        }
+    })
+    .map(|()| {
+        if let Some(progress) = &options.progress {
+            progress.clear();
+        }
+    })
+}
+
+/// Number of records between two checks of the [`ReadOptions::deadline`] and updates of the
+/// [`ReadOptions::progress`].
+const CHECK_INTERVAL: u64 = 4096;
+
+/// Feeds every record of the reader into the analyzer, until the deadline of the options has
+/// passed.
+pub fn analyze_reader<A, T, R>(
+    reader: R,
+    mut analyzer: A,
+    options: &ReadOptions,
+) -> Result<A, String>
+where
+    A: Analyzer<T>,
+    R: BufRead,
+{
+    let mut records = Records::new(reader, options.clone());
+    let mut lines = 0;
+    let mut reported = (0, 0);
+    let mut report_progress = |bytes: u64, lines: u64| {
+        if let Some(progress) = &options.progress {
+            progress.advance(bytes - reported.0, lines - reported.1);
+            reported = (bytes, lines);
+        }
+    };
+
+    while let Some(line) = records.next() {
+        // Reading the clock and drawing the progress for every record would slow down the
+        // analysis noticeably.
+        if lines % CHECK_INTERVAL == 0 {
+            if options.timed_out() {
+                break;
+            }
+            report_progress(records.bytes_read(), lines);
+        }
+        lines += 1;
+
+        let line = line.map_err(|e| format!("{}", e))?;
+        let line = match &options.json_field {
+            Some(field) => match field.extract(&line) {
+                Ok(value) => value,
+                Err(_) if options.skip_invalid_json => continue,
+                Err(e) => return Err(e),
+            },
+            None => line,
+        };
+        let line = match &options.decoder {
+            Some(decoder) => match decoder.decode(&line) {
+                Some(decoded) => decoded,
+                None => continue,
+            },
+            None => line,
+        };
+        analyzer.analyze(line);
+        analyzer.status()?;
+    }
+    report_progress(records.bytes_read(), lines);
+
+    Ok(analyzer)
+}
+
+pub fn count_file<P>(
+    path: P,
+    tree: Vec<GroupTree<Pattern>>,
+    sort: SortMode,
+) -> Result<PatternCounter, String>
+where
+    P: AsRef<Path>,
+{
+    analyze_file(
+        path,
+        PatternCounter::new(tree, sort),
+        &ReadOptions::default(),
+    )
 }
 
 pub fn match_file<P>(
     path: P,
     tree: Vec<GroupTree<Pattern>>,
     top: usize,
+    sort: SortMode,
 ) -> Result<PatternMatcher, String>
 where
     P: AsRef<Path>,
 {
-    let mut matches = PatternMatcher::new(tree, top);
+    analyze_file(
+        path,
+        PatternMatcher::new(tree, top, sort),
+        &ReadOptions::default(),
+    )
+}
 
-    let file = File::open(path).map_err(|e| format!("{}", e))?;
-    let reader = BufReader::new(file);
+/// Matches the patterns against the raw bytes of the file at `path`, without splitting it into
+/// lines. The whole file is read into memory.
+pub fn match_binary_file<P>(
+    path: P,
+    tree: Vec<GroupTree<BytePattern>>,
+    top: usize,
+    sort: SortMode,
+) -> Result<BytePatternMatcher, String>
+where
+    P: AsRef<Path>,
+{
+    let mut matcher = BytePatternMatcher::new(tree, top, sort);
+    let mut data = Vec::new();
+    open_input(path)?
+        .read_to_end(&mut data)
+        .map_err(|e| format!("{}", e))?;
+    matcher.analyze_bytes(&data);
+
+    Ok(matcher)
+}
+
+/// Writes the top matches of each pattern to `<pattern>.tsv` inside the directory, which is
+/// created if needed, for word cloud tools. Each line holds a match and its count, separated by a
+/// tab. Characters of the fully-qualified pattern names which are not safe in file names are
+/// replaced by `_`, as are tabs and line breaks inside the matches.
+pub fn write_wordclouds<P>(dir: P, matcher: &PatternMatcher) -> Result<(), String>
+where
+    P: AsRef<Path>,
+{
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+
+    for (name, matches) in matcher.top_matches() {
+        let file_name: String = name
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+                _ => '_',
+            })
+            .collect();
+        let path = dir.join(format!("{}.tsv", file_name));
+
+        let write = || -> io::Result<()> {
+            let mut writer = BufWriter::new(File::create(&path)?);
+            for (value, count) in matches {
+                let value = value.replace(['\t', '\n', '\r'], "_");
+                writeln!(writer, "{}\t{}", value, count)?;
+            }
+            writer.flush()
+        };
+        write().map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
 
-    for line in reader.lines() {
-        let line = line.unwrap();
-        matches.analyze(line);
+/// Cleans the file at `path` in place. The cleaned lines are written to a temporary file in the
+/// same directory, which is synced and then renamed over the original, so that an interrupted run
+/// never leaves a half-written file. The permissions of the original are kept, and with `backup`
+/// the original is first copied to `<path>.bak`.
+pub fn clean_file_in_place<P>(
+    path: P,
+    tree: Vec<GroupTree<Pattern>>,
+    options: &ReadOptions,
+    policy: OverlapPolicy,
+    backup: bool,
+) -> Result<(), String>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    if path == Path::new(STDIN) {
+        return Err(String::from("Unable to clean the standard input in place"));
     }
 
-    Ok(matches)
+    let permissions = fs::metadata(path)
+        .map_err(|e| format!("{}", e))?
+        .permissions();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let temp = tempfile::NamedTempFile::new_in(dir).map_err(|e| format!("{}", e))?;
+
+    let mut cleaner = PatternCleaner::new(tree, BufWriter::new(temp));
+    cleaner.overlap_policy(policy);
+    let mut cleaner = analyze_file(path, cleaner, options)?;
+    cleaner
+        .format(&mut io::sink())
+        .map_err(|e| format!("{}", e))?;
+    let temp = cleaner
+        .into_writer()
+        .into_inner()
+        .map_err(|e| format!("{}", e.error()))?;
+    temp.as_file().sync_all().map_err(|e| format!("{}", e))?;
+    fs::set_permissions(temp.path(), permissions).map_err(|e| format!("{}", e))?;
+
+    if backup {
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(".bak");
+        fs::copy(path, backup).map_err(|e| format!("{}", e))?;
+    }
+    temp.persist(path).map_err(|e| format!("{}", e.error))?;
+
+    Ok(())
+}
+
+pub fn count_vocab<P>(path: P, top: usize) -> Result<Vocabulizer, String>
+where
+    P: AsRef<Path>,
+{
+    analyze_file(path, Vocabulizer::new(top), &ReadOptions::default())
+}
+
+/// Loads the reports saved with `count --format json` from each file, and adds them up into a
+/// single report. Each file may contain several reports, e.g. one per analyzed file.
+pub fn sum_reports<P>(paths: &[P]) -> Result<CountReport, String>
+where
+    P: AsRef<Path>,
+{
+    let mut sum: Option<CountReport> = None;
+    for path in paths {
+        let path = path.as_ref();
+        let reader = open_input(path)?;
+        for report in serde_json::Deserializer::from_reader(reader).into_iter::<CountReport>() {
+            let report = report.map_err(|e| format!("{}: {}", path.display(), e))?;
+            match &mut sum {
+                Some(sum) => sum
+                    .merge(report)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?,
+                None => sum = Some(report),
+            }
+        }
+    }
+    sum.ok_or_else(|| String::from("No reports to sum"))
+}
+
+/// Writes whether the pattern matches the line, and if so the span, text, and captures of each
+/// match.
+pub fn explain<W>(writer: &mut W, pattern: &Pattern, line: &str) -> io::Result<()>
+where
+    W: Write,
+{
+    writeln!(writer, "Pattern: {}", pattern.name)?;
+    if pattern.source != pattern.regex.as_str() {
+        writeln!(writer, "Source:  {}", pattern.source)?;
+    }
+    writeln!(writer, "Regex:   {}", pattern.regex.as_str())?;
+    if let Some(unless) = &pattern.unless {
+        writeln!(writer, "Unless:  {}", unless.as_str())?;
+    }
+    if let Some(when) = &pattern.when {
+        writeln!(writer, "When:    {}", when.as_str())?;
+    }
+    writeln!(writer, "Line:    {:?}", line)?;
+
+    if !write_matches(writer, &pattern.regex, line, "")? {
+        writeln!(writer, "No match")?;
+    } else if pattern
+        .when
+        .as_ref()
+        .is_some_and(|when| !when.is_match(line))
+    {
+        writeln!(writer, "Excluded, since the line does not match when")?;
+    } else if pattern.excludes(line) {
+        writeln!(writer, "Excluded, since the line matches unless")?;
+    }
+    Ok(())
+}
+
+/// Writes the patterns which match the line, each followed by its matches and captures.
+pub fn test_line<W>(writer: &mut W, patterns: &[GroupTree<Pattern>], line: &str) -> io::Result<()>
+where
+    W: Write,
+{
+    let mut matched = false;
+    for (path, pattern) in leaves(patterns) {
+        if !pattern.regex.is_match(line) {
+            continue;
+        }
+
+        if pattern.excludes(line) {
+            writeln!(writer, "{} (excluded by unless or when)", path)?;
+        } else {
+            matched = true;
+            writeln!(writer, "{}", path)?;
+            write_matches(writer, &pattern.regex, line, "  ")?;
+        }
+    }
+
+    if !matched {
+        writeln!(writer, "No pattern matched")?;
+    }
+    Ok(())
+}
+
+/// Lines which a regex must match all of to be reported as matching every line.
+const LINT_PROBES: [&str; 4] = ["", "x", " \t", "\u{0}"];
+
+/// Writes a warning for each group of patterns with the same regex as written in the patterns
+/// file, each group of patterns with the same name in different groups, and each pattern without
+/// `unless` or `when` which matches every line. Returns the number of warnings.
+pub fn lint<W>(writer: &mut W, patterns: &[GroupTree<Pattern>]) -> io::Result<usize>
+where
+    W: Write,
+{
+    let leaves = leaves(patterns);
+    let mut by_source: IndexMap<&str, Vec<&str>> = IndexMap::new();
+    let mut by_name: IndexMap<&str, Vec<&str>> = IndexMap::new();
+    for (path, pattern) in &leaves {
+        by_source.entry(&pattern.source).or_default().push(path);
+        by_name.entry(&pattern.name).or_default().push(path);
+    }
+
+    let mut warnings = 0;
+    for (source, paths) in by_source.iter().filter(|(_, paths)| paths.len() > 1) {
+        writeln!(writer, "Duplicate regex '{}': {}", source, paths.join(", "))?;
+        warnings += 1;
+    }
+    for (name, paths) in by_name.iter().filter(|(_, paths)| paths.len() > 1) {
+        writeln!(writer, "Duplicate name '{}': {}", name, paths.join(", "))?;
+        warnings += 1;
+    }
+    for (path, pattern) in &leaves {
+        // A pattern matching everything but the lines excluded by `unless` or `when` is useful.
+        let filtered = pattern.unless.is_some() || pattern.when.is_some();
+        if !filtered
+            && LINT_PROBES
+                .iter()
+                .all(|probe| pattern.regex.is_match(probe))
+        {
+            writeln!(
+                writer,
+                "Matches every line: {} ('{}')",
+                path, pattern.source
+            )?;
+            warnings += 1;
+        }
+    }
+    Ok(warnings)
+}
+
+/// Writes the span and text of each match of the regex in the line, together with all of its
+/// capture groups, each line prefixed with `indent`. Returns whether the regex matched at all.
+fn write_matches<W>(writer: &mut W, regex: &Regex, line: &str, indent: &str) -> io::Result<bool>
+where
+    W: Write,
+{
+    let mut matched = false;
+    for (i, captures) in regex.captures_iter(line).enumerate() {
+        matched = true;
+        let mat = captures.get(0).unwrap();
+        writeln!(
+            writer,
+            "{}Match {}: {}..{} {:?}",
+            indent,
+            i + 1,
+            mat.start(),
+            mat.end(),
+            mat.as_str()
+        )?;
+
+        for (index, name) in regex.capture_names().enumerate().skip(1) {
+            let label = match name {
+                Some(name) => format!("{} ({})", index, name),
+                None => index.to_string(),
+            };
+            match captures.get(index) {
+                Some(group) => writeln!(
+                    writer,
+                    "{}  {}: {}..{} {:?}",
+                    indent,
+                    label,
+                    group.start(),
+                    group.end(),
+                    group.as_str()
+                )?,
+                None => writeln!(writer, "{}  {}: did not participate", indent, label)?,
+            }
+        }
+    }
+    Ok(matched)
 }
 
-pub fn count_vocab<P>(path: P) -> Result<Vocabulizer, String> where P: AsRef<Path>, {
-    let mut vocab = Vocabulizer::new();
+/// Node of the patterns file.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum PatternTreeHelper {
+    /// Contains just a regex.
+    Leaf(String),
 
-    let file = File::open(path).map_err(|e| format!("{}", e))?;
-    let reader = BufReader::new(file);
+    /// Contains a regex together with additional options.
+    Detailed(PatternHelper),
 
-    for line in reader.lines() {
-        let line = line.unwrap();
-        vocab.analyze(line);
+    /// Contains a map from a name to either a regex or to another sub-group, in the order in
+    /// which they appear in the file.
+    Node(IndexMap<String, PatternTreeHelper>),
+}
+
+/// Leaf pattern that sets options besides the regex.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct PatternHelper {
+    /// Regex matched against each line, which may reference definitions as `{{name}}`.
+    regex: String,
+
+    /// Weight of a single match when scoring, defaults to 1.0.
+    #[serde(default = "default_weight")]
+    weight: f64,
+
+    /// Names of the capture groups whose values are counted separately when matching.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    captures: Vec<String>,
+
+    /// Regex which excludes a line from the pattern, although the pattern matches it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    unless: Option<String>,
+
+    /// Regex which a line must match for the pattern to be evaluated on it at all.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    when: Option<String>,
+
+    /// Replacement of each match when cleaning, which may reference capture groups as `$1` or
+    /// `${name}`. Matches are removed if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    replace: Option<String>,
+
+    /// Keep Unicode mode for this pattern when the patterns are recompiled as ASCII with
+    /// `--ascii`.
+    #[serde(default, skip_serializing_if = "is_false")]
+    keep_unicode: bool,
+
+    /// Number of matches shown for this pattern by the matcher, overriding `--top`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    top: Option<usize>,
+}
+
+impl PatternHelper {
+    /// Returns whether any option besides the regex differs from its default.
+    fn has_options(&self) -> bool {
+        self.weight != default_weight()
+            || !self.captures.is_empty()
+            || self.unless.is_some()
+            || self.when.is_some()
+            || self.replace.is_some()
+            || self.keep_unicode
+            || self.top.is_some()
+    }
+}
+
+impl Default for PatternHelper {
+    fn default() -> Self {
+        PatternHelper {
+            regex: String::new(),
+            weight: default_weight(),
+            captures: Vec::new(),
+            unless: None,
+            when: None,
+            replace: None,
+            keep_unicode: false,
+            top: None,
+        }
     }
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
 
-    Ok(vocab)
+/// Top level of the patterns file.
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct PatternFileHelper {
+    /// Reusable regex fragments, which can be referenced as `{{name}}` inside any pattern.
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    definitions: IndexMap<String, String>,
+
+    #[serde(flatten)]
+    patterns: IndexMap<String, PatternTreeHelper>,
 }
 
 pub fn parse_input<P>(path: P) -> Result<Vec<GroupTree<Pattern>>, String>
 where
     P: AsRef<Path>,
 {
-    #[derive(Serialize, Deserialize)]
-    #[serde(untagged)]
-    enum PatternTreeHelper {
-        /// Contains just a regex.
-        Leaf(String),
+    parse_tree(path, |name, source, helper| {
+        let regex = Regex::new(&helper.regex).map_err(|e| format!("{}", e))?;
+        if let Some(capture) = helper.captures.iter().find(|capture| {
+            !regex
+                .capture_names()
+                .any(|name| name == Some(capture.as_str()))
+        }) {
+            return Err(format!("Unknown capture group '{}'", capture));
+        }
+
+        let unless = match &helper.unless {
+            Some(unless) => Some(Regex::new(unless).map_err(|e| format!("unless: {}", e))?),
+            None => None,
+        };
+        let when = match &helper.when {
+            Some(when) => Some(Regex::new(when).map_err(|e| format!("when: {}", e))?),
+            None => None,
+        };
+
+        Ok(Pattern {
+            weight: helper.weight,
+            captures: helper.captures.clone(),
+            unless,
+            when,
+            replace: helper.replace.clone(),
+            keep_unicode: helper.keep_unicode,
+            top: helper.top,
+            ..Pattern::new(name, source.to_string(), regex)
+        })
+    })
+}
 
-        /// Contains a map from a name to either a regex or to another sub-group.
-        Node(HashMap<String, PatternTreeHelper>),
+/// Turns each regex into a pattern named after the regex itself, reporting all invalid regexes.
+pub fn parse_expressions(exprs: &[String]) -> Result<Vec<GroupTree<Pattern>>, String> {
+    let mut patterns = Vec::new();
+    let mut invalid = Vec::new();
+    for expr in exprs {
+        match Regex::new(expr) {
+            Ok(regex) => patterns.push(GroupTree::Leaf(Pattern::new(
+                expr.clone(),
+                expr.clone(),
+                regex,
+            ))),
+            Err(e) => invalid.push(format!("Invalid pattern '{}': {}", expr, e)),
+        }
+    }
+    if !invalid.is_empty() {
+        return Err(invalid.join("\n"));
     }
 
-    let file = File::open(path).map_err(|e| format!("{}", e))?;
-    let reader = BufReader::new(file);
-    let pattern_tree: HashMap<String, PatternTreeHelper> =
-        serde_yaml::from_reader(reader).expect("Failed to parse YAML");
+    Ok(patterns)
+}
 
-    fn traverse(name: String, tree: &PatternTreeHelper) -> Result<GroupTree<Pattern>, String> {
+/// Writes the patterns as a patterns file, which parses back into the same tree. Definitions are
+/// already expanded in the written regexes.
+pub fn dump_patterns<W>(writer: W, patterns: &[GroupTree<Pattern>]) -> Result<(), String>
+where
+    W: Write,
+{
+    dump_config(writer, patterns, None)
+}
+
+/// Writes the patterns as a patterns file like [`dump_patterns`]. If definitions are given, they
+/// are written as well, and each regex is written as in its patterns file, still referencing the
+/// definitions. The `unless` and `when` regexes are always written expanded.
+pub fn dump_config<W>(
+    writer: W,
+    patterns: &[GroupTree<Pattern>],
+    definitions: Option<&IndexMap<String, String>>,
+) -> Result<(), String>
+where
+    W: Write,
+{
+    fn to_helper(tree: &GroupTree<Pattern>, expand: bool) -> (String, PatternTreeHelper) {
+        match tree {
+            GroupTree::Leaf(pattern) => {
+                let regex = if expand {
+                    pattern.regex.as_str().to_string()
+                } else {
+                    pattern.source.clone()
+                };
+                let helper = PatternHelper {
+                    regex,
+                    weight: pattern.weight,
+                    captures: pattern.captures.clone(),
+                    unless: pattern
+                        .unless
+                        .as_ref()
+                        .map(|unless| unless.as_str().to_string()),
+                    when: pattern.when.as_ref().map(|when| when.as_str().to_string()),
+                    replace: pattern.replace.clone(),
+                    keep_unicode: pattern.keep_unicode,
+                    top: pattern.top,
+                };
+                let helper = if helper.has_options() {
+                    PatternTreeHelper::Detailed(helper)
+                } else {
+                    PatternTreeHelper::Leaf(helper.regex)
+                };
+                (pattern.name.clone(), helper)
+            }
+            GroupTree::Group { name, group } => (
+                name.clone(),
+                PatternTreeHelper::Node(group.iter().map(|tree| to_helper(tree, expand)).collect()),
+            ),
+        }
+    }
+
+    let file = PatternFileHelper {
+        definitions: definitions.cloned().unwrap_or_default(),
+        patterns: patterns
+            .iter()
+            .map(|tree| to_helper(tree, definitions.is_none()))
+            .collect(),
+    };
+    serde_yaml::to_writer(writer, &file).map_err(|e| format!("{}", e))
+}
+
+/// Returns the JSON Schema of the patterns file, which is derived from the same types the file is
+/// parsed with.
+pub fn patterns_schema() -> String {
+    let mut schema = schemars::schema_for!(PatternFileHelper);
+
+    // The flattened map of patterns does not show up in the derived schema.
+    if let Some(object) = schema.schema.object.as_mut() {
+        let patterns = Schema::new_ref(String::from("#/definitions/PatternTreeHelper"));
+        object.additional_properties = Some(Box::new(patterns));
+    }
+    serde_json::to_string_pretty(&schema).unwrap()
+}
+
+/// Parses each of the patterns files, merging groups with the same name.
+pub fn parse_inputs<P>(paths: &[P]) -> Result<Vec<GroupTree<Pattern>>, String>
+where
+    P: AsRef<Path>,
+{
+    merge_inputs(paths, |path| parse_input(path))
+}
+
+/// Parses each of the patterns files like [`parse_binary_input`], merging groups with the same
+/// name.
+pub fn parse_binary_inputs<P>(paths: &[P]) -> Result<Vec<GroupTree<BytePattern>>, String>
+where
+    P: AsRef<Path>,
+{
+    merge_inputs(paths, |path| parse_binary_input(path))
+}
+
+/// Parses each of the patterns files with `parse`, and merges the trees in the order of the
+/// files.
+fn merge_inputs<P, T, F>(paths: &[P], parse: F) -> Result<Vec<GroupTree<T>>, String>
+where
+    P: AsRef<Path>,
+    T: pattern::group::Named,
+    F: Fn(&P) -> Result<Vec<GroupTree<T>>, String>,
+{
+    let mut trees = Vec::new();
+    for path in paths {
+        let tree = parse(path).map_err(|e| format!("{}: {}", path.as_ref().display(), e))?;
+        merge_trees(&mut trees, tree).map_err(|e| format!("{}: {}", path.as_ref().display(), e))?;
+    }
+    Ok(trees)
+}
+
+/// Parses the patterns file like [`parse_input`], but compiles each pattern into a regex which
+/// matches raw bytes instead of UTF-8 strings.
+///
+/// Byte patterns only count their matches, so patterns which set `unless`, `when`, or `captures`
+/// are rejected rather than counted differently than in text mode.
+pub fn parse_binary_input<P>(path: P) -> Result<Vec<GroupTree<BytePattern>>, String>
+where
+    P: AsRef<Path>,
+{
+    parse_tree(path, |name, _, helper| {
+        let unsupported = [
+            ("unless", helper.unless.is_some()),
+            ("when", helper.when.is_some()),
+            ("captures", !helper.captures.is_empty()),
+        ];
+        if let Some((option, _)) = unsupported.iter().find(|(_, set)| *set) {
+            return Err(format!("{} is not supported for binary patterns", option));
+        }
+
+        let regex = regex::bytes::Regex::new(&helper.regex).map_err(|e| format!("{}", e))?;
+        Ok(BytePattern { name, regex })
+    })
+}
+
+/// Prefix of a patterns path which names an environment variable holding the patterns instead of
+/// a file, e.g. `env:PATTERNS`.
+pub const ENV_PREFIX: &str = "env:";
+
+/// Reads the definitions of each of the patterns files, failing if two files define the same
+/// name differently.
+pub fn parse_definitions<P>(paths: &[P]) -> Result<IndexMap<String, String>, String>
+where
+    P: AsRef<Path>,
+{
+    let mut definitions = IndexMap::new();
+    for path in paths {
+        let path = path.as_ref();
+        let file: PatternFileHelper = serde_yaml::from_reader(open_patterns(path)?)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        for (name, fragment) in file.definitions {
+            match definitions.get(&name) {
+                Some(existing) if *existing != fragment => {
+                    return Err(format!(
+                        "{}: Conflicting definition of '{}'",
+                        path.display(),
+                        name
+                    ))
+                }
+                Some(_) => {}
+                None => {
+                    definitions.insert(name, fragment);
+                }
+            }
+        }
+    }
+    Ok(definitions)
+}
+
+/// Opens the patterns file at the path, or, if the path starts with [`ENV_PREFIX`], reads the
+/// patterns from the named environment variable, which must be set and not empty.
+fn open_patterns(path: &Path) -> Result<Box<dyn Read>, String> {
+    match path.to_str().and_then(|path| path.strip_prefix(ENV_PREFIX)) {
+        Some(name) => match env::var(name) {
+            Ok(value) if value.trim().is_empty() => {
+                Err(format!("Environment variable {} is empty", name))
+            }
+            Ok(value) => Ok(Box::new(Cursor::new(value))),
+            Err(env::VarError::NotPresent) => {
+                Err(format!("Environment variable {} is not set", name))
+            }
+            Err(e) => Err(format!("Environment variable {}: {}", name, e)),
+        },
+        None => {
+            let file = File::open(path).map_err(|e| format!("{}", e))?;
+            Ok(Box::new(BufReader::new(file)))
+        }
+    }
+}
+
+/// Parses the patterns file into a tree, calling `compile` for each leaf with its name, its regex
+/// exactly as written in the file, and its options after all definitions have been expanded.
+///
+/// The patterns are compiled in parallel, while the order of the tree is kept as in the file.
+fn parse_tree<P, T, F>(path: P, compile: F) -> Result<Vec<GroupTree<T>>, String>
+where
+    P: AsRef<Path>,
+    T: Send,
+    F: Fn(String, &str, &PatternHelper) -> Result<T, String> + Sync,
+{
+    let reader = open_patterns(path.as_ref())?;
+    let PatternFileHelper {
+        definitions,
+        patterns: pattern_tree,
+    } = serde_yaml::from_reader(reader).map_err(|e| format!("{}", e))?;
+
+    fn compile_leaf<T, F>(
+        name: String,
+        source: &str,
+        helper: &PatternHelper,
+        compile: &F,
+    ) -> Result<GroupTree<T>, String>
+    where
+        F: Fn(String, &str, &PatternHelper) -> Result<T, String>,
+    {
+        let label = name.clone();
+        compile(name, source, helper)
+            .map(GroupTree::Leaf)
+            .map_err(|e| format!("{}: {}", label, e))
+    }
+
+    fn traverse<T, F>(
+        name: String,
+        tree: &PatternTreeHelper,
+        definitions: &IndexMap<String, String>,
+        prefix: &Prefix,
+        compile: &F,
+    ) -> Result<GroupTree<T>, String>
+    where
+        T: Send,
+        F: Fn(String, &str, &PatternHelper) -> Result<T, String> + Sync,
+    {
         match tree {
             PatternTreeHelper::Leaf(pattern) => {
-                let regex = Regex::new(pattern).map_err(|e| format!("{}", e))?;
-                Ok(GroupTree::Leaf(Pattern { name, regex }))
+                let expanded = expand_definitions(pattern, definitions, &mut Vec::new())?;
+                let helper = PatternHelper {
+                    regex: Prefix::apply(&prefix.regex, &expanded),
+                    ..PatternHelper::default()
+                };
+                compile_leaf(
+                    name,
+                    &Prefix::apply(&prefix.source, pattern),
+                    &helper,
+                    compile,
+                )
+            }
+            PatternTreeHelper::Detailed(helper) => {
+                let unless = match &helper.unless {
+                    Some(unless) => Some(expand_definitions(unless, definitions, &mut Vec::new())?),
+                    None => None,
+                };
+                let when = match &helper.when {
+                    Some(when) => Some(expand_definitions(when, definitions, &mut Vec::new())?),
+                    None => None,
+                };
+                let regex = expand_definitions(&helper.regex, definitions, &mut Vec::new())?;
+                let expanded = PatternHelper {
+                    regex: Prefix::apply(&prefix.regex, &regex),
+                    unless,
+                    when,
+                    ..helper.clone()
+                };
+                compile_leaf(
+                    name,
+                    &Prefix::apply(&prefix.source, &helper.regex),
+                    &expanded,
+                    compile,
+                )
             }
             PatternTreeHelper::Node(map) => {
-                let (patterns, invalid): (Vec<_>, Vec<_>) = map
-                    .iter()
-                    .map(|(name, helper)| traverse(name.clone(), helper))
-                    .partition(Result::is_ok);
-                let patterns: Vec<_> = patterns.into_iter().map(Result::unwrap).collect();
-                let invalid: Vec<_> = invalid.into_iter().map(Result::unwrap).collect();
-                if !invalid.is_empty() {
-                    return Err(format!(
-                        "Unable to convert the following patterns: {:?}",
-                        invalid
-                    ));
+                let prefix = &prefix
+                    .nested(map, definitions)
+                    .map_err(|e| format!("{}: {}", name, e))?;
+                match map.get(MODE_KEY) {
+                    None => {}
+                    Some(PatternTreeHelper::Leaf(mode)) if mode == "each" => {}
+                    Some(PatternTreeHelper::Leaf(mode)) if mode == "any" => {
+                        let mut sources = Vec::new();
+                        let mut regexes = Vec::new();
+                        collect_union(map, definitions, prefix, &mut sources, &mut regexes)?;
+                        if regexes.is_empty() {
+                            return Err(format!("{}: Unable to combine an empty group", name));
+                        }
+
+                        let helper = PatternHelper {
+                            regex: alternation(regexes.iter().map(String::as_str)),
+                            ..PatternHelper::default()
+                        };
+                        let source = alternation(sources.iter().map(String::as_str));
+                        return compile_leaf(name, &source, &helper, compile);
+                    }
+                    Some(_) => {
+                        return Err(format!(
+                            "{}: Invalid {}, expected 'each' or 'any'",
+                            name, MODE_KEY
+                        ))
+                    }
                 }
 
+                let results: Vec<_> = map
+                    .iter()
+                    .filter(|(name, _)| *name != MODE_KEY && *name != PREFIX_KEY)
+                    .collect::<Vec<_>>()
+                    .into_par_iter()
+                    .map(|(name, helper)| {
+                        traverse(name.clone(), helper, definitions, prefix, compile)
+                    })
+                    .collect();
+
                 Ok(GroupTree::Group {
                     name,
-                    group: patterns,
+                    group: collect_patterns(results)?,
+                })
+            }
+        }
+    }
+
+    let results: Vec<_> = pattern_tree
+        .into_iter()
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|(name, helper)| traverse(name, &helper, &definitions, &Prefix::default(), &compile))
+        .collect();
+    collect_patterns(results)
+}
+
+/// Key inside a group which selects how the group is counted. With `each`, the default, every
+/// pattern is counted on its own. With `any`, the group is counted as a single pattern, which
+/// matches a line if any pattern inside the group matches.
+const MODE_KEY: &str = "$mode";
+
+/// Key inside a group whose regex is required at the start of each line matched by any pattern
+/// inside the group, including nested groups. Nested prefixes are required one after another.
+const PREFIX_KEY: &str = "$prefix";
+
+/// Regex fragments which the `$prefix` of the enclosing groups add in front of each regex, both as
+/// written in the file and with all definitions expanded. Each fragment is wrapped in a
+/// non-capturing group.
+#[derive(Debug, Clone, Default)]
+struct Prefix {
+    source: String,
+    regex: String,
+}
+
+impl Prefix {
+    /// Returns the prefix of the group, which adds its own `$prefix`, if any, to the prefix of the
+    /// enclosing groups.
+    fn nested(
+        &self,
+        map: &IndexMap<String, PatternTreeHelper>,
+        definitions: &IndexMap<String, String>,
+    ) -> Result<Prefix, String> {
+        match map.get(PREFIX_KEY) {
+            None => Ok(self.clone()),
+            Some(PatternTreeHelper::Leaf(prefix)) => {
+                let regex = expand_definitions(prefix, definitions, &mut Vec::new())?;
+                Ok(Prefix {
+                    source: format!("{}(?:{})", self.source, prefix),
+                    regex: format!("{}(?:{})", self.regex, regex),
                 })
             }
+            Some(_) => Err(format!("Invalid {}, expected a regex", PREFIX_KEY)),
         }
     }
 
-    pattern_tree
+    /// Anchors the fragments at the start of the line in front of the regex.
+    fn apply(fragments: &str, regex: &str) -> String {
+        if fragments.is_empty() {
+            regex.to_string()
+        } else {
+            format!("^{}(?:{})", fragments, regex)
+        }
+    }
+}
+
+/// Collects the regexes of all patterns inside the group, both as written in the file and with
+/// all definitions expanded.
+fn collect_union(
+    map: &IndexMap<String, PatternTreeHelper>,
+    definitions: &IndexMap<String, String>,
+    prefix: &Prefix,
+    sources: &mut Vec<String>,
+    regexes: &mut Vec<String>,
+) -> Result<(), String> {
+    for (name, tree) in map {
+        let source = match tree {
+            _ if name == MODE_KEY || name == PREFIX_KEY => continue,
+            PatternTreeHelper::Leaf(regex) => regex,
+            PatternTreeHelper::Detailed(helper)
+                if helper.unless.is_some() || helper.when.is_some() =>
+            {
+                return Err(format!(
+                    "Unable to combine '{}', which uses unless or when",
+                    name
+                ))
+            }
+            PatternTreeHelper::Detailed(helper) => &helper.regex,
+            PatternTreeHelper::Node(map) => {
+                let prefix = prefix.nested(map, definitions)?;
+                collect_union(map, definitions, &prefix, sources, regexes)?;
+                continue;
+            }
+        };
+        let regex = expand_definitions(source, definitions, &mut Vec::new())?;
+        regexes.push(Prefix::apply(&prefix.regex, &regex));
+        sources.push(Prefix::apply(&prefix.source, source));
+    }
+    Ok(())
+}
+
+/// Replaces each top-level group by a single pattern, which matches a line if any pattern inside
+/// the group matches.
+pub fn union_groups(trees: Vec<GroupTree<Pattern>>) -> Result<Vec<GroupTree<Pattern>>, String> {
+    trees
         .into_iter()
-        .map(|(name, helper)| traverse(name, &helper))
+        .map(|tree| match tree {
+            GroupTree::Leaf(pattern) => Ok(GroupTree::Leaf(pattern)),
+            GroupTree::Group { name, group } => {
+                let patterns: Vec<_> = leaves(&group)
+                    .into_iter()
+                    .map(|(_, pattern)| pattern)
+                    .collect();
+                Pattern::union(name, &patterns).map(GroupTree::Leaf)
+            }
+        })
         .collect()
 }
+
+/// Recompiles the regexes of all patterns with Unicode mode disabled, which is faster on ASCII
+/// input. Classes like `\w`, `\d`, and `\b` then only match ASCII characters.
+///
+/// Patterns which set `keep_unicode` are left as they are, as are patterns which can only be
+/// compiled in Unicode mode, e.g. because `.` or a negated class could match invalid UTF-8.
+pub fn ascii_patterns(trees: Vec<GroupTree<Pattern>>) -> Vec<GroupTree<Pattern>> {
+    let options = RegexOptions {
+        ascii: true,
+        ..RegexOptions::default()
+    };
+    // Without a size limit, every regex compiles just like it did before.
+    let (patterns, _) = recompile_patterns(trees, options).unwrap();
+    patterns
+}
+
+/// How [`recompile_patterns`] compiles the regexes of the patterns.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RegexOptions {
+    /// Disable Unicode mode, like [`ascii_patterns`] does.
+    pub ascii: bool,
+
+    /// Approximate number of bytes the lazy DFA of each regex may use for its cache, per thread.
+    /// Once the cache is full, it is cleared, and if that happens too often, the regex falls back
+    /// to a slower engine. Uses the default of the regex crate if unset.
+    ///
+    /// This limit never makes a regex fail to compile, and the regex crate does not report when
+    /// matching falls back, so its effect is only observable as a change in speed.
+    pub dfa_size_limit: Option<usize>,
+
+    /// Approximate number of bytes each compiled regex may take up. Regexes which exceed it, e.g.
+    /// because of large bounded repetitions of Unicode classes, fail to compile. Uses the default
+    /// of the regex crate if unset.
+    pub size_limit: Option<usize>,
+}
+
+/// Recompiles the regexes of all patterns, including their `unless` and `when` regexes, with the
+/// given options, reporting the fully-qualified names of all patterns which fail to compile, e.g.
+/// because they exceed the size limit.
+///
+/// Also returns the fully-qualified names of the patterns which stay in Unicode mode although
+/// ASCII mode was requested, because one of their regexes cannot be compiled without Unicode.
+/// Patterns which set `keep_unicode` are not included.
+pub fn recompile_patterns(
+    trees: Vec<GroupTree<Pattern>>,
+    options: RegexOptions,
+) -> Result<(Vec<GroupTree<Pattern>>, Vec<String>), String> {
+    /// Compiles the regex, returning whether it had to fall back to Unicode mode.
+    fn compile(
+        regex: &Regex,
+        ascii: bool,
+        options: RegexOptions,
+    ) -> Result<(Regex, bool), regex::Error> {
+        let build = |unicode: bool| {
+            let mut builder = RegexBuilder::new(regex.as_str());
+            builder.unicode(unicode);
+            if let Some(limit) = options.dfa_size_limit {
+                builder.dfa_size_limit(limit);
+            }
+            if let Some(limit) = options.size_limit {
+                builder.size_limit(limit);
+            }
+            builder.build()
+        };
+
+        match ascii {
+            true => match build(false) {
+                Ok(regex) => Ok((regex, false)),
+                Err(_) => build(true).map(|regex| (regex, true)),
+            },
+            false => build(true).map(|regex| (regex, false)),
+        }
+    }
+
+    fn traverse(
+        trees: Vec<GroupTree<Pattern>>,
+        prefix: &str,
+        options: RegexOptions,
+        invalid: &mut Vec<String>,
+        unicode: &mut Vec<String>,
+    ) -> Vec<GroupTree<Pattern>> {
+        let path = |name: &str| {
+            if prefix.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}.{}", prefix, name)
+            }
+        };
+
+        let mut patterns = Vec::new();
+        for tree in trees {
+            match tree {
+                GroupTree::Leaf(pattern) => {
+                    let ascii = options.ascii && !pattern.keep_unicode;
+                    let mut fallback = false;
+                    let mut compile = |regex: &Regex| {
+                        let (regex, unicode) = compile(regex, ascii, options)?;
+                        fallback |= unicode;
+                        Ok::<_, regex::Error>(regex)
+                    };
+                    let result = (|| {
+                        Ok::<_, regex::Error>((
+                            compile(&pattern.regex)?,
+                            pattern.unless.as_ref().map(&mut compile).transpose()?,
+                            pattern.when.as_ref().map(&mut compile).transpose()?,
+                        ))
+                    })();
+                    match result {
+                        Ok((regex, unless, when)) => {
+                            if fallback {
+                                unicode.push(path(&pattern.name));
+                            }
+                            patterns.push(GroupTree::Leaf(Pattern {
+                                regex,
+                                unless,
+                                when,
+                                ..pattern
+                            }))
+                        }
+                        Err(e) => invalid.push(format!("{}: {}", path(&pattern.name), e)),
+                    }
+                }
+                GroupTree::Group { name, group } => {
+                    let group = traverse(group, &path(&name), options, invalid, unicode);
+                    patterns.push(GroupTree::Group { name, group });
+                }
+            }
+        }
+        patterns
+    }
+
+    let mut invalid = Vec::new();
+    let mut unicode = Vec::new();
+    let patterns = traverse(trees, "", options, &mut invalid, &mut unicode);
+    if !invalid.is_empty() {
+        return Err(format!(
+            "Unable to compile the following patterns:\n{}",
+            invalid.join("\n")
+        ));
+    }
+
+    Ok((patterns, unicode))
+}
+
+/// Collects the compiled patterns in their original order, or reports all patterns which failed
+/// to compile.
+fn collect_patterns<T>(
+    results: Vec<Result<GroupTree<T>, String>>,
+) -> Result<Vec<GroupTree<T>>, String> {
+    let mut patterns = Vec::new();
+    let mut invalid = Vec::new();
+    for result in results {
+        match result {
+            Ok(pattern) => patterns.push(pattern),
+            Err(e) => invalid.push(e),
+        }
+    }
+    if !invalid.is_empty() {
+        return Err(format!(
+            "Unable to convert the following patterns: {:?}",
+            invalid
+        ));
+    }
+
+    Ok(patterns)
+}
+
+/// Replaces every reference `{{name}}` in the pattern with the definition of that name, wrapped
+/// in a non-capturing group. Definitions may themselves reference other definitions.
+///
+/// The `stack` holds the names of the definitions currently being expanded, to detect cycles.
+fn expand_definitions(
+    pattern: &str,
+    definitions: &IndexMap<String, String>,
+    stack: &mut Vec<String>,
+) -> Result<String, String> {
+    lazy_static! {
+        static ref REFERENCE: Regex = Regex::new(r"\{\{\s*(\w+)\s*\}\}").unwrap();
+    }
+
+    let mut expanded = String::with_capacity(pattern.len());
+    let mut last = 0;
+    for captures in REFERENCE.captures_iter(pattern) {
+        let reference = captures.get(0).unwrap();
+        let name = &captures[1];
+
+        if stack.iter().any(|other| other == name) {
+            return Err(format!(
+                "Cyclic definition: {} -> {}",
+                stack.join(" -> "),
+                name
+            ));
+        }
+        let definition = definitions
+            .get(name)
+            .ok_or_else(|| format!("Undefined definition: {{{{{}}}}}", name))?;
+
+        stack.push(name.to_string());
+        let definition = expand_definitions(definition, definitions, stack)?;
+        stack.pop();
+
+        expanded.push_str(&pattern[last..reference.start()]);
+        expanded.push_str("(?:");
+        expanded.push_str(&definition);
+        expanded.push(')');
+        last = reference.end();
+    }
+    expanded.push_str(&pattern[last..]);
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    fn patterns_file(contents: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn parse_input_keeps_file_order() {
+        let mut contents = String::new();
+        let mut expected = Vec::new();
+        for group in ["zulu", "alpha", "mike"] {
+            contents.push_str(&format!("{}:\n", group));
+            for leaf in (0..50).rev() {
+                contents.push_str(&format!("  p{}: 'x{}'\n", leaf, leaf));
+                expected.push(format!("{}.p{}", group, leaf));
+            }
+        }
+        contents.push_str("last: 'y'\n");
+        expected.push("last".to_string());
+
+        let file = patterns_file(&contents);
+        let patterns = parse_input(file.path()).unwrap();
+        let names: Vec<_> = leaves(&patterns)
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(names, expected);
+    }
+
+    #[test]
+    fn dump_config_keeps_the_prefix_of_groups() {
+        let contents = "\
+definitions:
+  num: '[0-9]+'
+outer:
+  $prefix: 'a{{num}}'
+  inner:
+    $prefix: 'b'
+    leaf: 'c{{num}}'
+";
+        let file = patterns_file(contents);
+        let patterns = parse_input(file.path()).unwrap();
+        let definitions = parse_definitions(&[file.path()]).unwrap();
+
+        for definitions in [None, Some(&definitions)] {
+            let mut dumped = Vec::new();
+            dump_config(&mut dumped, &patterns, definitions).unwrap();
+            let dumped = patterns_file(&String::from_utf8(dumped).unwrap());
+            let reparsed = parse_input(dumped.path()).unwrap();
+
+            let (_, pattern) = leaves(&patterns).remove(0);
+            let (_, again) = leaves(&reparsed).remove(0);
+            assert!(again.regex.is_match("a1bc2"));
+            assert!(!again.regex.is_match("bc2"));
+            assert_eq!(pattern.regex.as_str(), again.regex.as_str());
+        }
+    }
+
+    #[test]
+    fn analyze_file_keeps_a_lent_analyzer_after_a_failed_read() {
+        let patterns = parse_expressions(&["a".to_string()]).unwrap();
+        let mut sampler = LineSampler::new(patterns, 10, Some(0));
+        let options = ReadOptions::default();
+
+        let file = patterns_file("a\nb\na\n");
+        assert!(analyze_file("/nonexistent/input", &mut sampler, &options).is_err());
+        analyze_file(file.path(), &mut sampler, &options).unwrap();
+
+        assert_eq!(sampler.lines(), ["a", "a"]);
+    }
+
+    #[test]
+    fn parse_inputs_reports_malformed_yaml() {
+        let file = patterns_file("group:\n  - [unclosed\n");
+        let error = match parse_inputs(&[file.path()]) {
+            Ok(_) => panic!("malformed YAML was accepted"),
+            Err(e) => e,
+        };
+        assert!(
+            error.starts_with(&file.path().display().to_string()),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn parse_binary_input_rejects_text_only_options() {
+        let file = patterns_file("plain: 'a+'\n");
+        assert!(parse_binary_input(file.path()).is_ok());
+
+        let file = patterns_file("counted:\n  regex: '(?P<x>a+)'\n  captures: [x]\n");
+        let error = match parse_binary_input(file.path()) {
+            Ok(_) => panic!("captures were accepted for a binary pattern"),
+            Err(e) => e,
+        };
+        assert!(
+            error.contains("counted") && error.contains("captures"),
+            "{}",
+            error
+        );
+
+        let file = patterns_file("filtered:\n  regex: 'a+'\n  unless: 'b'\n");
+        let error = match parse_binary_input(file.path()) {
+            Ok(_) => panic!("unless was accepted for a binary pattern"),
+            Err(e) => e,
+        };
+        assert!(
+            error.contains("filtered") && error.contains("unless"),
+            "{}",
+            error
+        );
+
+        let file = patterns_file("limited:\n  regex: 'a+'\n  when: 'b'\n");
+        let error = match parse_binary_input(file.path()) {
+            Ok(_) => panic!("when was accepted for a binary pattern"),
+            Err(e) => e,
+        };
+        assert!(
+            error.contains("limited") && error.contains("when"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn parse_input_reports_every_invalid_pattern() {
+        let contents = "\
+first:
+  ok: 'a'
+  broken_one: '('
+  also_ok: 'b'
+  broken_two: '[a'
+second:
+  nested:
+    broken_three: 'a{2,1}'
+broken_four: ')'
+fine: 'c'
+";
+        let file = patterns_file(contents);
+        let error = match parse_input(file.path()) {
+            Ok(_) => panic!("invalid patterns were accepted"),
+            Err(e) => e,
+        };
+        for name in ["broken_one", "broken_two", "broken_three", "broken_four"] {
+            assert!(error.contains(name), "{} missing from: {}", name, error);
+        }
+        for name in ["ok", "also_ok", "fine"] {
+            assert!(!error.contains(&format!("{}:", name)), "{}", error);
+        }
+    }
+}
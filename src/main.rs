@@ -1,9 +1,23 @@
-use std::io::stdout;
+use std::fs::{self, File};
+use std::io::{self, stdout, BufRead, BufWriter, Write};
 use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use clap::{Parser, Subcommand};
+use clap::{ArgEnum, Parser, Subcommand};
+use console::Term;
 
-use analyzer::Analyzer;
+use analyzer::watch::DirectoryWatcher;
+use analyzer::{
+    Analyzer, Cardinality, CooccurrenceCounter, CountMode, Decoder, Encoding, GroupTree, JsonField,
+    LineLimit, LineSampler, Merge, Named, NumberFormat, OverlapCounter, OverlapPolicy, Pattern,
+    PatternCleaner, PatternCounter, PatternExtractor, PatternMatcher, Progress, ReadOptions,
+    RecordSeparator, RegexOptions, ShapeSuggester, SortMode, SortSpec, Summary, Template,
+    Throughput, TimelineCounter,
+};
+use regex::Regex;
 
 /// Command line arguments configuration.
 #[derive(Parser, Debug)]
@@ -15,6 +29,100 @@ struct Cli {
     /// Subcommand
     #[clap(subcommand)]
     command: Commands,
+
+    /// How to label each file in the output.
+    #[clap(long, global = true, arg_enum, default_value = "name")]
+    path_label: PathLabel,
+
+    /// Locale whose thousands separator is used in human readable numbers, e.g. `de`.
+    #[clap(long, global = true, default_value = "en")]
+    locale: NumberFormat,
+
+    /// Write human readable numbers without a thousands separator.
+    #[clap(long, global = true)]
+    no_grouping: bool,
+
+    /// Leave out the footer with the number of patterns, matches, and lines below the human
+    /// readable results of each input.
+    #[clap(long, global = true)]
+    quiet: bool,
+}
+
+impl Cli {
+    /// Returns how the numbers of the human readable output are formatted.
+    fn number_format(&self) -> NumberFormat {
+        if self.no_grouping {
+            NumberFormat::ungrouped()
+        } else {
+            self.locale
+        }
+    }
+}
+
+/// Labels of the input files in the output.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum PathLabel {
+    /// The absolute path of the file.
+    Full,
+
+    /// The name of the file without its directory.
+    Name,
+
+    /// The path as it was given on the command line.
+    Relative,
+}
+
+impl PathLabel {
+    /// Returns the label of the file, where `-` is the standard input.
+    fn label(&self, file: &str) -> String {
+        if file == analyzer::STDIN {
+            return String::from("<stdin>");
+        }
+
+        let path = Path::new(file);
+        match self {
+            PathLabel::Full => path
+                .canonicalize()
+                .unwrap_or_else(|_| path.to_path_buf())
+                .display()
+                .to_string(),
+            PathLabel::Name => path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| file.to_string()),
+            PathLabel::Relative => file.to_string(),
+        }
+    }
+
+    /// Returns the label of the file, or of the entry inside the archive file as `file!entry`.
+    fn entry_label(&self, file: &str, entry: Option<&str>) -> String {
+        match entry {
+            Some(entry) => format!("{}!{}", self.label(file), entry),
+            None => self.label(file),
+        }
+    }
+}
+
+/// Output formats of the analysis results.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    /// Human readable tables.
+    Human,
+
+    /// Prometheus text exposition format.
+    Prometheus,
+
+    /// OpenMetrics text format, with a timestamp on every sample and the number of analyzed
+    /// lines, e.g. for pushing to a Pushgateway.
+    #[clap(name = "openmetrics")]
+    OpenMetrics,
+
+    /// One JSON report per line, which can be summed up later.
+    Json,
+
+    /// Folded stacks for flamegraph tools, one `group;pattern count` line per pattern, with the
+    /// counts of all inputs written one after another.
+    Folded,
 }
 
 /// Subcommands are stored in this enum.
@@ -22,58 +130,911 @@ struct Cli {
 enum Commands {
     /// Find actual matches and count the matches for each regex.
     Match {
-        /// Path to the patterns file.
-        #[clap(short, long)]
-        patterns: String,
+        /// Path to a patterns file, or `env:NAME` to read the patterns from an environment
+        /// variable. Can be repeated, merging groups with the same name.
+        #[clap(
+            short,
+            long,
+            multiple_occurrences = true,
+            required_unless_present = "expr"
+        )]
+        patterns: Vec<String>,
 
-        /// Path to the input file.
+        /// Inline pattern, which is added after the patterns of the patterns file and named after
+        /// its regex. Can be repeated.
+        #[clap(short, long, multiple_occurrences = true)]
+        expr: Vec<String>,
+
+        /// Path to the input file. Each text file inside a .tar, .tar.gz, or .zip archive is
+        /// analyzed separately.
         files: Vec<String>,
 
-        /// Comma separated list of patterns to include.
+        /// Path of a file listing further input files, one per line, which are analyzed after the
+        /// files given as arguments. Blank lines and lines starting with `#` are ignored. `-`
+        /// reads the list from the standard input.
+        #[clap(long)]
+        files_from: Option<String>,
+
+        /// Comma separated globs of the fully-qualified, dot separated paths of the patterns to
+        /// include, e.g. `network.**`. A `*` matches within a single name and `**` across names.
         #[clap(long)]
         include: Option<String>,
 
-        /// Comma separated list of patterns to exclude.
+        /// Comma separated globs of the fully-qualified, dot separated paths of the patterns to
+        /// exclude, e.g. `*.debug`.
         #[clap(long)]
         exclude: Option<String>,
 
-        /// If displaying matches, show only the top n matches.
+        /// Fail if no pattern is left after the filtering, instead of analyzing nothing.
+        #[clap(long)]
+        require_patterns: bool,
+
+        /// If displaying matches, show only the top n matches. Patterns can override it with
+        /// `top` in the patterns file.
         #[clap(short, long, default_value_t = usize::MAX)]
         top: usize,
+
+        /// Only show the number of distinct matches for each pattern.
+        #[clap(long)]
+        cardinality: bool,
+
+        /// Only show the number of distinct matches and the Shannon entropy of the distribution
+        /// of the matches of each pattern. A low entropy means that a few values dominate.
+        #[clap(long, conflicts_with_all = &["binary", "cardinality"])]
+        entropy: bool,
+
+        /// Only show the median, the 90th and 99th percentile, and the maximum of how often each
+        /// distinct match of each pattern occurs, which summarizes patterns with many distinct
+        /// matches.
+        #[clap(long, conflicts_with_all = &["binary", "cardinality", "entropy"])]
+        distribution: bool,
+
+        /// Only print the shortest and longest match of each pattern.
+        #[clap(long)]
+        extremes: bool,
+
+        /// Only print how many bytes the matches of each pattern cover, and which percentage of
+        /// the analyzed bytes that is. Line separators are not counted.
+        #[clap(long, conflicts_with_all = &["binary", "cardinality", "entropy", "extremes"])]
+        bytes: bool,
+
+        /// Only print for each capture group in how many matches it captured a non-empty string,
+        /// to find groups which never participate.
+        #[clap(
+            long,
+            conflicts_with_all = &["binary", "cardinality", "entropy", "extremes", "bytes"]
+        )]
+        count_captures: bool,
+
+        /// Sum the numeric value of the named capture group per match and per value of each
+        /// counted capture group, e.g. the bytes transferred per endpoint, and show the top
+        /// matches by their sum next to their count. Patterns without the capture are only
+        /// counted.
+        #[clap(
+            long,
+            value_name = "NAME",
+            conflicts_with_all = &[
+                "binary",
+                "cardinality",
+                "entropy",
+                "distribution",
+                "extremes",
+                "bytes",
+                "count-captures"
+            ]
+        )]
+        sum_capture: Option<String>,
+
+        /// Attribute each part of a line to at most one pattern: of overlapping matches, only the
+        /// match of the pattern which comes first in the patterns file is counted, even if a later
+        /// pattern's match starts before it, so that overlapping patterns do not count the same
+        /// text twice.
+        #[clap(
+            long,
+            conflicts_with_all = &["binary", "extremes", "bytes", "count-captures", "sum-capture"]
+        )]
+        exclusive: bool,
+
+        /// Keep the patterns in the order of the patterns file instead of sorting them by name.
+        #[clap(long)]
+        no_sort: bool,
+
+        /// Prefix each match with the name of the pattern that produced it.
+        #[clap(long)]
+        show_pattern: bool,
+
+        /// Recompile the patterns without Unicode mode, which is faster on ASCII input, but makes
+        /// classes like `\w`, `\d`, and `\b` match ASCII only. Patterns which need Unicode
+        /// can opt out with `keep_unicode: true`. Patterns which cannot be compiled without
+        /// Unicode are named on stderr and matched in Unicode mode.
+        #[clap(long)]
+        ascii: bool,
+
+        /// Approximate number of bytes the lazy DFA of each regex may use for its cache, per
+        /// thread. Larger caches help with heavy patterns on adversarial input. Defaults to the
+        /// limit of the regex crate.
+        #[clap(long, value_name = "BYTES")]
+        dfa_size_limit: Option<usize>,
+
+        /// Approximate number of bytes each compiled regex may take up. Patterns which exceed it
+        /// fail to compile and are named. Defaults to the limit of the regex crate.
+        #[clap(long, value_name = "BYTES")]
+        size_limit: Option<usize>,
+
+        /// Format of the output. The JSON reports list the patterns in the order of the patterns
+        /// file and the matches by descending count, so that they can be diffed across runs. With
+        /// --cardinality, they list the number of distinct matches of each pattern instead.
+        #[clap(long, arg_enum, default_value = "human")]
+        format: Format,
+
+        /// Print how many lines and bytes were analyzed per second after each file.
+        #[clap(long)]
+        throughput: bool,
+
+        /// Separator between the records of the input files: \n, \r\n, \0, a hex byte like 0x1e,
+        /// or a single character.
+        #[clap(long, default_value = "\\n")]
+        record_separator: RecordSeparator,
+
+        /// Keep the trailing \r of lines ending in \r\n instead of removing it.
+        #[clap(long)]
+        keep_cr: bool,
+
+        /// Analyze the decoded payload of each line instead, which is encoded as base64 or hex.
+        /// Lines whose payload cannot be decoded are skipped.
+        #[clap(long)]
+        decode: Option<Encoding>,
+
+        /// Regex selecting the encoded payload of each line with its first capture group, or
+        /// else its whole match. Lines which it does not match are skipped.
+        #[clap(long, requires = "decode")]
+        decode_field: Option<Regex>,
+
+        /// Print how many lines were skipped because their payload could not be decoded.
+        #[clap(long, requires = "decode")]
+        count_decode_failures: bool,
+
+        /// Show the progress of each file on stderr, with the percentage and the estimated
+        /// remaining time for regular files and the number of lines for other inputs.
+        #[clap(long)]
+        progress: bool,
+
+        /// Stop reading after the given number of seconds and print the results of the input read
+        /// so far.
+        #[clap(long)]
+        timeout: Option<u64>,
+
+        /// Truncate lines longer than the given number of bytes while reading them, without
+        /// splitting a UTF-8 character, so that input without line breaks cannot exhaust the
+        /// memory. Prints how many lines were truncated.
+        #[clap(long, value_name = "N")]
+        max_line_bytes: Option<usize>,
+
+        /// Parse each line as JSON and analyze only the value of the given dot separated field,
+        /// e.g. `.message`.
+        #[clap(long, conflicts_with = "binary")]
+        json_field: Option<JsonField>,
+
+        /// Skip lines which are not valid JSON or lack the --json-field, instead of failing.
+        #[clap(long, requires = "json-field")]
+        skip_invalid_json: bool,
+
+        /// Number of threads used to analyze each file, by splitting it into chunks.
+        #[clap(short, long, default_value_t = 1)]
+        jobs: usize,
+
+        /// Abort if the matches take up more than the given number of megabytes.
+        #[clap(long)]
+        limit_memory: Option<usize>,
+
+        /// Write the cumulative percentage of the pattern's matches after each count, e.g. to see
+        /// that the top 3 matches make up 80% of all matches.
+        #[clap(long)]
+        cumulative: bool,
+
+        /// Count at most the given number of matches of each pattern per line, where 0 counts all
+        /// matches. Count always counts a line once per pattern, no matter how often it matches.
+        #[clap(long, default_value_t = 0, conflicts_with = "binary")]
+        max_per_line: usize,
+
+        /// Write each match as the given template instead of as a table, replacing `{pattern}`,
+        /// `{match}`, `{count}`, and `{rank}`, e.g. `{pattern} => {match} ({count})`.
+        #[clap(
+            long,
+            conflicts_with_all = &[
+                "binary",
+                "cardinality",
+                "entropy",
+                "distribution",
+                "extremes",
+                "bytes",
+                "count-captures",
+                "sum-capture"
+            ]
+        )]
+        template: Option<Template>,
+
+        /// Order of the matches as comma separated keys, count or match, each with an optional
+        /// direction, asc or desc, e.g. `count:asc` or `count:desc,match:asc`. Defaults to
+        /// descending counts.
+        #[clap(
+            long,
+            conflicts_with_all = &[
+                "binary",
+                "cardinality",
+                "entropy",
+                "distribution",
+                "extremes",
+                "bytes",
+                "count-captures",
+                "sum-capture"
+            ]
+        )]
+        sort: Option<SortSpec>,
+
+        /// Write the top matches of each pattern of all inputs together to `<pattern>.tsv` inside
+        /// the given directory, as a match and its count separated by a tab on each line, for word
+        /// cloud tools. The directory is created if it does not exist.
+        #[clap(
+            long,
+            value_name = "DIR",
+            conflicts_with_all = &["binary", "extremes", "bytes", "count-captures"]
+        )]
+        wordcloud: Option<String>,
+
+        /// Truncate matches and capture values after the given number of characters, marking them
+        /// with a trailing `…`, and print how many matches of each pattern were truncated.
+        #[clap(long, conflicts_with = "binary")]
+        max_match_len: Option<usize>,
+
+        /// Print the fully-qualified name of the pattern at each index of the flattened patterns
+        /// to stderr before analyzing, to correlate indices with patterns after sorting.
+        #[clap(long)]
+        debug_indices: bool,
+
+        /// Match the raw bytes of the files instead of their lines, showing non-printable bytes
+        /// escaped. Use `(?-u)` inside a pattern to match arbitrary bytes, e.g. `(?-u)\xFF`.
+        #[clap(long)]
+        binary: bool,
     },
 
     /// Count the number of matches for each regex, but do not save the actual returned matches.
     Count {
-        /// YAML file containing the list of patterns to search.
-        #[clap(short, long)]
-        patterns: String,
+        /// Path to a patterns file, or `env:NAME` to read the patterns from an environment
+        /// variable. Can be repeated, merging groups with the same name.
+        #[clap(
+            short,
+            long,
+            multiple_occurrences = true,
+            required_unless_present = "expr"
+        )]
+        patterns: Vec<String>,
 
-        /// Path to the input file.
+        /// Inline pattern, which is added after the patterns of the patterns file and named after
+        /// its regex. Can be repeated.
+        #[clap(short, long, multiple_occurrences = true)]
+        expr: Vec<String>,
+
+        /// Path to the input file. Each text file inside a .tar, .tar.gz, or .zip archive is
+        /// analyzed separately.
         files: Vec<String>,
 
-        /// Comma separated list of patterns to include.
+        /// Path of a file listing further input files, one per line, which are analyzed after the
+        /// files given as arguments. Blank lines and lines starting with `#` are ignored. `-`
+        /// reads the list from the standard input.
+        #[clap(long)]
+        files_from: Option<String>,
+
+        /// Comma separated globs of the fully-qualified, dot separated paths of the patterns to
+        /// include, e.g. `network.**`. A `*` matches within a single name and `**` across names.
         #[clap(long)]
         include: Option<String>,
 
-        /// Comma separated list of patterns to exclude.
+        /// Comma separated globs of the fully-qualified, dot separated paths of the patterns to
+        /// exclude, e.g. `*.debug`.
         #[clap(long)]
         exclude: Option<String>,
+
+        /// Fail if no pattern is left after the filtering, instead of analyzing nothing.
+        #[clap(long)]
+        require_patterns: bool,
+
+        /// Number of threads used to analyze each file, by splitting it into chunks.
+        #[clap(short, long, default_value_t = 1)]
+        jobs: usize,
+
+        /// Show the weighted score of all patterns instead of the counts.
+        #[clap(long)]
+        score: bool,
+
+        /// Keep the patterns in the order of the patterns file instead of sorting them by name.
+        #[clap(long)]
+        no_sort: bool,
+
+        /// Continuously count all files created in or appended to the directory, until
+        /// interrupted.
+        #[clap(long)]
+        watch: Option<String>,
+
+        /// When watching, the minimum number of seconds between printing the counts.
+        #[clap(long, default_value_t = 2)]
+        interval: u64,
+
+        /// When watching, redraw the counts in place like a dashboard, instead of printing them
+        /// below each other. The counts are cut to the size of the terminal.
+        #[clap(long, requires = "watch")]
+        live: bool,
+
+        /// Show n randomly sampled lines that matched each pattern.
+        #[clap(long, default_value_t = 0)]
+        examples: usize,
+
+        /// Show one randomly sampled line that matched each pattern.
+        #[clap(long)]
+        sample_lines: bool,
+
+        /// Mark the patterns whose count is at or below the given percentile, between 0 and 100,
+        /// of the counts of all patterns which matched at least once as rare. Together with
+        /// --sample-lines, only the sampled lines of the rare patterns are shown.
+        #[clap(long, value_name = "PERCENTILE", conflicts_with = "score")]
+        rare: Option<f64>,
+
+        /// Seed for the random sampling, to make the output reproducible.
+        #[clap(long)]
+        seed: Option<u64>,
+
+        /// Number of spaces each nested group is indented by.
+        #[clap(long, default_value_t = 2)]
+        indent: usize,
+
+        /// Separator between the name of a pattern and its count.
+        #[clap(long, default_value = " ")]
+        separator: String,
+
+        /// Count each top-level group once per line if any of its patterns matches, instead of
+        /// counting each pattern.
+        #[clap(long)]
+        group_union: bool,
+
+        /// Recompile the patterns without Unicode mode, which is faster on ASCII input, but makes
+        /// classes like `\w`, `\d`, and `\b` match ASCII only. Patterns which need Unicode
+        /// can opt out with `keep_unicode: true`. Patterns which cannot be compiled without
+        /// Unicode are named on stderr and matched in Unicode mode.
+        #[clap(long)]
+        ascii: bool,
+
+        /// Approximate number of bytes the lazy DFA of each regex may use for its cache, per
+        /// thread. Larger caches help with heavy patterns on adversarial input. Defaults to the
+        /// limit of the regex crate.
+        #[clap(long, value_name = "BYTES")]
+        dfa_size_limit: Option<usize>,
+
+        /// Approximate number of bytes each compiled regex may take up. Patterns which exceed it
+        /// fail to compile and are named. Defaults to the limit of the regex crate.
+        #[clap(long, value_name = "BYTES")]
+        size_limit: Option<usize>,
+
+        /// Write each count divided by the count of the given pattern, either its fully-qualified
+        /// path or, if unique, just its name.
+        #[clap(long)]
+        relative_to: Option<String>,
+
+        /// Write each count scaled to a million lines after the count, to compare files of
+        /// different lengths.
+        #[clap(long, conflicts_with_all = &["score", "file-as-record"])]
+        per_million: bool,
+
+        /// Write the regex of each pattern after its count, as written in the patterns file. Only
+        /// affects the human format.
+        #[clap(long, conflicts_with = "score")]
+        show_regex: bool,
+
+        /// Write the peak number of lines matching each pattern within any window of the given
+        /// number of consecutive lines after the count, to detect bursts. With several jobs,
+        /// windows spanning two chunks are not considered.
+        #[clap(long, conflicts_with_all = &["score", "file-as-record"])]
+        window: Option<usize>,
+
+        /// What is counted for each pattern: `lines` counts each matching line once, while
+        /// `occurrences` counts every non-overlapping match, so a line can count several times.
+        #[clap(long, default_value = "lines")]
+        count_mode: CountMode,
+
+        /// Count each run of consecutive lines matching the same pattern once, so that entries
+        /// spanning several lines, like stack traces, are counted as single events.
+        #[clap(long)]
+        collapse_consecutive: bool,
+
+        /// Only write the patterns counted at least the given number of times, leaving out
+        /// groups which become empty.
+        #[clap(long, default_value_t = 0, conflicts_with = "score")]
+        at_least: u64,
+
+        /// Only write the given number of group levels, collapsing deeper groups into a single
+        /// `group (…)` line with the sum of their counts. Zero collapses the top-level groups.
+        #[clap(long, conflicts_with = "score")]
+        max_depth: Option<usize>,
+
+        /// Break down the count of each pattern declaring the named capture group by the values
+        /// of the capture, written below the count. Patterns without the capture keep a plain
+        /// count. Only affects the human format.
+        #[clap(long, value_name = "CAPTURE", conflicts_with = "score")]
+        group_by: Option<String>,
+
+        /// Print the total count of each top-level group after the counts.
+        #[clap(long)]
+        rollup: bool,
+
+        /// Measure how long each pattern takes to evaluate on the input, and print the total time
+        /// of each pattern after the counts, slowest first.
+        #[clap(long)]
+        profile: bool,
+
+        /// Print the fully-qualified name of the pattern at each index of the flattened patterns
+        /// to stderr before analyzing, to correlate indices with patterns after sorting.
+        #[clap(long)]
+        debug_indices: bool,
+
+        /// Print the resolved patterns files, the regex options, the filters, the number of
+        /// patterns left, and the first compiled patterns, then exit without analyzing anything.
+        #[clap(long)]
+        explain_config: bool,
+
+        /// Format of the output.
+        #[clap(long, arg_enum, default_value = "human")]
+        format: Format,
+
+        /// Print a SHA-256 hash of the sorted fully-qualified names and counts of the patterns
+        /// followed by the label of each input, instead of the counts, so that a wrapper can skip
+        /// further steps if the counts are unchanged since a previous run.
+        #[clap(long, conflicts_with_all = &["watch", "file-as-record", "split-output"])]
+        result_hash: bool,
+
+        /// Prefix of the metric names of the openmetrics format, separated by an underscore.
+        #[clap(long, default_value = "")]
+        metric_prefix: String,
+
+        /// Print how many lines and bytes were analyzed per second after each file.
+        #[clap(long)]
+        throughput: bool,
+
+        /// Separator between the records of the input files: \n, \r\n, \0, a hex byte like 0x1e,
+        /// or a single character.
+        #[clap(long, default_value = "\\n")]
+        record_separator: RecordSeparator,
+
+        /// Keep the trailing \r of lines ending in \r\n instead of removing it.
+        #[clap(long)]
+        keep_cr: bool,
+
+        /// Analyze the decoded payload of each line instead, which is encoded as base64 or hex.
+        /// Lines whose payload cannot be decoded are skipped.
+        #[clap(long)]
+        decode: Option<Encoding>,
+
+        /// Regex selecting the encoded payload of each line with its first capture group, or
+        /// else its whole match. Lines which it does not match are skipped.
+        #[clap(long, requires = "decode")]
+        decode_field: Option<Regex>,
+
+        /// Print how many lines were skipped because their payload could not be decoded.
+        #[clap(long, requires = "decode")]
+        count_decode_failures: bool,
+
+        /// Show the progress of each file on stderr, with the percentage and the estimated
+        /// remaining time for regular files and the number of lines for other inputs.
+        #[clap(long)]
+        progress: bool,
+
+        /// Stop reading after the given number of seconds and print the results of the input read
+        /// so far.
+        #[clap(long)]
+        timeout: Option<u64>,
+
+        /// Truncate lines longer than the given number of bytes while reading them, without
+        /// splitting a UTF-8 character, so that input without line breaks cannot exhaust the
+        /// memory. Prints how many lines were truncated.
+        #[clap(long, value_name = "N")]
+        max_line_bytes: Option<usize>,
+
+        /// Parse each line as JSON and analyze only the value of the given dot separated field,
+        /// e.g. `.message`.
+        #[clap(long, conflicts_with_all = &["watch", "file-as-record"])]
+        json_field: Option<JsonField>,
+
+        /// Skip lines which are not valid JSON or lack the --json-field, instead of failing.
+        #[clap(long, requires = "json-field")]
+        skip_invalid_json: bool,
+
+        /// Analyze each file as a single record, counting the files instead of the lines that
+        /// match each pattern, and print a single count for all files.
+        #[clap(long, conflicts_with_all = &["watch", "throughput"])]
+        file_as_record: bool,
+
+        /// Write the counts of each top-level group to `<group>.txt` inside the given directory
+        /// instead of the standard output, and the patterns outside of any group to `root.txt`.
+        /// The directory is created if it does not exist.
+        #[clap(
+            long,
+            value_name = "DIR",
+            conflicts_with_all = &["watch", "file-as-record", "score", "rollup", "profile", "throughput"]
+        )]
+        split_output: Option<String>,
+    },
+
+    /// Sum up the reports saved with `count --format json`.
+    Sum {
+        /// Paths to the saved reports.
+        reports: Vec<String>,
+
+        /// Number of spaces each nested group is indented by.
+        #[clap(long, default_value = "2")]
+        indent: usize,
+
+        /// Output format of the combined report.
+        #[clap(long, arg_enum, default_value = "human")]
+        format: Format,
+    },
+
+    /// Stream every match to the output as soon as it is found, one per line, without counting.
+    Extract {
+        /// Path to a patterns file, or `env:NAME` to read the patterns from an environment
+        /// variable. Can be repeated, merging groups with the same name.
+        #[clap(
+            short,
+            long,
+            multiple_occurrences = true,
+            required_unless_present = "expr"
+        )]
+        patterns: Vec<String>,
+
+        /// Inline pattern, which is added after the patterns of the patterns file and named after
+        /// its regex. Can be repeated.
+        #[clap(short, long, multiple_occurrences = true)]
+        expr: Vec<String>,
+
+        /// Path to the input file.
+        files: Vec<String>,
+
+        /// Prefix each match with the name of the pattern that produced it.
+        #[clap(long)]
+        show_pattern: bool,
+
+        /// Prefix each match with the 1-based number of its line.
+        #[clap(short = 'n', long)]
+        line_number: bool,
+
+        /// Prefix each match with the name of its file.
+        #[clap(short = 'H', long)]
+        with_filename: bool,
+
+        /// Separator between the records of the input files: \n, \r\n, \0, a hex byte like 0x1e,
+        /// or a single character.
+        #[clap(long, default_value = "\\n")]
+        record_separator: RecordSeparator,
     },
 
-    /// Clean the files by removing and replacing.
+    /// Write a reproducible random sample of the lines which match any pattern, e.g. to build
+    /// test fixtures.
+    Sample {
+        /// Path to a patterns file, or `env:NAME` to read the patterns from an environment
+        /// variable. Can be repeated, merging groups with the same name.
+        #[clap(
+            short,
+            long,
+            multiple_occurrences = true,
+            required_unless_present = "expr"
+        )]
+        patterns: Vec<String>,
+
+        /// Inline pattern, which is added after the patterns of the patterns file and named after
+        /// its regex. Can be repeated.
+        #[clap(short, long, multiple_occurrences = true)]
+        expr: Vec<String>,
+
+        /// Path to the input file. A single sample is taken from the lines of all files.
+        files: Vec<String>,
+
+        /// Number of lines to sample.
+        #[clap(short, long, default_value_t = 10)]
+        n: usize,
+
+        /// Seed for the random sampling, to make the sample reproducible.
+        #[clap(long)]
+        seed: Option<u64>,
+
+        /// Path of the file the sampled lines are written to, instead of the standard output.
+        #[clap(short, long)]
+        output: Option<String>,
+
+        /// Separator between the records of the input files: \n, \r\n, \0, a hex byte like 0x1e,
+        /// or a single character.
+        #[clap(long, default_value = "\\n")]
+        record_separator: RecordSeparator,
+    },
+
+    /// Clean the files by replacing the matches of each pattern with its `replace` value, or by
+    /// removing them.
     Clean {
-        /// Path to the YAML configuration file.
+        /// Path to a patterns file, or `env:NAME` to read the patterns from an environment
+        /// variable. Can be repeated, merging groups with the same name.
+        #[clap(short, long, multiple_occurrences = true, required = true)]
+        patterns: Vec<String>,
+
+        /// Path to the input file, which is cleaned into a sibling file with the `.clean`
+        /// extension appended. The standard input `-` is cleaned to the standard output line by
+        /// line, so that Clean can be used as a filter inside a pipeline.
+        #[clap(default_value = analyzer::STDIN)]
+        files: Vec<String>,
+
+        /// Only print how many matches each pattern would replace and the first changed lines,
+        /// without writing any cleaned output.
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Number of changed lines shown before and after cleaning them with --dry-run.
+        #[clap(long, default_value_t = 5)]
+        examples: usize,
+
+        /// Overwrite each file with its cleaned lines instead of writing a sibling file. The
+        /// original is replaced atomically, so that an interrupted run never leaves a
+        /// half-written file.
+        #[clap(long, conflicts_with = "dry-run")]
+        in_place: bool,
+
+        /// Keep a copy of each original file with the `.bak` extension appended when cleaning in
+        /// place.
+        #[clap(long, requires = "in-place")]
+        backup: bool,
+
+        /// Which match is replaced if the matches of several patterns overlap: `tree-order`
+        /// replaces the match of the pattern which comes first in the patterns file, while
+        /// `longest` replaces the longest match. All patterns are matched against the original
+        /// line, so the replacements never affect each other.
+        #[clap(long, default_value = "tree-order")]
+        overlap_policy: OverlapPolicy,
+    },
+
+    /// Explain whether and how a single pattern matches a line.
+    Explain {
+        /// Path to a patterns file, or `env:NAME` to read the patterns from an environment
+        /// variable. Can be repeated, merging groups with the same name.
+        #[clap(short, long, multiple_occurrences = true, required = true)]
+        patterns: Vec<String>,
+
+        /// Name of the pattern, either its fully-qualified path like `network.http.status` or,
+        /// if unique, just its name.
+        pattern_name: String,
+
+        /// Line to match the pattern against.
+        line: String,
+    },
+
+    /// Warn about patterns with the same regex or name, and about patterns matching every line.
+    /// Exits with an error if there are any warnings.
+    Lint {
+        /// Path to a patterns file, or `env:NAME` to read the patterns from an environment
+        /// variable. Can be repeated, merging groups with the same name.
+        #[clap(short, long, multiple_occurrences = true, required = true)]
+        patterns: Vec<String>,
+    },
+
+    /// Interactively test lines typed on stdin against all patterns. Enter `:reload` to reload the
+    /// patterns file.
+    Repl {
+        /// Path to a patterns file, or `env:NAME` to read the patterns from an environment
+        /// variable. Can be repeated, merging groups with the same name.
+        #[clap(short, long, multiple_occurrences = true, required = true)]
+        patterns: Vec<String>,
+    },
+
+    /// Write the JSON Schema of the patterns file, e.g. to validate it in an editor.
+    Schema,
+
+    /// Write the effective patterns file, with all definitions expanded.
+    Dump {
+        /// Path to a patterns file, or `env:NAME` to read the patterns from an environment
+        /// variable. Can be repeated, merging groups with the same name.
+        #[clap(short, long, multiple_occurrences = true, required = true)]
+        patterns: Vec<String>,
+    },
+
+    /// Write the patterns file a run with the same patterns and filters would use.
+    Config {
+        /// Path to a patterns file, or `env:NAME` to read the patterns from an environment
+        /// variable. Can be repeated, merging groups with the same name.
+        #[clap(
+            short,
+            long,
+            multiple_occurrences = true,
+            required_unless_present = "expr"
+        )]
+        patterns: Vec<String>,
+
+        /// Inline pattern, which is added after the patterns of the patterns file and named after
+        /// its regex. Can be repeated.
+        #[clap(short, long, multiple_occurrences = true)]
+        expr: Vec<String>,
+
+        /// Comma separated globs of the fully-qualified, dot separated paths of the patterns to
+        /// include, e.g. `network.**`. A `*` matches within a single name and `**` across names.
+        #[clap(long)]
+        include: Option<String>,
+
+        /// Comma separated globs of the fully-qualified, dot separated paths of the patterns to
+        /// exclude, e.g. `*.debug`.
+        #[clap(long)]
+        exclude: Option<String>,
+
+        /// Expand the definitions inside the regexes instead of writing the definitions of the
+        /// patterns files.
+        #[clap(long)]
+        expand_definitions: bool,
+
+        /// Path of the file the patterns are written to, instead of the standard output.
+        #[clap(short, long)]
+        output: Option<String>,
+    },
+
+    /// Count how often the lines matching one pattern also match another one.
+    Cooccur {
+        /// Path to a patterns file, or `env:NAME` to read the patterns from an environment
+        /// variable. Can be repeated, merging groups with the same name.
+        #[clap(short, long, multiple_occurrences = true, required = true)]
+        patterns: Vec<String>,
+
+        /// Name of the first pattern, either its fully-qualified path or, if unique, just its
+        /// name.
+        a: String,
+
+        /// Name of the second pattern.
+        b: String,
+
+        /// Path to the input file.
+        files: Vec<String>,
+
+        /// Number of threads used to analyze each file, by splitting it into chunks.
+        #[clap(short, long, default_value_t = 1)]
+        jobs: usize,
+
+        /// Separator between the records of the input files: \n, \r\n, \0, a hex byte like 0x1e,
+        /// or a single character.
+        #[clap(long, default_value = "\\n")]
+        record_separator: RecordSeparator,
+    },
+
+    /// Count for every pair of patterns how many lines both match, showing the pairs with the most
+    /// overlap, which may be redundant.
+    Overlap {
+        /// Path to a patterns file, or `env:NAME` to read the patterns from an environment
+        /// variable. Can be repeated, merging groups with the same name.
+        #[clap(
+            short,
+            long,
+            multiple_occurrences = true,
+            required_unless_present = "expr"
+        )]
+        patterns: Vec<String>,
+
+        /// Inline pattern, which is added after the patterns of the patterns file and named after
+        /// its regex. Can be repeated.
+        #[clap(short, long, multiple_occurrences = true)]
+        expr: Vec<String>,
+
+        /// Path to the input file.
+        files: Vec<String>,
+
+        /// Show only the top n pairs.
+        #[clap(short, long, default_value_t = 10)]
+        top: usize,
+
+        /// Number of threads used to analyze each file, by splitting it into chunks.
+        #[clap(short, long, default_value_t = 1)]
+        jobs: usize,
+
+        /// Separator between the records of the input files: \n, \r\n, \0, a hex byte like 0x1e,
+        /// or a single character.
+        #[clap(long, default_value = "\\n")]
+        record_separator: RecordSeparator,
+    },
+
+    /// Count the lines matching each pattern per time bucket, e.g. per minute.
+    Timeline {
+        /// Path to a patterns file, or `env:NAME` to read the patterns from an environment
+        /// variable. Can be repeated, merging groups with the same name.
+        #[clap(
+            short,
+            long,
+            multiple_occurrences = true,
+            required_unless_present = "expr"
+        )]
+        patterns: Vec<String>,
+
+        /// Inline pattern, which is added after the patterns of the patterns file and named after
+        /// its regex. Can be repeated.
+        #[clap(short, long, multiple_occurrences = true)]
+        expr: Vec<String>,
+
+        /// Regex extracting the time bucket of each line from its timestamp, using the first
+        /// capture group or else the whole match, e.g. `^(\S+ \d\d:\d\d)` for minutes.
+        /// Buckets are ordered by name and lines without a timestamp belong to the previous
+        /// bucket.
         #[clap(short, long)]
-        patterns: String,
+        bucket: String,
 
         /// Path to the input file.
         files: Vec<String>,
+
+        /// Show a sparkline of the counts of each pattern across the buckets.
+        #[clap(long)]
+        sparkline: bool,
+
+        /// Separator between the records of the input files: \n, \r\n, \0, a hex byte like 0x1e,
+        /// or a single character.
+        #[clap(long, default_value = "\\n")]
+        record_separator: RecordSeparator,
+    },
+
+    /// Compare the distinct matches of each pattern in two files, showing the matches which only
+    /// occur in the second file as new and those which only occur in the first one as gone.
+    MatchDiff {
+        /// Path to a patterns file, or `env:NAME` to read the patterns from an environment
+        /// variable. Can be repeated, merging groups with the same name.
+        #[clap(
+            short,
+            long,
+            multiple_occurrences = true,
+            required_unless_present = "expr"
+        )]
+        patterns: Vec<String>,
+
+        /// Inline pattern, which is added after the patterns of the patterns file and named after
+        /// its regex. Can be repeated.
+        #[clap(short, long, multiple_occurrences = true)]
+        expr: Vec<String>,
+
+        /// Path to the old input file.
+        file_a: String,
+
+        /// Path to the new input file.
+        file_b: String,
+
+        /// Show only the top n new and gone matches of each pattern.
+        #[clap(short, long, default_value_t = 10)]
+        top: usize,
+
+        /// Separator between the records of the input files: \n, \r\n, \0, a hex byte like 0x1e,
+        /// or a single character.
+        #[clap(long, default_value = "\\n")]
+        record_separator: RecordSeparator,
+    },
+
+    /// Suggest patterns for the most common shapes of the lines of sample files, replacing
+    /// timestamps, hex strings, and numbers with regexes, and write them as a starter patterns
+    /// file.
+    Suggest {
+        /// Path to the sample file.
+        #[clap(required = true)]
+        files: Vec<String>,
+
+        /// Suggest only the n most common shapes.
+        #[clap(short, long, default_value_t = 20)]
+        top: usize,
+
+        /// Separator between the records of the input files: \n, \r\n, \0, a hex byte like 0x1e,
+        /// or a single character.
+        #[clap(long, default_value = "\\n")]
+        record_separator: RecordSeparator,
     },
 
     /// Analyze the entire vocab of the source file.
     Vocab {
         /// Path to the input file.
         files: Vec<String>,
+
+        /// Show only the top n words.
+        #[clap(short, long, default_value_t = usize::MAX)]
+        top: usize,
     },
 }
 
@@ -81,51 +1042,1418 @@ enum Commands {
 fn main() {
     let cli = Cli::parse();
 
+    let mut failures = Failures::default();
+    run(&cli, &mut failures);
+    if failures.count > 0 {
+        process::exit(1);
+    }
+}
+
+/// Counts the files which could not be analyzed, so that the remaining files are still analyzed
+/// before exiting with an error.
+#[derive(Debug, Default)]
+struct Failures {
+    count: usize,
+}
+
+impl Failures {
+    /// Returns the analysis of the file, or warns that the file is skipped.
+    fn check<T>(&mut self, file: &str, result: Result<T, String>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                eprintln!("skipping {}: {}", file, e);
+                self.count += 1;
+                None
+            }
+        }
+    }
+}
+
+fn run(cli: &Cli, failures: &mut Failures) {
+    let numbers = cli.number_format();
     match &cli.command {
         Commands::Match {
             patterns,
+            expr,
             files,
-            include: _,
-            exclude: _,
+            files_from,
+            include,
+            exclude,
+            require_patterns,
             top,
+            cardinality,
+            entropy,
+            distribution,
+            extremes,
+            bytes,
+            count_captures,
+            sum_capture,
+            exclusive,
+            no_sort,
+            show_pattern,
+            ascii,
+            dfa_size_limit,
+            size_limit,
+            format,
+            record_separator,
+            keep_cr,
+            json_field,
+            skip_invalid_json,
+            timeout,
+            max_line_bytes,
+            progress,
+            decode,
+            decode_field,
+            count_decode_failures,
+            jobs,
+            throughput,
+            limit_memory,
+            max_match_len,
+            debug_indices,
+            cumulative,
+            max_per_line,
+            template,
+            sort,
+            wordcloud,
+            binary,
         } => {
-            let patterns = analyzer::parse_input(&patterns).unwrap();
+            let files = &input_files(files, files_from);
+            if matches!(
+                format,
+                Format::Prometheus | Format::OpenMetrics | Format::Folded
+            ) {
+                eprintln!(
+                    "The prometheus, openmetrics, and folded formats are not supported for matches"
+                );
+                process::exit(1);
+            }
+            if *binary && *format == Format::Json {
+                eprintln!("The json format is not supported with --binary");
+                process::exit(1);
+            }
+            if (*bytes
+                || *count_captures
+                || sum_capture.is_some()
+                || *extremes
+                || *entropy
+                || *distribution)
+                && *format == Format::Json
+            {
+                eprintln!(
+                    "The json format is not supported with --bytes, --count-captures, \
+                    --sum-capture, --extremes, --entropy, or --distribution"
+                );
+                process::exit(1);
+            }
+
+            if *binary {
+                let patterns = match (patterns.is_empty(), expr.is_empty()) {
+                    (false, true) => {
+                        let patterns =
+                            analyzer::parse_binary_inputs(patterns).unwrap_or_else(|e| {
+                                eprintln!("{}", e);
+                                process::exit(1);
+                            });
+                        select_patterns(patterns, include, exclude)
+                    }
+                    _ => {
+                        eprintln!("Inline patterns are not supported together with --binary");
+                        process::exit(1);
+                    }
+                };
+
+                if *require_patterns {
+                    require_leaves(&patterns);
+                }
+
+                for file in files {
+                    let result = analyzer::match_binary_file(
+                        file,
+                        patterns.clone(),
+                        *top,
+                        sort_mode(*no_sort),
+                    );
+                    let mut matcher = match failures.check(file, result) {
+                        Some(matcher) => matcher,
+                        None => continue,
+                    };
+                    matcher.show_pattern(*show_pattern);
+                    matcher.number_format(numbers);
+                    println!("==== {} ====", cli.path_label.label(file));
+                    matcher.format(&mut stdout()).unwrap();
+                    println!();
+                }
+                return;
+            }
+
+            let mut patterns = select_patterns(load_patterns(patterns, expr), include, exclude);
+            if *require_patterns {
+                require_leaves(&patterns);
+            }
+            let options = RegexOptions {
+                ascii: *ascii,
+                dfa_size_limit: *dfa_size_limit,
+                size_limit: *size_limit,
+            };
+            if options != RegexOptions::default() {
+                patterns = recompile_patterns(patterns, options);
+            }
 
+            // The JSON reports always keep the order of the patterns file, see --format.
+            let pattern_order = match format {
+                Format::Json => SortMode::SourceOrder,
+                _ => sort_mode(*no_sort),
+            };
+            let new_matcher = || {
+                let mut matcher = PatternMatcher::new(patterns.clone(), *top, pattern_order);
+                if let Some(megabytes) = limit_memory {
+                    matcher.limit_memory(megabytes.saturating_mul(1024 * 1024));
+                }
+                matcher.extremes(*extremes);
+                matcher.bytes(*bytes);
+                matcher.count_captures(*count_captures);
+                if let Some(capture) = sum_capture {
+                    matcher.sum_capture(capture);
+                }
+                if *exclusive {
+                    matcher.exclusive(&patterns, pattern_order);
+                }
+                matcher.max_match_len(*max_match_len);
+                matcher.max_per_line(*max_per_line);
+                matcher.cumulative(*cumulative);
+                matcher.template(template.clone());
+                matcher.sort_by(sort.clone());
+                matcher.number_format(numbers);
+                matcher
+            };
+            if *debug_indices {
+                print_indices(&new_matcher().index_names());
+            }
+            let read_options = ReadOptions {
+                separator: *record_separator,
+                keep_cr: *keep_cr,
+                json_field: json_field.clone(),
+                skip_invalid_json: *skip_invalid_json,
+                decoder: decode.map(|encoding| Decoder::new(encoding, decode_field.clone())),
+                deadline: timeout.map(|seconds| Instant::now() + Duration::from_secs(seconds)),
+                line_limit: max_line_bytes.map(LineLimit::new),
+                progress: None,
+            };
+            let options_for = |file: &str| ReadOptions {
+                progress: progress.then(|| Progress::new(file)),
+                ..read_options.clone()
+            };
+
+            let mut total = Throughput::default();
+            let mut summary = Summary::default();
+            let mut inputs = 0;
+            let mut cloud: Option<PatternMatcher> = None;
             for file in files {
-                let file = Path::new(&file);
-                let mut matcher = analyzer::match_file(file, patterns.clone(), *top).unwrap();
-                println!("==== {} ====", file.file_name().unwrap().to_str().unwrap());
-                matcher.format(&mut stdout());
-                println!();
+                if read_options.timed_out() {
+                    break;
+                }
+                let result = analyzer::analyze_input_metered(
+                    file,
+                    *jobs,
+                    new_matcher,
+                    &options_for(file),
+                    |entry, result| {
+                        let label = cli.path_label.entry_label(file, entry);
+                        let (mut matcher, file_throughput) = match failures.check(&label, result) {
+                            Some(result) => result,
+                            None => return,
+                        };
+                        total += file_throughput;
+                        inputs += 1;
+                        if *format == Format::Json && *cardinality {
+                            let cardinality: Vec<_> = matcher
+                                .cardinality()
+                                .into_iter()
+                                .map(|(pattern, distinct)| Cardinality { pattern, distinct })
+                                .collect();
+                            println!("{}", serde_json::to_string(&cardinality).unwrap());
+                            return;
+                        }
+                        if *format == Format::Json {
+                            println!("{}", serde_json::to_string(&matcher.report()).unwrap());
+                        } else {
+                            matcher.show_pattern(*show_pattern);
+                            println!("==== {} ====", label);
+                            if *cardinality {
+                                matcher.format_cardinality(&mut stdout()).unwrap();
+                            } else if *entropy {
+                                matcher.format_entropy(&mut stdout()).unwrap();
+                            } else if *distribution {
+                                matcher.format_distribution(&mut stdout()).unwrap();
+                            } else if *extremes {
+                                matcher.format_extremes(&mut stdout()).unwrap();
+                            } else if *bytes {
+                                matcher.format_bytes(&mut stdout()).unwrap();
+                            } else if *count_captures {
+                                matcher.format_capture_counts(&mut stdout()).unwrap();
+                            } else if sum_capture.is_some() {
+                                matcher.format_sums(&mut stdout()).unwrap();
+                            } else {
+                                matcher.format(&mut stdout()).unwrap();
+                            }
+                            let file_summary = Summary {
+                                patterns: matcher.len(),
+                                matches: matcher.total_matches(),
+                                lines: matcher.total_lines(),
+                            };
+                            summary += file_summary;
+                            if !cli.quiet {
+                                file_summary.format(&mut stdout(), numbers).unwrap();
+                            }
+                            if *throughput {
+                                println!();
+                                file_throughput.format(&mut stdout(), numbers).unwrap();
+                            }
+                            println!();
+                        }
+
+                        if wordcloud.is_some() {
+                            match &mut cloud {
+                                Some(cloud) => cloud.merge(matcher),
+                                None => cloud = Some(matcher),
+                            }
+                        }
+                    },
+                );
+                failures.check(file, result);
+            }
+            if !cli.quiet && *format == Format::Human && inputs > 1 {
+                print!("All inputs: ");
+                summary.format(&mut stdout(), numbers).unwrap();
+            }
+            if *throughput && inputs > 1 {
+                print!("Total: ");
+                total.format(&mut stdout(), numbers).unwrap();
+            }
+            if let (Some(dir), Some(cloud)) = (wordcloud, &cloud) {
+                if let Err(e) = analyzer::write_wordclouds(dir, cloud) {
+                    eprintln!("Unable to write the word clouds: {}", e);
+                    process::exit(1);
+                }
             }
+            note_skipped_input(&read_options, *count_decode_failures);
         }
         Commands::Count {
             patterns,
+            expr,
+            files,
+            files_from,
+            include,
+            exclude,
+            require_patterns,
+            jobs,
+            score,
+            no_sort,
+            watch,
+            interval,
+            live,
+            examples,
+            sample_lines,
+            rare,
+            seed,
+            indent,
+            separator,
+            group_union,
+            ascii,
+            dfa_size_limit,
+            size_limit,
+            relative_to,
+            per_million,
+            show_regex,
+            window,
+            count_mode,
+            collapse_consecutive,
+            at_least,
+            max_depth,
+            group_by,
+            rollup,
+            profile,
+            debug_indices,
+            explain_config,
+            format,
+            result_hash,
+            metric_prefix,
+            throughput,
+            record_separator,
+            keep_cr,
+            json_field,
+            skip_invalid_json,
+            timeout,
+            max_line_bytes,
+            progress,
+            decode,
+            decode_field,
+            count_decode_failures,
+            file_as_record,
+            split_output,
+        } => {
+            let files = &input_files(files, files_from);
+            if rare.is_some_and(|percentile| !(0.0..=100.0).contains(&percentile)) {
+                eprintln!("--rare must be a percentile between 0 and 100");
+                process::exit(1);
+            }
+            if split_output.is_some() && *format != Format::Human {
+                eprintln!("--split-output only supports the human format");
+                process::exit(1);
+            }
+            let read_options = ReadOptions {
+                separator: *record_separator,
+                keep_cr: *keep_cr,
+                json_field: json_field.clone(),
+                skip_invalid_json: *skip_invalid_json,
+                decoder: decode.map(|encoding| Decoder::new(encoding, decode_field.clone())),
+                deadline: timeout.map(|seconds| Instant::now() + Duration::from_secs(seconds)),
+                line_limit: max_line_bytes.map(LineLimit::new),
+                progress: None,
+            };
+            let options_for = |file: &str| ReadOptions {
+                progress: progress.then(|| Progress::new(file)),
+                ..read_options.clone()
+            };
+            let paths = patterns;
+            let loaded = load_patterns(paths, expr);
+            let loaded_leaves = analyzer::leaves(&loaded).len();
+            let mut patterns = select_patterns(loaded, include, exclude);
+            if *require_patterns {
+                require_leaves(&patterns);
+            }
+            if *group_union {
+                patterns = analyzer::union_groups(patterns).unwrap();
+            }
+            let options = RegexOptions {
+                ascii: *ascii,
+                dfa_size_limit: *dfa_size_limit,
+                size_limit: *size_limit,
+            };
+            if options != RegexOptions::default() {
+                patterns = recompile_patterns(patterns, options);
+            }
+            if *explain_config {
+                write_config_explanation(
+                    &mut stdout(),
+                    paths,
+                    expr,
+                    include,
+                    exclude,
+                    options,
+                    loaded_leaves,
+                    &patterns,
+                )
+                .unwrap();
+                return;
+            }
+            if let Some(name) = relative_to {
+                if analyzer::find_leaf(&patterns, name).is_none() {
+                    eprintln!("No unique pattern named '{}'", name);
+                    process::exit(1);
+                }
+            }
+            let new_counter = || {
+                let mut counter = PatternCounter::new(patterns.clone(), sort_mode(*no_sort));
+                if *examples > 0 {
+                    counter.sample_examples(*examples, *seed);
+                }
+                if *sample_lines {
+                    counter.sample_lines(*seed);
+                }
+                if let Some(percentile) = rare {
+                    counter.rare(*percentile);
+                }
+                if let Some(max_depth) = max_depth {
+                    counter.max_depth(*max_depth);
+                }
+                if let Some(capture) = group_by {
+                    counter.group_by(capture);
+                }
+                if *profile {
+                    counter.profile();
+                }
+                counter.per_million(*per_million);
+                counter.show_regex(*show_regex);
+                counter.count_mode(*count_mode);
+                counter.collapse_consecutive(*collapse_consecutive);
+                counter.at_least(*at_least);
+                if let Some(size) = window {
+                    counter.window(*size);
+                }
+                counter.indent(*indent);
+                counter.separator(separator.clone());
+                counter.number_format(numbers);
+                counter
+            };
+            if *debug_indices {
+                print_indices(&new_counter().index_names());
+            }
+            let set_baseline = |label: &str, counter: &mut PatternCounter| {
+                if let Some(name) = relative_to {
+                    match counter.count(name) {
+                        Some(0) | None => {
+                            eprintln!(
+                                "{}: '{}' did not match any line, skipping the ratios",
+                                label, name
+                            )
+                        }
+                        Some(baseline) => counter.relative_to(baseline),
+                    }
+                }
+            };
+            let format_counter = |label: &str, counter: &mut PatternCounter| {
+                println!("==== {} ====", label);
+                if *count_mode == CountMode::Occurrences {
+                    println!(
+                        "(counting occurrences, a line matching a pattern twice counts twice)"
+                    );
+                }
+                set_baseline(label, counter);
+                if *score {
+                    counter.format_score(&mut stdout()).unwrap();
+                } else {
+                    counter.format(&mut stdout()).unwrap();
+                }
+                if *rollup {
+                    println!();
+                    counter.format_rollup(&mut stdout()).unwrap();
+                }
+                if *profile {
+                    println!();
+                    counter.format_profile(&mut stdout()).unwrap();
+                }
+            };
+
+            if *file_as_record {
+                let mut counter = new_counter();
+                let mut analyzed = 0;
+                for file in files {
+                    if let Some(text) = failures.check(file, analyzer::read_input(file)) {
+                        counter.analyze(text);
+                        analyzed += 1;
+                    }
+                }
+                match format {
+                    Format::Human => {
+                        format_counter(&format!("{} files", analyzed), &mut counter);
+                        println!();
+                    }
+                    Format::Json => {
+                        println!("{}", serde_json::to_string(&counter.report()).unwrap())
+                    }
+                    Format::Folded => counter.format_folded(&mut stdout()).unwrap(),
+                    Format::Prometheus | Format::OpenMetrics => {
+                        eprintln!(
+                            "The prometheus and openmetrics formats are not supported with \
+                            --file-as-record"
+                        );
+                        process::exit(1);
+                    }
+                }
+                return;
+            }
+
+            if let Some(dir) = watch {
+                let stop = Arc::new(AtomicBool::new(false));
+                let handler_stop = stop.clone();
+                if let Err(e) =
+                    ctrlc::set_handler(move || handler_stop.store(true, Ordering::SeqCst))
+                {
+                    eprintln!("Unable to handle Ctrl-C: {}", e);
+                    process::exit(1);
+                }
+
+                let term = Term::stdout();
+                let render = |counter: &mut PatternCounter| {
+                    let mut output = Vec::new();
+                    writeln!(output, "==== {} ====", dir).unwrap();
+                    if *score {
+                        counter.format_score(&mut output).unwrap();
+                    } else {
+                        counter.format(&mut output).unwrap();
+                    }
+                    if *live {
+                        redraw(&term, &output).unwrap();
+                    } else {
+                        stdout().write_all(&output).unwrap();
+                        println!();
+                    }
+                };
+
+                let mut watcher = DirectoryWatcher::new(new_counter());
+                watcher.keep_cr(*keep_cr);
+                if *live {
+                    term.hide_cursor().unwrap();
+                    render(watcher.analyzer());
+                }
+                let result = watcher.watch(dir, Duration::from_secs(*interval), &stop, render);
+                if *live {
+                    term.show_cursor().unwrap();
+                }
+                if let Err(e) = result {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+                render(watcher.analyzer());
+                return;
+            }
+
+            if *result_hash {
+                for file in files {
+                    if read_options.timed_out() {
+                        break;
+                    }
+                    let result = analyzer::analyze_input_metered(
+                        file,
+                        *jobs,
+                        new_counter,
+                        &options_for(file),
+                        |entry, result| {
+                            let label = cli.path_label.entry_label(file, entry);
+                            if let Some((counter, _)) = failures.check(&label, result) {
+                                println!("{}  {}", counter.result_hash(), label);
+                            }
+                        },
+                    );
+                    failures.check(file, result);
+                }
+                note_skipped_input(&read_options, *count_decode_failures);
+                return;
+            }
+
+            if *format == Format::Prometheus {
+                PatternCounter::format_prometheus_header(&mut stdout(), *count_mode).unwrap();
+                for file in files {
+                    if read_options.timed_out() {
+                        break;
+                    }
+                    let result = analyzer::analyze_input_metered(
+                        file,
+                        *jobs,
+                        new_counter,
+                        &options_for(file),
+                        |entry, result| {
+                            let label = cli.path_label.entry_label(file, entry);
+                            if let Some((counter, _)) = failures.check(&label, result) {
+                                counter.format_prometheus(&mut stdout(), &label).unwrap();
+                            }
+                        },
+                    );
+                    failures.check(file, result);
+                }
+                note_skipped_input(&read_options, *count_decode_failures);
+                return;
+            }
+
+            if *format == Format::OpenMetrics {
+                let mut counters = Vec::new();
+                for file in files {
+                    if read_options.timed_out() {
+                        break;
+                    }
+                    let result = analyzer::analyze_input_metered(
+                        file,
+                        *jobs,
+                        new_counter,
+                        &options_for(file),
+                        |entry, result| {
+                            let label = cli.path_label.entry_label(file, entry);
+                            if let Some((counter, throughput)) = failures.check(&label, result) {
+                                counters.push((label, counter, throughput.lines));
+                            }
+                        },
+                    );
+                    failures.check(file, result);
+                }
+
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs_f64();
+                PatternCounter::format_openmetrics(
+                    &mut stdout(),
+                    &counters,
+                    metric_prefix,
+                    timestamp,
+                )
+                .unwrap();
+                note_skipped_input(&read_options, *count_decode_failures);
+                return;
+            }
+
+            if matches!(format, Format::Json | Format::Folded) {
+                for file in files {
+                    if read_options.timed_out() {
+                        break;
+                    }
+                    let result = analyzer::analyze_input_metered(
+                        file,
+                        *jobs,
+                        new_counter,
+                        &options_for(file),
+                        |entry, result| {
+                            let label = cli.path_label.entry_label(file, entry);
+                            if let Some((counter, _)) = failures.check(&label, result) {
+                                if *format == Format::Folded {
+                                    counter.format_folded(&mut stdout()).unwrap();
+                                } else {
+                                    println!(
+                                        "{}",
+                                        serde_json::to_string(&counter.report()).unwrap()
+                                    );
+                                }
+                            }
+                        },
+                    );
+                    failures.check(file, result);
+                }
+                note_skipped_input(&read_options, *count_decode_failures);
+                return;
+            }
+
+            let mut split_files =
+                split_output
+                    .as_ref()
+                    .map(|dir| match create_split_output(dir, &patterns) {
+                        Ok(split_files) => split_files,
+                        Err(e) => {
+                            eprintln!("Unable to create the split output in {}: {}", dir, e);
+                            process::exit(1);
+                        }
+                    });
+            let mut split_counter = |label: &str, counter: &mut PatternCounter| {
+                set_baseline(label, counter);
+                for (group, writer) in split_files.iter_mut().flatten() {
+                    let result = (|| {
+                        writeln!(writer, "==== {} ====", label)?;
+                        if *count_mode == CountMode::Occurrences {
+                            writeln!(
+                                writer,
+                                "(counting occurrences, a line matching a pattern twice counts \
+                                twice)"
+                            )?;
+                        }
+                        counter.format_group(writer, group.as_deref())?;
+                        writeln!(writer)
+                    })();
+                    if let Err(e) = result {
+                        eprintln!("Unable to write the split output: {}", e);
+                        process::exit(1);
+                    }
+                }
+            };
+
+            let mut total = Throughput::default();
+            let mut summary = Summary::default();
+            let mut inputs = 0;
+            for file in files {
+                if read_options.timed_out() {
+                    break;
+                }
+                let result = analyzer::analyze_input_metered(
+                    file,
+                    *jobs,
+                    new_counter,
+                    &options_for(file),
+                    |entry, result| {
+                        let label = cli.path_label.entry_label(file, entry);
+                        let (mut counter, file_throughput) = match failures.check(&label, result) {
+                            Some(result) => result,
+                            None => return,
+                        };
+                        total += file_throughput;
+                        inputs += 1;
+                        if split_output.is_some() {
+                            split_counter(&label, &mut counter);
+                            return;
+                        }
+                        format_counter(&label, &mut counter);
+                        let file_summary = Summary {
+                            patterns: counter.len(),
+                            matches: counter.total_matches(),
+                            lines: counter.total_lines(),
+                        };
+                        summary += file_summary;
+                        if !cli.quiet {
+                            file_summary.format(&mut stdout(), numbers).unwrap();
+                        }
+                        if *throughput {
+                            println!();
+                            file_throughput.format(&mut stdout(), numbers).unwrap();
+                        }
+                        println!();
+                    },
+                );
+                failures.check(file, result);
+            }
+            if !cli.quiet && split_output.is_none() && inputs > 1 {
+                print!("All inputs: ");
+                summary.format(&mut stdout(), numbers).unwrap();
+            }
+            if *throughput && inputs > 1 {
+                print!("Total: ");
+                total.format(&mut stdout(), numbers).unwrap();
+            }
+            for (_, writer) in split_files.iter_mut().flatten() {
+                if let Err(e) = writer.flush() {
+                    eprintln!("Unable to write the split output: {}", e);
+                    process::exit(1);
+                }
+            }
+            note_skipped_input(&read_options, *count_decode_failures);
+        }
+        Commands::Sum {
+            reports,
+            indent,
+            format,
+        } => {
+            let report = match analyzer::sum_reports(reports) {
+                Ok(report) => report,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            };
+            match format {
+                Format::Human => report.format(&mut stdout(), *indent, numbers).unwrap(),
+                Format::Json => println!("{}", serde_json::to_string(&report).unwrap()),
+                Format::Prometheus | Format::OpenMetrics | Format::Folded => {
+                    eprintln!(
+                        "The prometheus, openmetrics, and folded formats are not supported for \
+                        summed reports"
+                    );
+                    process::exit(1);
+                }
+            }
+        }
+        Commands::Extract {
+            patterns,
+            expr,
             files,
-            include: _,
-            exclude: _,
+            show_pattern,
+            line_number,
+            with_filename,
+            record_separator,
         } => {
-            let patterns = analyzer::parse_input(&patterns).unwrap();
+            let patterns = load_patterns(patterns, expr);
+            let read_options = ReadOptions {
+                separator: *record_separator,
+                ..ReadOptions::default()
+            };
+
+            let stdout = stdout();
             for file in files {
-                let file = Path::new(&file);
-                let mut counter = analyzer::count_file(file, patterns.clone()).unwrap();
-                println!("==== {} ====", file.file_name().unwrap().to_str().unwrap());
-                counter.format(&mut stdout());
-                println!();
+                let mut extractor = PatternExtractor::new(
+                    patterns.clone(),
+                    SortMode::default(),
+                    BufWriter::new(stdout.lock()),
+                );
+                extractor.show_pattern(*show_pattern);
+                extractor.line_numbers(*line_number);
+                if *with_filename {
+                    extractor.file_name(Some(cli.path_label.label(file)));
+                }
+                let result = analyzer::analyze_file(file, extractor, &read_options);
+                if let Some(mut extractor) = failures.check(file, result) {
+                    extractor.format(&mut io::sink()).unwrap();
+                }
+            }
+        }
+        Commands::Sample {
+            patterns,
+            expr,
+            files,
+            n,
+            seed,
+            output,
+            record_separator,
+        } => {
+            let patterns = load_patterns(patterns, expr);
+            let read_options = ReadOptions {
+                separator: *record_separator,
+                ..ReadOptions::default()
+            };
+
+            let mut sampler = LineSampler::new(patterns, *n, *seed);
+            for file in files {
+                let result = analyzer::analyze_file(file, &mut sampler, &read_options);
+                failures.check(file, result);
+            }
+
+            let result = match output {
+                Some(output) => File::create(output).and_then(|file| {
+                    let mut writer = BufWriter::new(file);
+                    sampler.format(&mut writer)?;
+                    writer.flush()
+                }),
+                None => sampler.format(&mut stdout()),
+            };
+            if let Err(e) = result {
+                eprintln!("Unable to write the sample: {}", e);
+                process::exit(1);
             }
         }
         Commands::Clean {
-            patterns: _,
-            files: _,
-        } => {}
-        Commands::Vocab { files } => {
+            patterns,
+            files,
+            dry_run,
+            examples,
+            in_place,
+            backup,
+            overlap_policy,
+        } => {
+            let patterns = load_patterns(patterns, &[]);
+            let read_options = ReadOptions {
+                keep_cr: true,
+                ..ReadOptions::default()
+            };
+
+            for file in files {
+                if *in_place {
+                    let result = analyzer::clean_file_in_place(
+                        file,
+                        patterns.clone(),
+                        &read_options,
+                        *overlap_policy,
+                        *backup,
+                    );
+                    failures.check(file, result);
+                    continue;
+                }
+
+                // The lock of stdout is line buffered, so each cleaned line is passed on at once.
+                let writer: Box<dyn Write> = if *dry_run {
+                    Box::new(io::sink())
+                } else if file == analyzer::STDIN {
+                    Box::new(stdout().lock())
+                } else {
+                    let output = format!("{}.clean", file);
+                    match File::create(&output) {
+                        Ok(output) => Box::new(BufWriter::new(output)),
+                        Err(e) => {
+                            failures.check::<()>(file, Err(format!("{}: {}", output, e)));
+                            continue;
+                        }
+                    }
+                };
+                let mut cleaner = PatternCleaner::new(patterns.clone(), writer);
+                cleaner.overlap_policy(*overlap_policy);
+                if *dry_run {
+                    cleaner.examples(*examples);
+                }
+                let result = analyzer::analyze_file(file, cleaner, &read_options);
+                if let Some(mut cleaner) = failures.check(file, result) {
+                    if *dry_run {
+                        println!("==== {} ====", cli.path_label.label(file));
+                        cleaner.format_changes(&mut stdout(), numbers).unwrap();
+                        println!();
+                    }
+                    let result = cleaner
+                        .format(&mut io::sink())
+                        .map_err(|e| format!("{}", e));
+                    failures.check(file, result);
+                }
+            }
+            if *dry_run {
+                println!("Dry run, no files were modified.");
+            }
+        }
+        Commands::Explain {
+            patterns,
+            pattern_name,
+            line,
+        } => {
+            let patterns = load_patterns(patterns, &[]);
+            match analyzer::find_leaf(&patterns, pattern_name) {
+                Some(pattern) => analyzer::explain(&mut stdout(), pattern, line).unwrap(),
+                None => {
+                    eprintln!("No unique pattern named '{}'", pattern_name);
+                    process::exit(1);
+                }
+            }
+        }
+        Commands::Lint { patterns } => {
+            let patterns = load_patterns(patterns, &[]);
+            match analyzer::lint(&mut stdout(), &patterns).unwrap() {
+                0 => println!("No problems found"),
+                _ => process::exit(1),
+            }
+        }
+        Commands::Repl { patterns: paths } => {
+            let mut patterns = load_patterns(paths, &[]);
+            let stdin = io::stdin();
+            let mut lines = stdin.lock().lines();
+            loop {
+                print!("> ");
+                stdout().flush().unwrap();
+                let line = match lines.next() {
+                    Some(line) => line.unwrap(),
+                    None => break,
+                };
+
+                match line.trim() {
+                    ":reload" => match analyzer::parse_inputs(paths) {
+                        Ok(reloaded) => {
+                            patterns = reloaded;
+                            println!("Reloaded {}", paths.join(", "));
+                        }
+                        Err(e) => eprintln!("Unable to reload: {}", e),
+                    },
+                    _ => analyzer::test_line(&mut stdout(), &patterns, &line).unwrap(),
+                }
+            }
+            println!();
+        }
+        Commands::Schema => println!("{}", analyzer::patterns_schema()),
+        Commands::Dump { patterns } => {
+            let patterns = load_patterns(patterns, &[]);
+            analyzer::dump_patterns(stdout(), &patterns).unwrap();
+        }
+        Commands::Config {
+            patterns: paths,
+            expr,
+            include,
+            exclude,
+            expand_definitions,
+            output,
+        } => {
+            let patterns = select_patterns(load_patterns(paths, expr), include, exclude);
+            let definitions = if *expand_definitions {
+                None
+            } else {
+                Some(analyzer::parse_definitions(paths).unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }))
+            };
+
+            let result = match output {
+                Some(output) => {
+                    File::create(output)
+                        .map_err(|e| format!("{}", e))
+                        .and_then(|file| {
+                            let mut writer = BufWriter::new(file);
+                            analyzer::dump_config(&mut writer, &patterns, definitions.as_ref())?;
+                            writer.flush().map_err(|e| format!("{}", e))
+                        })
+                }
+                None => analyzer::dump_config(stdout(), &patterns, definitions.as_ref()),
+            };
+            if let Err(e) = result {
+                eprintln!("Unable to write the patterns: {}", e);
+                process::exit(1);
+            }
+        }
+        Commands::Cooccur {
+            patterns,
+            a,
+            b,
+            files,
+            jobs,
+            record_separator,
+        } => {
+            let read_options = ReadOptions {
+                separator: *record_separator,
+                ..ReadOptions::default()
+            };
+            let patterns = load_patterns(patterns, &[]);
+            let find = |name: &str| match analyzer::find_leaf(&patterns, name) {
+                Some(pattern) => pattern.clone(),
+                None => {
+                    eprintln!("No unique pattern named '{}'", name);
+                    process::exit(1);
+                }
+            };
+            let (a, b) = (find(a), find(b));
+            let new_counter = || {
+                let mut counter = CooccurrenceCounter::new(a.clone(), b.clone());
+                counter.number_format(numbers);
+                counter
+            };
+
+            for file in files {
+                let result =
+                    analyzer::analyze_file_parallel(file, *jobs, new_counter, &read_options);
+                let mut counter = match failures.check(file, result) {
+                    Some(counter) => counter,
+                    None => continue,
+                };
+                println!("==== {} ====", cli.path_label.label(file));
+                counter.format(&mut stdout()).unwrap();
+                println!();
+            }
+        }
+        Commands::Overlap {
+            patterns,
+            expr,
+            files,
+            top,
+            jobs,
+            record_separator,
+        } => {
+            let read_options = ReadOptions {
+                separator: *record_separator,
+                ..ReadOptions::default()
+            };
+            let patterns = load_patterns(patterns, expr);
+            let new_counter = || {
+                let mut counter = OverlapCounter::new(&patterns, *top);
+                counter.number_format(numbers);
+                counter
+            };
+
+            for file in files {
+                let result =
+                    analyzer::analyze_file_parallel(file, *jobs, new_counter, &read_options);
+                let mut counter = match failures.check(file, result) {
+                    Some(counter) => counter,
+                    None => continue,
+                };
+                println!("==== {} ====", cli.path_label.label(file));
+                counter.format(&mut stdout()).unwrap();
+                println!();
+            }
+        }
+        Commands::Timeline {
+            patterns,
+            expr,
+            bucket,
+            files,
+            sparkline,
+            record_separator,
+        } => {
+            let patterns = load_patterns(patterns, expr);
+            let bucket = Regex::new(bucket).unwrap_or_else(|e| {
+                eprintln!("Invalid bucket regex: {}", e);
+                process::exit(1);
+            });
+            let read_options = ReadOptions {
+                separator: *record_separator,
+                ..ReadOptions::default()
+            };
+
+            for file in files {
+                let mut timeline = TimelineCounter::new(patterns.clone(), bucket.clone());
+                timeline.sparkline(*sparkline);
+                timeline.number_format(numbers);
+                let result = analyzer::analyze_file(file, timeline, &read_options);
+                let mut timeline = match failures.check(file, result) {
+                    Some(timeline) => timeline,
+                    None => continue,
+                };
+                println!("==== {} ====", cli.path_label.label(file));
+                timeline.format(&mut stdout()).unwrap();
+                println!();
+            }
+        }
+        Commands::MatchDiff {
+            patterns,
+            expr,
+            file_a,
+            file_b,
+            top,
+            record_separator,
+        } => {
+            let patterns = load_patterns(patterns, expr);
+            let read_options = ReadOptions {
+                separator: *record_separator,
+                ..ReadOptions::default()
+            };
+            let analyze = |file: &String| {
+                let mut matcher = PatternMatcher::new(patterns.clone(), *top, SortMode::default());
+                matcher.number_format(numbers);
+                analyzer::analyze_file(file, matcher, &read_options).unwrap_or_else(|e| {
+                    eprintln!("Unable to analyze {}: {}", file, e);
+                    process::exit(1);
+                })
+            };
+            let (a, b) = (analyze(file_a), analyze(file_b));
+
+            println!(
+                "==== {} -> {} ====",
+                cli.path_label.label(file_a),
+                cli.path_label.label(file_b)
+            );
+            a.format_diff(&mut stdout(), &b).unwrap();
+        }
+        Commands::Suggest {
+            files,
+            top,
+            record_separator,
+        } => {
+            let read_options = ReadOptions {
+                separator: *record_separator,
+                ..ReadOptions::default()
+            };
+            let mut suggester = ShapeSuggester::new(*top);
             for file in files {
-                let file = Path::new(&file);
-                let mut vocabulizer = analyzer::vocabulizer(file).unwrap();
-                println!("==== {} ====", file.file_name().unwrap().to_str().unwrap());
-                vocabulizer.format(&mut stdout());
+                let result = analyzer::analyze_file(file, ShapeSuggester::new(*top), &read_options);
+                if let Some(other) = failures.check(file, result) {
+                    suggester.merge(other);
+                }
+            }
+            suggester.number_format(numbers);
+            suggester.format(&mut stdout()).unwrap();
+        }
+        Commands::Vocab { files, top } => {
+            for file in files {
+                let mut vocabulizer = match failures.check(file, analyzer::count_vocab(file, *top))
+                {
+                    Some(vocabulizer) => vocabulizer,
+                    None => continue,
+                };
+                vocabulizer.number_format(numbers);
+                println!("==== {} ====", cli.path_label.label(file));
+                vocabulizer.format(&mut stdout()).unwrap();
                 println!();
             }
         }
     }
 }
+
+/// Keeps the patterns selected by the comma separated `--include` and `--exclude` globs.
+fn select_patterns<T>(
+    patterns: Vec<GroupTree<T>>,
+    include: &Option<String>,
+    exclude: &Option<String>,
+) -> Vec<GroupTree<T>>
+where
+    T: Named,
+{
+    let globs = |list: &Option<String>| -> Vec<String> {
+        list.iter()
+            .flat_map(|list| list.split(','))
+            .map(|glob| glob.trim().to_string())
+            .filter(|glob| !glob.is_empty())
+            .collect()
+    };
+    analyzer::filter_tree(patterns, &globs(include), &globs(exclude))
+}
+
+/// Creates the directory of the `--split-output` and a file for each top-level group, named after
+/// the group, as well as `root.txt` for the patterns outside of any group if there are any.
+fn create_split_output(
+    dir: &str,
+    patterns: &[GroupTree<Pattern>],
+) -> io::Result<Vec<(Option<String>, BufWriter<File>)>> {
+    fs::create_dir_all(dir)?;
+    let create = |name: &str| -> io::Result<BufWriter<File>> {
+        let name = name.replace(['/', '\\'], "_");
+        Ok(BufWriter::new(File::create(
+            Path::new(dir).join(format!("{}.txt", name)),
+        )?))
+    };
+
+    let mut split_files = Vec::new();
+    if patterns
+        .iter()
+        .any(|tree| matches!(tree, GroupTree::Leaf(_)))
+    {
+        split_files.push((None, create("root")?));
+    }
+    for tree in patterns {
+        if let GroupTree::Group { name, .. } = tree {
+            split_files.push((Some(name.clone()), create(name)?));
+        }
+    }
+    Ok(split_files)
+}
+
+/// Exits with an error if the filtered pattern trees do not contain a single pattern.
+fn require_leaves<T>(patterns: &[GroupTree<T>])
+where
+    T: Named,
+{
+    if analyzer::leaves(patterns).is_empty() {
+        eprintln!("No patterns are left after applying --include and --exclude");
+        process::exit(1);
+    }
+}
+
+/// Warns that the results are incomplete if the `--timeout` stopped the analysis or lines were
+/// truncated, and, if `count_decode_failures` is set, prints how many lines could not be decoded.
+fn note_skipped_input(options: &ReadOptions, count_decode_failures: bool) {
+    if options.timed_out() {
+        eprintln!("Timed out, the results only cover the input read before the timeout");
+    }
+    if let Some(limit) = options
+        .line_limit
+        .as_ref()
+        .filter(|limit| limit.truncated() > 0)
+    {
+        eprintln!(
+            "{} lines were truncated to {} bytes",
+            limit.truncated(),
+            limit.max_bytes()
+        );
+    }
+    if let (Some(decoder), true) = (&options.decoder, count_decode_failures) {
+        eprintln!("{} lines could not be decoded", decoder.failures());
+    }
+}
+
+/// Number of patterns whose compiled regex is shown by `--explain-config`.
+const EXPLAINED_PATTERNS: usize = 5;
+
+/// Writes what a run would analyze for `--explain-config`: where each patterns file is read from,
+/// the regex options, the filters, how many of the loaded patterns are left, and the first
+/// patterns with their compiled regexes.
+#[allow(clippy::too_many_arguments)]
+fn write_config_explanation<W>(
+    writer: &mut W,
+    paths: &[String],
+    exprs: &[String],
+    include: &Option<String>,
+    exclude: &Option<String>,
+    options: RegexOptions,
+    loaded_leaves: usize,
+    patterns: &[GroupTree<Pattern>],
+) -> io::Result<()>
+where
+    W: Write,
+{
+    writeln!(writer, "Patterns files:")?;
+    for path in paths {
+        match path.strip_prefix(analyzer::ENV_PREFIX) {
+            Some(name) => writeln!(writer, "  {} (environment variable {})", path, name)?,
+            None => {
+                let resolved = Path::new(path)
+                    .canonicalize()
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_else(|_| path.clone());
+                writeln!(writer, "  {} ({})", path, resolved)?
+            }
+        }
+    }
+    writeln!(writer, "Inline patterns: {}", exprs.len())?;
+    writeln!(
+        writer,
+        "Regex options: ascii={}, dfa_size_limit={}, size_limit={}",
+        options.ascii,
+        options
+            .dfa_size_limit
+            .map_or_else(|| String::from("default"), |limit| limit.to_string()),
+        options
+            .size_limit
+            .map_or_else(|| String::from("default"), |limit| limit.to_string())
+    )?;
+    writeln!(
+        writer,
+        "Include: {}",
+        include.as_deref().unwrap_or("everything")
+    )?;
+    writeln!(
+        writer,
+        "Exclude: {}",
+        exclude.as_deref().unwrap_or("nothing")
+    )?;
+
+    let leaves = analyzer::leaves(patterns);
+    writeln!(
+        writer,
+        "Patterns: {} of {} loaded",
+        leaves.len(),
+        loaded_leaves
+    )?;
+    for (path, pattern) in leaves.iter().take(EXPLAINED_PATTERNS) {
+        writeln!(writer, "  {}: /{}/", path, pattern.regex.as_str())?;
+    }
+    if leaves.len() > EXPLAINED_PATTERNS {
+        writeln!(writer, "  … {} more", leaves.len() - EXPLAINED_PATTERNS)?;
+    }
+    Ok(())
+}
+
+/// Writes the fully-qualified name of the pattern at each flattened index to stderr.
+fn print_indices(names: &[String]) {
+    for (index, name) in names.iter().enumerate() {
+        eprintln!("{}: {}", index, name);
+    }
+}
+
+/// Replaces the screen of the terminal with the output, cut to the size of the terminal, which is
+/// checked on every redraw so that resizing the terminal takes effect with the next redraw.
+fn redraw(term: &Term, output: &[u8]) -> io::Result<()> {
+    let (height, width) = term.size();
+    term.clear_screen()?;
+    // Leave the last row empty, so that the terminal does not scroll.
+    for line in String::from_utf8_lossy(output)
+        .lines()
+        .take(usize::from(height).saturating_sub(1))
+    {
+        term.write_line(&console::truncate_str(line, width.into(), ""))?;
+    }
+    Ok(())
+}
+
+/// Returns the input files followed by the files listed in the `--files-from` file. Exits if the
+/// list cannot be read.
+fn input_files(files: &[String], files_from: &Option<String>) -> Vec<String> {
+    let mut files = files.to_vec();
+    if let Some(list) = files_from {
+        let listed = analyzer::open_input(list).and_then(|reader| {
+            reader
+                .lines()
+                .collect::<io::Result<Vec<_>>>()
+                .map_err(|e| format!("{}", e))
+        });
+        match listed {
+            Ok(lines) => files.extend(
+                lines
+                    .iter()
+                    .map(|line| line.trim())
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(str::to_string),
+            ),
+            Err(e) => {
+                eprintln!("Unable to read the files from {}: {}", list, e);
+                process::exit(1);
+            }
+        }
+    }
+    files
+}
+
+/// Loads the patterns of the patterns files followed by the inline patterns. Exits if any of them
+/// are invalid.
+fn load_patterns(paths: &[String], exprs: &[String]) -> Vec<GroupTree<Pattern>> {
+    let patterns = analyzer::parse_inputs(paths).and_then(|mut patterns| {
+        analyzer::merge_trees(&mut patterns, analyzer::parse_expressions(exprs)?)?;
+        Ok(patterns)
+    });
+    patterns.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    })
+}
+
+/// Recompiles the patterns for the `--ascii`, `--dfa-size-limit`, and `--size-limit` flags, exiting
+/// with the names of the patterns which fail to compile, and naming the patterns which stay in
+/// Unicode mode.
+fn recompile_patterns(
+    patterns: Vec<GroupTree<Pattern>>,
+    options: RegexOptions,
+) -> Vec<GroupTree<Pattern>> {
+    let (patterns, unicode) = analyzer::recompile_patterns(patterns, options).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    if !unicode.is_empty() {
+        eprintln!(
+            "The following patterns need Unicode and are matched without --ascii:\n{}",
+            unicode.join("\n")
+        );
+    }
+    patterns
+}
+
+/// Returns the sort mode selected by the `--no-sort` flag.
+fn sort_mode(no_sort: bool) -> SortMode {
+    if no_sort {
+        SortMode::SourceOrder
+    } else {
+        SortMode::Alphabetical
+    }
+}
@@ -0,0 +1,149 @@
+//! Contains the instrumentation which measures how fast an input is analyzed.
+//!
+use std::io::{self, Write};
+use std::ops::AddAssign;
+use std::time::Duration;
+
+use crate::{Analyzer, Merge, NumberFormat};
+
+/// Amount of input analyzed within some time.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Throughput {
+    pub lines: u64,
+
+    /// Number of bytes of all lines, without their separators.
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl Throughput {
+    /// Writes the number of lines and bytes analyzed per second to the given writer.
+    pub fn format<W>(&self, writer: &mut W, numbers: NumberFormat) -> io::Result<()>
+    where
+        W: Write,
+    {
+        let seconds = self.elapsed.as_secs_f64();
+        let per_second = |amount: u64| {
+            if seconds > 0.0 {
+                amount as f64 / seconds
+            } else {
+                0.0
+            }
+        };
+
+        writeln!(
+            writer,
+            "{} lines, {} bytes in {:.3}s ({} lines/s, {:.2} MB/s)",
+            numbers.format(self.lines),
+            numbers.format(self.bytes),
+            seconds,
+            numbers.format(per_second(self.lines) as u64),
+            per_second(self.bytes) / (1024.0 * 1024.0)
+        )
+    }
+}
+
+impl AddAssign for Throughput {
+    fn add_assign(&mut self, other: Self) {
+        self.lines += other.lines;
+        self.bytes += other.bytes;
+        self.elapsed += other.elapsed;
+    }
+}
+
+/// Volume of the results of an input, written as a footer below them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Number of patterns the input was analyzed with.
+    pub patterns: usize,
+
+    /// Number of matches of all patterns.
+    pub matches: u64,
+
+    /// Number of analyzed lines.
+    pub lines: u64,
+}
+
+impl Summary {
+    /// Writes the number of patterns, matches, and analyzed lines to the given writer.
+    pub fn format<W>(&self, writer: &mut W, numbers: NumberFormat) -> io::Result<()>
+    where
+        W: Write,
+    {
+        writeln!(
+            writer,
+            "{} patterns, {} total matches, {} lines processed",
+            numbers.format(self.patterns),
+            numbers.format(self.matches),
+            numbers.format(self.lines)
+        )
+    }
+}
+
+impl AddAssign for Summary {
+    /// Adds up the matches and lines, while all inputs are analyzed with the same patterns.
+    fn add_assign(&mut self, other: Self) {
+        self.patterns = self.patterns.max(other.patterns);
+        self.matches += other.matches;
+        self.lines += other.lines;
+    }
+}
+
+/// Wraps an analyzer, counting the lines and bytes passed to it.
+#[derive(Debug, Clone)]
+pub struct Metered<A> {
+    analyzer: A,
+    lines: u64,
+    bytes: u64,
+}
+
+impl<A> Metered<A> {
+    pub fn new(analyzer: A) -> Self {
+        Metered {
+            analyzer,
+            lines: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Consumes the wrapper, returning the analyzer together with the number of lines and bytes
+    /// it analyzed.
+    pub fn into_inner(self) -> (A, u64, u64) {
+        (self.analyzer, self.lines, self.bytes)
+    }
+}
+
+impl<T, A> Analyzer<T> for Metered<A>
+where
+    A: Analyzer<T>,
+{
+    type Analysis = A::Analysis;
+
+    fn analyze(&mut self, line: String) {
+        self.lines += 1;
+        self.bytes += line.len() as u64;
+        self.analyzer.analyze(line);
+    }
+
+    fn format<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        self.analyzer.format(writer)
+    }
+
+    fn status(&self) -> Result<(), String> {
+        self.analyzer.status()
+    }
+}
+
+impl<A> Merge for Metered<A>
+where
+    A: Merge,
+{
+    fn merge(&mut self, other: Self) {
+        self.analyzer.merge(other.analyzer);
+        self.lines += other.lines;
+        self.bytes += other.bytes;
+    }
+}
@@ -1,15 +1,201 @@
-use std::{collections::HashMap, io::Write};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, Write},
+    str::FromStr,
+};
 
 use num_format::{Locale, ToFormattedString};
 use regex::Regex;
 
-use self::group::GroupVec;
+use self::group::{GroupVec, SortMode};
 
 /// Actual pattern instance, which holds its name and its regex.
 #[derive(Debug, Clone)]
 pub struct Pattern {
     pub name: String,
+
+    /// Regex as written in the patterns file, before any definitions are expanded. The `$prefix`
+    /// of the enclosing groups is anchored in front of it, since the groups do not keep it, so
+    /// that the source still matches the same lines when it is written back as a patterns file.
+    pub source: String,
     pub regex: Regex,
+
+    /// Weight each match contributes to the overall score.
+    pub weight: f64,
+
+    /// Names of the capture groups whose values are counted separately by the matcher.
+    pub captures: Vec<String>,
+
+    /// Lines matching this regex are ignored by the pattern.
+    pub unless: Option<Regex>,
+
+    /// Only lines matching this regex are considered by the pattern.
+    pub when: Option<Regex>,
+
+    /// Replacement of each match when cleaning, which may reference capture groups like `$1` or
+    /// `${name}`. Matches are removed if there is none.
+    pub replace: Option<String>,
+
+    /// Whether the pattern keeps Unicode mode when the patterns are recompiled as ASCII.
+    pub keep_unicode: bool,
+
+    /// Number of matches the matcher shows for this pattern, instead of its global top.
+    pub top: Option<usize>,
+}
+
+impl Pattern {
+    /// Creates a pattern with the default weight and without any further options.
+    pub fn new(name: String, source: String, regex: Regex) -> Self {
+        Pattern {
+            name,
+            source,
+            regex,
+            weight: 1.0,
+            captures: Vec::new(),
+            unless: None,
+            when: None,
+            replace: None,
+            keep_unicode: false,
+            top: None,
+        }
+    }
+
+    /// Returns whether the line is excluded from the pattern, because it does not match the
+    /// `when` regex or because it matches the `unless` regex. The `when` regex is checked first,
+    /// so that it can skip expensive patterns before they are evaluated.
+    pub fn excludes(&self, line: &str) -> bool {
+        self.when.as_ref().is_some_and(|when| !when.is_match(line))
+            || self
+                .unless
+                .as_ref()
+                .is_some_and(|unless| unless.is_match(line))
+    }
+
+    /// Combines the patterns into a single pattern, which matches a line if any of them matches.
+    pub fn union(name: String, patterns: &[&Pattern]) -> Result<Pattern, String> {
+        if patterns.is_empty() {
+            return Err(format!("{}: Unable to combine an empty group", name));
+        }
+        if let Some(pattern) = patterns
+            .iter()
+            .find(|pattern| pattern.unless.is_some() || pattern.when.is_some())
+        {
+            return Err(format!(
+                "{}: Unable to combine '{}', which uses unless or when",
+                name, pattern.name
+            ));
+        }
+
+        let source = alternation(patterns.iter().map(|pattern| pattern.source.as_str()));
+        let regex = alternation(patterns.iter().map(|pattern| pattern.regex.as_str()));
+        let regex = Regex::new(&regex).map_err(|e| format!("{}: {}", name, e))?;
+        Ok(Pattern::new(name, source, regex))
+    }
+}
+
+/// Formats the numbers written by the human readable formatters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberFormat {
+    /// Locale whose thousands separator is used, or none to write the digits without grouping.
+    locale: Option<Locale>,
+}
+
+impl NumberFormat {
+    /// Groups the digits with the thousands separator of the locale.
+    pub fn new(locale: Locale) -> Self {
+        NumberFormat {
+            locale: Some(locale),
+        }
+    }
+
+    /// Writes the digits without grouping them.
+    pub fn ungrouped() -> Self {
+        NumberFormat { locale: None }
+    }
+
+    pub fn format<N>(&self, number: N) -> String
+    where
+        N: ToFormattedString + ToString,
+    {
+        match &self.locale {
+            Some(locale) => number.to_formatted_string(locale),
+            None => number.to_string(),
+        }
+    }
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat::new(Locale::en)
+    }
+}
+
+impl FromStr for NumberFormat {
+    type Err = String;
+
+    /// Parses the name of a locale, like `en` or `de`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Locale::from_name(s)
+            .map(NumberFormat::new)
+            .map_err(|_| format!("Unknown locale '{}'", s))
+    }
+}
+
+/// Joins the regexes into a single regex which matches wherever any of them matches.
+pub(crate) fn alternation<'a, I>(regexes: I) -> String
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    regexes
+        .into_iter()
+        .map(|regex| format!("(?:{})", regex))
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+/// A match of a single pattern inside a line, together with whatever the analyzer needs to process
+/// it once the overlaps are resolved.
+pub(crate) struct Span<T> {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+
+    /// Index of the pattern in the flattened patterns.
+    pub(crate) index: usize,
+    pub(crate) value: T,
+}
+
+/// Resolves the overlaps between the matches of several patterns inside the same line, returning
+/// the kept spans from left to right.
+///
+/// The spans are visited in the order of the given key, and spans with equal keys in the order
+/// they are given in. Each span is kept unless it conflicts with a span kept before, i.e. unless
+/// they share a character or start at the same position. Two empty matches at the same position
+/// thus conflict, as does an empty match with a match that starts there, while an empty match
+/// right at the end of another match does not.
+pub(crate) fn resolve_overlaps<T, K, F>(mut spans: Vec<Span<T>>, key: F) -> Vec<Span<T>>
+where
+    K: Ord,
+    F: FnMut(&Span<T>) -> K,
+{
+    spans.sort_by_key(key);
+
+    // The kept spans never overlap and start at distinct positions, so sorted by their starts,
+    // their ends are sorted as well, and only the closest kept span on either side can conflict.
+    let mut ends = BTreeMap::new();
+    let mut kept = Vec::new();
+    for span in spans {
+        let before = ends.range(..=span.start).next_back();
+        let after = ends.range(span.start + 1..).next();
+        let conflicts = before
+            .is_some_and(|(start, end)| *start == span.start || span.start < *end)
+            || after.is_some_and(|(start, _)| *start < span.end);
+        if !conflicts {
+            ends.insert(span.start, span.end);
+            kept.push(span);
+        }
+    }
+    kept.sort_by_key(|span| span.start);
+    kept
 }
 
 pub mod group {
@@ -37,16 +223,74 @@ pub mod group {
         pub flattened: Vec<V>,
     }
 
+    /// Determines the order of the groups and leaves when creating a GroupVec.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub enum SortMode {
+        /// Sort the leaves before the groups on each level, and both of them by name. Leaves with
+        /// the same name are sorted by their regex, and any remaining ties keep their order in the
+        /// source.
+        #[default]
+        Alphabetical,
+
+        /// Keep the order in which the patterns appear in the source.
+        SourceOrder,
+    }
+
     impl<V> GroupVec<V> {
+        /// Creates a GroupVec from the given tree, sorting it alphabetically.
         pub fn from_tree<T>(tree_vec: Vec<GroupTree<T>>) -> GroupVec<V>
         where
             V: From<T>,
+            T: Named,
+        {
+            Self::from_tree_with_options(tree_vec, SortMode::default())
+        }
+
+        /// Creates a GroupVec from the given tree, ordering the groups according to `sort`.
+        ///
+        /// The tree is sorted before it is flattened, so that the flattened vec always follows the
+        /// same order as a depth-first walk of `inner`. Every formatter relies on this, so that
+        /// passes over the flattened vec, e.g. to compute column widths, and passes over the tree
+        /// see the patterns in the same deterministic order.
+        pub fn from_tree_with_options<T>(tree_vec: Vec<GroupTree<T>>, sort: SortMode) -> GroupVec<V>
+        where
+            V: From<T>,
+            T: Named,
         {
+            /// Sorts a single level of the tree in place.
+            fn sort_level<T>(level: &mut [GroupTree<T>], sort: SortMode)
+            where
+                T: Named,
+            {
+                if sort == SortMode::Alphabetical {
+                    fn source<T: Named>(tree: &GroupTree<T>) -> &str {
+                        match tree {
+                            GroupTree::Leaf(value) => value.source(),
+                            GroupTree::Group { .. } => "",
+                        }
+                    }
+
+                    level.sort_by(|a, b| {
+                        let is_group =
+                            |tree: &GroupTree<T>| matches!(tree, GroupTree::Group { .. });
+                        is_group(a)
+                            .cmp(&is_group(b))
+                            .then_with(|| a.name().cmp(b.name()))
+                            .then_with(|| source(a).cmp(source(b)))
+                    });
+                }
+            }
+
             /// Helper function that traverses the GroupTree and consumes it, creating the
             /// GroupVec.
-            fn traverse<T, V>(tree: GroupTree<T>, vec: &mut Vec<V>) -> GroupTree<usize>
+            fn traverse<T, V>(
+                tree: GroupTree<T>,
+                vec: &mut Vec<V>,
+                sort: SortMode,
+            ) -> GroupTree<usize>
             where
                 V: From<T>,
+                T: Named,
             {
                 match tree {
                     GroupTree::Leaf(other) => {
@@ -55,14 +299,14 @@ pub mod group {
 
                         GroupTree::Leaf(vec.len() - 1)
                     }
-                    GroupTree::Group { name, group } => {
+                    GroupTree::Group { name, mut group } => {
+                        sort_level(&mut group, sort);
+
                         let mut inner_group: Vec<GroupTree<usize>> = Vec::new();
                         for item in group {
-                            inner_group.push(traverse(item, vec));
+                            inner_group.push(traverse(item, vec, sort));
                         }
 
-                        inner_group.sort();
-
                         GroupTree::Group {
                             name,
                             group: inner_group,
@@ -71,238 +315,4587 @@ pub mod group {
                 }
             }
 
+            let mut tree_vec = tree_vec;
+            sort_level(&mut tree_vec, sort);
+
             let mut flattened = Vec::new();
             let mut inner = Vec::new();
             for tree in tree_vec {
-                inner.push(traverse(tree, &mut flattened));
+                inner.push(traverse(tree, &mut flattened, sort));
             }
-            inner.sort();
 
             GroupVec { inner, flattened }
         }
-    }
 
-    impl<V> Deref for GroupVec<V> {
-        type Target = [V];
+        /// Returns the index of each leaf in the order of a depth-first walk of the tree,
+        /// together with the names of the groups containing it.
+        pub fn leaf_paths(&self) -> Vec<(Vec<&str>, usize)> {
+            fn traverse<'a>(
+                tree: &'a GroupTree<usize>,
+                path: &mut Vec<&'a str>,
+                paths: &mut Vec<(Vec<&'a str>, usize)>,
+            ) {
+                match tree {
+                    GroupTree::Leaf(index) => paths.push((path.clone(), *index)),
+                    GroupTree::Group { name, group } => {
+                        path.push(name);
+                        for inner in group {
+                            traverse(inner, path, paths);
+                        }
+                        path.pop();
+                    }
+                }
+            }
 
-        fn deref(&self) -> &Self::Target {
-            &self.flattened
+            let mut paths = Vec::new();
+            for tree in &self.inner {
+                traverse(tree, &mut Vec::new(), &mut paths);
+            }
+            paths
         }
-    }
 
-    impl<V> DerefMut for GroupVec<V> {
-        fn deref_mut(&mut self) -> &mut Self::Target {
-            &mut self.flattened
+        /// Returns the fully-qualified, dot separated name of the leaf in each slot of the
+        /// flattened vec, which is reconstructed by walking the tree.
+        pub fn index_names(&self) -> Vec<String>
+        where
+            V: Named,
+        {
+            let mut names = vec![String::new(); self.flattened.len()];
+            for (mut path, index) in self.leaf_paths() {
+                path.push(self.flattened[index].name());
+                names[index] = path.join(".");
+            }
+            names
         }
     }
 
-    /// Trait used to convert between two types when creating the GroupVec from the GroupTree structs.
-    pub trait From<T> {
-        fn from(other: T) -> Self;
+    impl<T> GroupTree<T>
+    where
+        T: Named,
+    {
+        /// Returns the name of the group, or of the value if this is a leaf.
+        pub fn name(&self) -> &str {
+            match self {
+                GroupTree::Leaf(value) => value.name(),
+                GroupTree::Group { name, .. } => name,
+            }
+        }
     }
-}
-
-pub trait Analyzer<T> {
-    type Analysis;
-
-    /// Perform analysis on the given line.
-    fn analyze(&mut self, line: String);
 
-    /// Writes the results to the given writer.
-    fn format<W>(&mut self, writer: &mut W)
+    /// Finds a leaf either by its fully-qualified, dot separated path like `network.http.status`,
+    /// or by its name alone if it is unique across all groups.
+    pub fn find_leaf<'a, T>(trees: &'a [GroupTree<T>], path: &str) -> Option<&'a T>
     where
-        W: Write;
-}
+        T: Named,
+    {
+        fn by_path<'a, T>(trees: &'a [GroupTree<T>], path: &[&str]) -> Option<&'a T>
+        where
+            T: Named,
+        {
+            let (first, rest) = path.split_first()?;
+            trees.iter().find_map(|tree| match tree {
+                GroupTree::Leaf(value) if rest.is_empty() && value.name() == *first => Some(value),
+                GroupTree::Group { name, group } if name == first => by_path(group, rest),
+                _ => None,
+            })
+        }
 
-pub mod counter {
-    use super::group::*;
-    use super::Analyzer;
-    use super::*;
+        fn by_name<'a, T>(trees: &'a [GroupTree<T>], name: &str, found: &mut Vec<&'a T>)
+        where
+            T: Named,
+        {
+            for tree in trees {
+                match tree {
+                    GroupTree::Leaf(value) if value.name() == name => found.push(value),
+                    GroupTree::Leaf(_) => {}
+                    GroupTree::Group { group, .. } => by_name(group, name, found),
+                }
+            }
+        }
 
-    #[derive(Debug, Clone)]
-    struct Inner {
-        pattern: Pattern,
-        count: u64,
+        let segments: Vec<_> = path.split('.').collect();
+        by_path(trees, &segments).or_else(|| {
+            let mut found = Vec::new();
+            by_name(trees, path, &mut found);
+            match found[..] {
+                [value] => Some(value),
+                _ => None,
+            }
+        })
     }
 
-    impl group::From<Pattern> for Inner {
-        fn from(other: Pattern) -> Self {
-            Inner {
-                pattern: other,
-                count: 0_u64,
+    /// Adds the trees of `other` to `trees`. Groups with the same name on the same level are
+    /// merged recursively, while two leaves, or a leaf and a group, with the same name are an
+    /// error.
+    pub fn merge_trees<T>(
+        trees: &mut Vec<GroupTree<T>>,
+        other: Vec<GroupTree<T>>,
+    ) -> Result<(), String>
+    where
+        T: Named,
+    {
+        fn merge<T>(
+            prefix: &str,
+            trees: &mut Vec<GroupTree<T>>,
+            other: Vec<GroupTree<T>>,
+        ) -> Result<(), String>
+        where
+            T: Named,
+        {
+            for tree in other {
+                let path = if prefix.is_empty() {
+                    tree.name().to_string()
+                } else {
+                    format!("{}.{}", prefix, tree.name())
+                };
+
+                match trees
+                    .iter_mut()
+                    .find(|existing| existing.name() == tree.name())
+                {
+                    None => trees.push(tree),
+                    Some(GroupTree::Group { group, .. }) => match tree {
+                        GroupTree::Group { group: other, .. } => merge(&path, group, other)?,
+                        GroupTree::Leaf(_) => return Err(format!("Duplicate pattern '{}'", path)),
+                    },
+                    Some(GroupTree::Leaf(_)) => {
+                        return Err(format!("Duplicate pattern '{}'", path))
+                    }
+                }
             }
+            Ok(())
         }
-    }
 
-    #[derive(Debug)]
-    pub struct PatternCounter {
-        patterns: GroupVec<Inner>,
+        merge("", trees, other)
     }
 
-    impl PatternCounter {
-        pub fn new(tree: Vec<GroupTree<Pattern>>) -> Self {
-            PatternCounter {
-                patterns: GroupVec::from_tree::<Pattern>(tree),
-            }
+    /// Returns for each slot of the flattened vec of a [`GroupVec`] created from the trees with
+    /// the given sort mode the position of its leaf in a depth-first walk of the unsorted trees,
+    /// i.e. in the order of the patterns file.
+    pub fn source_positions<T>(trees: &[GroupTree<T>], sort: SortMode) -> Vec<usize>
+    where
+        T: Named,
+    {
+        /// A leaf which only remembers its position and what it is sorted by.
+        struct Positioned {
+            position: usize,
+            name: String,
+            source: String,
         }
-    }
 
-    impl Analyzer<Pattern> for PatternCounter {
-        type Analysis = u64;
+        impl Named for Positioned {
+            fn name(&self) -> &str {
+                &self.name
+            }
 
-        fn analyze(&mut self, line: String) {
-            for inner in &mut self.patterns[..] {
-                if inner.pattern.regex.is_match(&line) {
-                    inner.count += 1;
-                }
+            fn source(&self) -> &str {
+                &self.source
+            }
+        }
+
+        impl From<Positioned> for usize {
+            fn from(other: Positioned) -> Self {
+                other.position
             }
         }
 
-        fn format<W>(&mut self, _writer: &mut W)
+        fn position<T>(trees: &[GroupTree<T>], next: &mut usize) -> Vec<GroupTree<Positioned>>
         where
-            W: Write,
+            T: Named,
         {
-            // Find longest name and count
-            let mut longest_name = 0;
-            let mut longest_count = 0;
+            trees
+                .iter()
+                .map(|tree| match tree {
+                    GroupTree::Leaf(value) => {
+                        *next += 1;
+                        GroupTree::Leaf(Positioned {
+                            position: *next - 1,
+                            name: value.name().to_string(),
+                            source: value.source().to_string(),
+                        })
+                    }
+                    GroupTree::Group { name, group } => GroupTree::Group {
+                        name: name.clone(),
+                        group: position(group, next),
+                    },
+                })
+                .collect()
+        }
 
-            for inner in &self.patterns[..] {
-                let name_len = inner.pattern.name.chars().count();
-                if longest_name < name_len {
-                    longest_name = name_len;
-                }
+        GroupVec::<usize>::from_tree_with_options(position(trees, &mut 0), sort).flattened
+    }
 
-                let count_len = inner.count.to_formatted_string(&Locale::en).chars().count();
-                if longest_count < count_len {
-                    longest_count = count_len;
+    /// Returns every leaf together with its fully-qualified, dot separated path, in depth-first
+    /// order.
+    pub fn leaves<T>(trees: &[GroupTree<T>]) -> Vec<(String, &T)>
+    where
+        T: Named,
+    {
+        fn collect<'a, T>(prefix: &str, trees: &'a [GroupTree<T>], found: &mut Vec<(String, &'a T)>)
+        where
+            T: Named,
+        {
+            let path = |name: &str| {
+                if prefix.is_empty() {
+                    name.to_string()
+                } else {
+                    format!("{}.{}", prefix, name)
+                }
+            };
+            for tree in trees {
+                match tree {
+                    GroupTree::Leaf(value) => found.push((path(value.name()), value)),
+                    GroupTree::Group { name, group } => collect(&path(name), group, found),
                 }
             }
+        }
 
-            fn traverse(tree: &GroupTree<usize>, slice: &[Inner], indent: usize) {
-                match tree {
-                    GroupTree::Leaf(index) => {
-                        let Inner { pattern, count } = slice.get(*index).unwrap();
-                        println!(
-                            "{: <indent$}{:} {:}",
-                            "",
-                            format!("{}:", pattern.name),
-                            count.to_formatted_string(&Locale::en),
-                            indent = indent
-                        );
-                    }
-                    GroupTree::Group { name, group } => {
-                        println!("{}:", name);
-                        for inner_tree in group {
-                            traverse(inner_tree, slice, indent + 2);
+        let mut found = Vec::new();
+        collect("", trees, &mut found);
+        found
+    }
+
+    /// Keeps the leaves whose fully-qualified, dot separated path matches any of the `include`
+    /// globs, or all leaves if there are none, unless it matches any of the `exclude` globs.
+    /// Groups which are left empty are removed.
+    pub fn filter_tree<T>(
+        trees: Vec<GroupTree<T>>,
+        include: &[String],
+        exclude: &[String],
+    ) -> Vec<GroupTree<T>>
+    where
+        T: Named,
+    {
+        fn filter<T>(
+            prefix: &str,
+            trees: Vec<GroupTree<T>>,
+            include: &[String],
+            exclude: &[String],
+        ) -> Vec<GroupTree<T>>
+        where
+            T: Named,
+        {
+            trees
+                .into_iter()
+                .filter_map(|tree| {
+                    let path = if prefix.is_empty() {
+                        tree.name().to_string()
+                    } else {
+                        format!("{}.{}", prefix, tree.name())
+                    };
+
+                    match tree {
+                        GroupTree::Leaf(value) => {
+                            let included = include.is_empty()
+                                || include.iter().any(|glob| glob_matches(glob, &path));
+                            let excluded = exclude.iter().any(|glob| glob_matches(glob, &path));
+                            (included && !excluded).then_some(GroupTree::Leaf(value))
+                        }
+                        GroupTree::Group { name, group } => {
+                            let group = filter(&path, group, include, exclude);
+                            (!group.is_empty()).then_some(GroupTree::Group { name, group })
                         }
                     }
-                }
-            }
+                })
+                .collect()
+        }
 
-            for group_tree in &self.patterns.inner {
-                traverse(group_tree, &self.patterns[..], 0)
+        filter("", trees, include, exclude)
+    }
+
+    /// Returns whether the glob matches the whole dot separated path. A `*` matches any
+    /// characters within a single name, `**` any characters across names, and `?` a single
+    /// character other than `.`.
+    pub fn glob_matches(glob: &str, path: &str) -> bool {
+        fn matches_from(glob: &[char], path: &[char]) -> bool {
+            match glob {
+                [] => path.is_empty(),
+                ['*', '*', rest @ ..] => (0..=path.len()).any(|i| matches_from(rest, &path[i..])),
+                ['*', rest @ ..] => (0..=path.len())
+                    .take_while(|&i| i == 0 || path[i - 1] != '.')
+                    .any(|i| matches_from(rest, &path[i..])),
+                ['?', rest @ ..] => match path {
+                    [c, path @ ..] if *c != '.' => matches_from(rest, path),
+                    _ => false,
+                },
+                [g, rest @ ..] => match path {
+                    [c, path @ ..] if c == g => matches_from(rest, path),
+                    _ => false,
+                },
             }
         }
+
+        let glob: Vec<char> = glob.chars().collect();
+        let path: Vec<char> = path.chars().collect();
+        matches_from(&glob, &path)
     }
-}
 
-pub mod matcher {
-    use super::Analyzer;
-    use super::*;
-    use crate::GroupTree;
+    impl<V> Deref for GroupVec<V> {
+        type Target = [V];
 
-    #[derive(Debug, Clone)]
-    struct Inner {
-        pub pattern: Pattern,
-        pub matches: HashMap<String, u64>,
+        fn deref(&self) -> &Self::Target {
+            &self.flattened
+        }
     }
 
-    impl group::From<Pattern> for Inner {
-        fn from(other: Pattern) -> Self {
-            Inner {
-                pattern: other,
-                matches: HashMap::new(),
-            }
+    impl<V> DerefMut for GroupVec<V> {
+        fn deref_mut(&mut self) -> &mut Self::Target {
+            &mut self.flattened
         }
     }
 
-    #[derive(Debug, Clone)]
-    pub struct PatternMatcher {
-        patterns: GroupVec<Inner>,
-        top: usize,
+    /// Trait used to convert between two types when creating the GroupVec from the GroupTree structs.
+    pub trait From<T> {
+        fn from(other: T) -> Self;
     }
 
-    impl PatternMatcher {
-        pub fn new(tree: Vec<GroupTree<Pattern>>, top: usize) -> Self {
-            PatternMatcher {
-                patterns: GroupVec::from_tree::<Pattern>(tree),
-                top,
-            }
+    /// Trait for values stored in the leaves of a GroupTree that can be sorted by name.
+    pub trait Named {
+        fn name(&self) -> &str;
+
+        /// Regex of the value, which breaks ties between values with the same name.
+        fn source(&self) -> &str {
+            ""
         }
     }
+}
 
-    impl Analyzer<Pattern> for PatternMatcher {
-        type Analysis = HashMap<String, u64>;
+pub mod sample {
+    //! Contains helpers to retain a uniform random sample of a stream of values, and the analyzer
+    //! which samples the lines matching any pattern.
+    //!
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
 
-        fn analyze(&mut self, line: String) {
-            for inner in &mut self.patterns[..] {
-                for mat in inner.pattern.regex.find_iter(&line) {
-                    let entry = inner.matches.entry(mat.as_str().to_string()).or_insert(0);
-                    *entry += 1;
-                }
+    use super::group::*;
+    use super::*;
+
+    /// Retains a uniform random sample of at most `capacity` values, using reservoir sampling.
+    #[derive(Debug, Clone)]
+    pub struct Reservoir<T> {
+        capacity: usize,
+
+        /// Number of values offered so far.
+        seen: u64,
+        items: Vec<T>,
+    }
+
+    impl<T> Reservoir<T> {
+        pub fn new(capacity: usize) -> Self {
+            Reservoir {
+                capacity,
+                seen: 0,
+                items: Vec::with_capacity(capacity),
             }
         }
 
-        fn format<W>(&mut self, _writer: &mut W)
+        /// Offers a value to the reservoir. The value is only created if it is selected.
+        pub fn offer<F>(&mut self, rng: &mut StdRng, value: F)
         where
-            W: std::io::Write,
+            F: FnOnce() -> T,
         {
-            // Find longest name and count
-            let mut longest_name = 0;
-            let mut longest_match = 0;
-            let mut longest_count = 0;
-            for inner in &self.patterns[..] {
-                let name_len = inner.pattern.name.chars().count();
-                if longest_name < name_len {
-                    longest_name = name_len;
-                }
-
-                for (_mat, count) in inner.matches.iter().take(self.top) {
-                    let count_len = count.to_formatted_string(&Locale::en).chars().count();
-                    if longest_count < count_len {
-                        longest_count = count_len;
-                    }
-                    let match_len = inner.pattern.name.chars().count();
-                    if longest_match < match_len {
-                        longest_match = match_len;
-                    }
+            self.seen += 1;
+            if self.items.len() < self.capacity {
+                self.items.push(value());
+            } else {
+                let index = rng.gen_range(0..self.seen);
+                if index < self.capacity as u64 {
+                    self.items[index as usize] = value();
                 }
             }
+        }
 
-            for inner in &self.patterns[..] {
-                println!("{}", inner.pattern.name);
-                // TODO: Sort data before printing.
-                for (mat, count) in inner.matches.iter().take(self.top) {
-                    println!(
-                        "\t{:<match_len$} {} {:>count_len$}",
-                        format!("{}:", mat),
-                        count.to_formatted_string(&Locale::en),
-                        match_len = longest_match + 1,
-                        count_len = longest_count
-                    );
-                }
+        /// Returns the sampled values.
+        pub fn items(&self) -> &[T] {
+            &self.items
+        }
+
+        /// Merges the sample of another stream into this one, so that the result is a sample of
+        /// both streams. Each slot is filled from either sample with a probability proportional
+        /// to the number of values that stream has seen.
+        pub fn merge(&mut self, rng: &mut StdRng, other: Reservoir<T>) {
+            let mut ours = std::mem::take(&mut self.items);
+            let mut theirs = other.items;
+
+            while self.items.len() < self.capacity && !(ours.is_empty() && theirs.is_empty()) {
+                let take_ours = theirs.is_empty()
+                    || (!ours.is_empty() && rng.gen_range(0..self.seen + other.seen) < self.seen);
+                let items = if take_ours { &mut ours } else { &mut theirs };
+
+                let index = rng.gen_range(0..items.len());
+                self.items.push(items.swap_remove(index));
             }
+            self.seen += other.seen;
         }
     }
-}
 
-pub mod vocab {
-    use super::*;
+    /// Creates the random number generator used for sampling, which is reproducible if a seed is
+    /// given.
+    pub fn rng(seed: Option<u64>) -> StdRng {
+        match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+
+    /// Retains a uniform random sample of the whole lines which match at least one pattern.
+    #[derive(Debug)]
+    pub struct LineSampler {
+        patterns: GroupVec<Pattern>,
+        rng: StdRng,
+        reservoir: Reservoir<String>,
+    }
+
+    impl LineSampler {
+        /// Creates a sampler retaining `size` lines, which samples the same lines for the same
+        /// seed and input.
+        pub fn new(tree: Vec<GroupTree<Pattern>>, size: usize, seed: Option<u64>) -> Self {
+            LineSampler {
+                patterns: GroupVec::from_tree_with_options::<Pattern>(tree, SortMode::SourceOrder),
+                rng: rng(seed),
+                reservoir: Reservoir::new(size),
+            }
+        }
+
+        /// Returns the sampled lines.
+        pub fn lines(&self) -> &[String] {
+            self.reservoir.items()
+        }
+    }
+
+    impl Merge for LineSampler {
+        fn merge(&mut self, other: Self) {
+            self.reservoir.merge(&mut self.rng, other.reservoir);
+        }
+    }
+
+    impl Analyzer<Pattern> for LineSampler {
+        type Analysis = Vec<String>;
+
+        fn analyze(&mut self, line: String) {
+            let matched = self.patterns[..]
+                .iter()
+                .any(|pattern| !pattern.excludes(&line) && pattern.regex.is_match(&line));
+            if matched {
+                self.reservoir.offer(&mut self.rng, || line);
+            }
+        }
+
+        /// Writes each sampled line.
+        fn format<W>(&mut self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            for line in self.lines() {
+                writeln!(writer, "{}", line)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+impl group::Named for Pattern {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn source(&self) -> &str {
+        &self.source
+    }
+}
+
+/// Analyzers which can combine the results of analyzing separate parts of an input.
+pub trait Merge {
+    /// Adds the results of `other` to the results of this analyzer. Both must have been created
+    /// from the same patterns.
+    fn merge(&mut self, other: Self);
+}
+
+pub trait Analyzer<T> {
+    type Analysis;
+
+    /// Perform analysis on the given line.
+    fn analyze(&mut self, line: String);
+
+    /// Writes the results to the given writer.
+    fn format<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: Write;
+
+    /// Returns an error if the analysis cannot continue, which is checked after each line.
+    fn status(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Lets an analyzer be lent to an analysis, so that it is kept if reading one of several inputs
+/// fails.
+impl<T, A> Analyzer<T> for &mut A
+where
+    A: Analyzer<T>,
+{
+    type Analysis = A::Analysis;
+
+    fn analyze(&mut self, line: String) {
+        (**self).analyze(line)
+    }
+
+    fn format<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        (**self).format(writer)
+    }
+
+    fn status(&self) -> Result<(), String> {
+        (**self).status()
+    }
+}
+
+pub mod counter {
+    use std::mem;
+    use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+    use std::time::{Duration, Instant};
+
+    use console::style;
+    use rand::rngs::StdRng;
+    use serde::{Deserialize, Serialize};
+    use sha2::{Digest, Sha256};
+
+    use super::group::*;
+    use super::sample::{self, Reservoir};
+    use super::Analyzer;
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    struct Inner {
+        pattern: Pattern,
+        count: u64,
+    }
+
+    impl group::From<Pattern> for Inner {
+        fn from(other: Pattern) -> Self {
+            Inner {
+                pattern: other,
+                count: 0_u64,
+            }
+        }
+    }
+
+    impl Named for Inner {
+        fn name(&self) -> &str {
+            &self.pattern.name
+        }
+    }
+
+    /// Example lines retained for each pattern.
+    #[derive(Debug)]
+    struct Examples {
+        rng: StdRng,
+
+        /// One reservoir per pattern, in the same order as the flattened patterns.
+        reservoirs: Vec<Reservoir<String>>,
+    }
+
+    /// Count of each pattern broken down by the value of a named capture group.
+    #[derive(Debug)]
+    struct Breakdown {
+        capture: String,
+
+        /// Count of each value of the capture, in the same order as the flattened patterns, or
+        /// `None` for the patterns without the capture.
+        values: Vec<Option<HashMap<String, u64>>>,
+    }
+
+    impl Breakdown {
+        /// Counts the values the capture takes in the line, once per line or once per
+        /// occurrence depending on the mode.
+        fn record(&mut self, index: usize, pattern: &Pattern, mode: CountMode, line: &str) {
+            let values = match &mut self.values[index] {
+                Some(values) => values,
+                None => return,
+            };
+            let captures = pattern.regex.captures_iter(line);
+            let take = match mode {
+                CountMode::Lines => 1,
+                CountMode::Occurrences => usize::MAX,
+            };
+            for captures in captures.take(take) {
+                if let Some(value) = captures.name(&self.capture) {
+                    *values.entry(value.as_str().to_string()).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    /// What the counter counts for each pattern.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum CountMode {
+        /// The lines the pattern matches, however often it matches within each line.
+        #[default]
+        Lines,
+
+        /// The non-overlapping occurrences of the pattern, so a line can be counted several
+        /// times.
+        Occurrences,
+    }
+
+    impl FromStr for CountMode {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "lines" => Ok(CountMode::Lines),
+                "occurrences" => Ok(CountMode::Occurrences),
+                _ => Err(format!(
+                    "Invalid count mode '{}', expected lines or occurrences",
+                    s
+                )),
+            }
+        }
+    }
+
+    impl CountMode {
+        /// Returns the `# HELP` text of the metrics holding the count of each pattern.
+        fn help(&self) -> &'static str {
+            match self {
+                CountMode::Lines => "Number of lines matching each pattern.",
+                CountMode::Occurrences => "Number of occurrences of each pattern.",
+            }
+        }
+
+        /// Returns how often the pattern is counted for the line.
+        fn count(&self, pattern: &Pattern, line: &str) -> u64 {
+            if pattern.excludes(line) {
+                return 0;
+            }
+            match self {
+                CountMode::Lines => pattern.regex.is_match(line) as u64,
+                CountMode::Occurrences => pattern.regex.find_iter(line).count() as u64,
+            }
+        }
+    }
+
+    /// Sliding window over the last analyzed lines, which tracks the peak number of lines matching
+    /// each pattern within any window.
+    #[derive(Debug)]
+    struct Window {
+        size: usize,
+
+        /// Whether each pattern matched each line of the window, in the same order as the
+        /// flattened patterns. Indexed by the line number modulo the window size.
+        hits: Vec<Vec<bool>>,
+
+        /// Number of lines inside the current window matching each pattern.
+        current: Vec<u64>,
+
+        /// Highest number of lines inside any window matching each pattern.
+        peaks: Vec<u64>,
+
+        /// Position of the next line inside the ring buffer.
+        position: usize,
+    }
+
+    impl Window {
+        fn new(size: usize, patterns: usize) -> Self {
+            Window {
+                size,
+                hits: vec![vec![false; size]; patterns],
+                current: vec![0; patterns],
+                peaks: vec![0; patterns],
+                position: 0,
+            }
+        }
+
+        /// Records whether the pattern matched the current line, replacing the line which
+        /// dropped out of the window.
+        fn record(&mut self, index: usize, matches: bool) {
+            let hit = &mut self.hits[index][self.position];
+            if *hit {
+                self.current[index] -= 1;
+            }
+            *hit = matches;
+            if matches {
+                self.current[index] += 1;
+                self.peaks[index] = self.peaks[index].max(self.current[index]);
+            }
+        }
+
+        /// Moves the window to the next line, after all patterns were recorded.
+        fn advance(&mut self) {
+            self.position = (self.position + 1) % self.size;
+        }
+    }
+
+    /// Serializable counts of a [`PatternCounter`], which can be saved and summed up later.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct CountReport {
+        pub patterns: Vec<ReportTree>,
+
+        /// Number of analyzed lines.
+        #[serde(rename = "_total_lines", default)]
+        pub total_lines: u64,
+
+        /// Sum of the counts of all patterns.
+        #[serde(rename = "_total_matches", default)]
+        pub total_matches: u64,
+    }
+
+    /// A single node of a [`CountReport`].
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(untagged)]
+    pub enum ReportTree {
+        Leaf {
+            name: String,
+            count: u64,
+        },
+        Group {
+            name: String,
+            group: Vec<ReportTree>,
+        },
+    }
+
+    impl CountReport {
+        /// Adds the counts of the other report, which must contain exactly the same patterns in
+        /// the same order.
+        pub fn merge(&mut self, other: CountReport) -> Result<(), String> {
+            let ours = self.pattern_names();
+            let theirs = other.pattern_names();
+            if ours != theirs {
+                let missing: Vec<_> = ours.iter().filter(|name| !theirs.contains(name)).collect();
+                let added: Vec<_> = theirs.iter().filter(|name| !ours.contains(name)).collect();
+                return Err(if missing.is_empty() && added.is_empty() {
+                    format!("The patterns are in a different order: {:?}", theirs)
+                } else {
+                    format!(
+                        "The reports have different patterns, only in the first: {:?}, only in \
+                        the second: {:?}",
+                        missing, added
+                    )
+                });
+            }
+
+            fn add(ours: &mut [ReportTree], theirs: Vec<ReportTree>) {
+                for (ours, theirs) in ours.iter_mut().zip(theirs) {
+                    match (ours, theirs) {
+                        (ReportTree::Leaf { count, .. }, ReportTree::Leaf { count: other, .. }) => {
+                            *count += other
+                        }
+                        (
+                            ReportTree::Group { group, .. },
+                            ReportTree::Group { group: other, .. },
+                        ) => add(group, other),
+                        _ => unreachable!("The structure was validated before"),
+                    }
+                }
+            }
+
+            add(&mut self.patterns, other.patterns);
+            self.total_lines += other.total_lines;
+            self.total_matches += other.total_matches;
+            Ok(())
+        }
+
+        /// Returns the fully-qualified, dot-separated names of all groups and patterns in
+        /// depth-first order. Group names end with a dot, so a group never compares equal to a
+        /// pattern of the same name.
+        fn pattern_names(&self) -> Vec<String> {
+            fn collect(prefix: &str, trees: &[ReportTree], names: &mut Vec<String>) {
+                for tree in trees {
+                    let (name, group) = match tree {
+                        ReportTree::Leaf { name, .. } => (name, None),
+                        ReportTree::Group { name, group } => (name, Some(group)),
+                    };
+                    let path = if prefix.is_empty() {
+                        name.clone()
+                    } else {
+                        format!("{}.{}", prefix, name)
+                    };
+                    match group {
+                        Some(group) => {
+                            names.push(format!("{}.", path));
+                            collect(&path, group, names);
+                        }
+                        None => names.push(path),
+                    }
+                }
+            }
+
+            let mut names = Vec::new();
+            collect("", &self.patterns, &mut names);
+            names
+        }
+
+        /// Writes the counts as an indented tree, like [`PatternCounter`] does.
+        pub fn format<W>(
+            &self,
+            writer: &mut W,
+            indent: usize,
+            numbers: NumberFormat,
+        ) -> io::Result<()>
+        where
+            W: Write,
+        {
+            fn format_tree<W>(
+                writer: &mut W,
+                tree: &ReportTree,
+                level: usize,
+                indent: usize,
+                numbers: NumberFormat,
+            ) -> io::Result<()>
+            where
+                W: Write,
+            {
+                match tree {
+                    ReportTree::Leaf { name, count } => writeln!(
+                        writer,
+                        "{: <level$}{}: {}",
+                        "",
+                        name,
+                        numbers.format(*count),
+                        level = level
+                    ),
+                    ReportTree::Group { name, group } => {
+                        writeln!(writer, "{: <level$}{}:", "", name, level = level)?;
+                        for tree in group {
+                            format_tree(writer, tree, level + indent, indent, numbers)?;
+                        }
+                        Ok(())
+                    }
+                }
+            }
+
+            for tree in &self.patterns {
+                format_tree(writer, tree, 0, indent, numbers)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct PatternCounter {
+        patterns: GroupVec<Inner>,
+        examples: Option<Examples>,
+
+        /// A single line retained for each pattern, shown dimmed below the count.
+        sample_lines: Option<Examples>,
+
+        /// Number of spaces each nested group is indented by.
+        indent: usize,
+
+        /// Separator written between a pattern's name and its count.
+        separator: String,
+
+        /// Count each count is divided by, written after the count.
+        baseline: Option<u64>,
+
+        /// Time spent evaluating each pattern, in the same order as the flattened patterns. Only
+        /// measured if set, since reading the clock for every pattern and line is not free.
+        profile: Option<Vec<Duration>>,
+
+        /// Number of analyzed lines.
+        lines: u64,
+
+        /// Whether each count per million analyzed lines is written after the count.
+        per_million: bool,
+
+        /// Whether the regex of each pattern, as written in the patterns file, is written after
+        /// its count.
+        show_regex: bool,
+
+        /// Peak number of matching lines of each pattern within a sliding window of lines,
+        /// written after the count. Only tracked if set.
+        window: Option<Window>,
+
+        /// Whether lines or occurrences are counted.
+        mode: CountMode,
+
+        /// Minimum count of the patterns which are written, groups without any such pattern are
+        /// left out as well.
+        at_least: u64,
+
+        /// Percentile of the counts of the matching patterns, at or below which a pattern is
+        /// marked as rare.
+        rare: Option<f64>,
+
+        /// Number of group levels which are written, deeper groups are collapsed into a single
+        /// line with the sum of their counts.
+        max_depth: Option<usize>,
+
+        /// Whether each pattern matched the previous line, in the same order as the flattened
+        /// patterns. Only tracked if runs of consecutive matching lines are counted once.
+        previous: Option<Vec<bool>>,
+
+        /// Count of each pattern per value of a named capture group, written below the count.
+        group_by: Option<Breakdown>,
+
+        /// Formats the counts.
+        numbers: NumberFormat,
+    }
+
+    impl PatternCounter {
+        pub fn new(tree: Vec<GroupTree<Pattern>>, sort: SortMode) -> Self {
+            PatternCounter {
+                patterns: GroupVec::from_tree_with_options::<Pattern>(tree, sort),
+                examples: None,
+                sample_lines: None,
+                indent: 2,
+                separator: String::from(" "),
+                baseline: None,
+                profile: None,
+                lines: 0,
+                per_million: false,
+                show_regex: false,
+                window: None,
+                mode: CountMode::default(),
+                at_least: 0,
+                rare: None,
+                max_depth: None,
+                previous: None,
+                group_by: None,
+                numbers: NumberFormat::default(),
+            }
+        }
+
+        /// Creates a counter for already compiled regexes, each named by the given name, without
+        /// any groups. Counts and formats exactly like a patterns file which lists the same names
+        /// and regexes at its top level.
+        pub fn from_regexes(regexes: Vec<(String, Regex)>) -> Self {
+            let tree = regexes
+                .into_iter()
+                .map(|(name, regex)| {
+                    GroupTree::Leaf(Pattern::new(name, regex.as_str().to_string(), regex))
+                })
+                .collect();
+            Self::new(tree, SortMode::default())
+        }
+
+        /// Returns the number of analyzed lines.
+        pub fn total_lines(&self) -> u64 {
+            self.lines
+        }
+
+        /// Returns the sum of the counts of all patterns, so a line matching several patterns is
+        /// counted once for each of them.
+        pub fn total_matches(&self) -> u64 {
+            self.patterns[..].iter().map(|inner| inner.count).sum()
+        }
+
+        /// Writes each count divided by the given count after the count. Zero counts are
+        /// ignored.
+        pub fn relative_to(&mut self, baseline: u64) {
+            self.baseline = Some(baseline).filter(|baseline| *baseline > 0);
+        }
+
+        /// Writes each count scaled to a million analyzed lines after the count, so that the counts
+        /// of files with different lengths can be compared.
+        pub fn per_million(&mut self, per_million: bool) {
+            self.per_million = per_million;
+        }
+
+        /// Writes the regex of each pattern, as written in the patterns file, after its count.
+        pub fn show_regex(&mut self, show_regex: bool) {
+            self.show_regex = show_regex;
+        }
+
+        /// Writes only the patterns whose count is at least the given count, leaving out groups
+        /// without any such pattern. Only affects the written counts.
+        pub fn at_least(&mut self, at_least: u64) {
+            self.at_least = at_least;
+        }
+
+        /// Marks the matching patterns whose count is at or below the given percentile, between 0
+        /// and 100, of the counts of all matching patterns as rare. If the sampled lines are
+        /// shown, they are only shown for the rare patterns.
+        pub fn rare(&mut self, percentile: f64) {
+            self.rare = Some(percentile);
+        }
+
+        /// Returns the count at the percentile set by [`PatternCounter::rare`], using the
+        /// nearest-rank method over the counts of the patterns which matched at least once, or
+        /// `None` if it is not set or no pattern matched.
+        pub fn rare_threshold(&self) -> Option<u64> {
+            let percentile = self.rare?;
+            let mut counts: Vec<_> = self.patterns[..]
+                .iter()
+                .map(|inner| inner.count)
+                .filter(|count| *count > 0)
+                .collect();
+            if counts.is_empty() {
+                return None;
+            }
+            counts.sort_unstable();
+
+            let rank = (percentile / 100.0 * counts.len() as f64).ceil() as usize;
+            Some(counts[rank.clamp(1, counts.len()) - 1])
+        }
+
+        /// Collapses the groups nested inside `max_depth` levels of groups into a single line with
+        /// the sum of the counts of all patterns inside them, so that zero collapses even the
+        /// top-level groups. Only affects the written counts.
+        pub fn max_depth(&mut self, max_depth: usize) {
+            self.max_depth = Some(max_depth);
+        }
+
+        /// Returns whether the subtree contains any pattern whose count is at least the minimum
+        /// count, so that it is written.
+        fn shows(&self, tree: &GroupTree<usize>) -> bool {
+            match tree {
+                GroupTree::Leaf(index) => self.patterns[*index].count >= self.at_least,
+                GroupTree::Group { group, .. } => group.iter().any(|tree| self.shows(tree)),
+            }
+        }
+
+        /// Sets whether the lines matching each pattern or its occurrences are counted. Must be
+        /// called before analyzing any lines.
+        pub fn count_mode(&mut self, mode: CountMode) {
+            self.mode = mode;
+        }
+
+        /// Counts each run of consecutive lines matching the same pattern as a single event, e.g.
+        /// the lines of a stack trace, by only counting the first line of the run. Must be called
+        /// before analyzing any lines.
+        ///
+        /// When analyzing chunks in parallel, runs spanning two chunks are counted twice.
+        pub fn collapse_consecutive(&mut self, collapse: bool) {
+            self.previous = collapse.then(|| vec![false; self.patterns.len()]);
+        }
+
+        /// Tracks the peak number of lines matching each pattern within any window of `size`
+        /// consecutive lines, which is written after the count. A size of zero disables the
+        /// window.
+        ///
+        /// When analyzing chunks in parallel, windows spanning two chunks are not considered.
+        pub fn window(&mut self, size: usize) {
+            self.window = (size > 0).then(|| Window::new(size, self.patterns.len()));
+        }
+
+        /// Breaks down the count of each pattern which declares the named capture group by the
+        /// values the capture takes, which are written below the count. Patterns without the
+        /// capture keep a plain count, and matches in which the capture does not participate are
+        /// left out of the breakdown. Must be called before analyzing any lines.
+        pub fn group_by(&mut self, capture: &str) {
+            let values = self.patterns[..]
+                .iter()
+                .map(|inner| {
+                    inner
+                        .pattern
+                        .regex
+                        .capture_names()
+                        .any(|name| name == Some(capture))
+                        .then(HashMap::new)
+                })
+                .collect();
+            self.group_by = Some(Breakdown {
+                capture: capture.to_string(),
+                values,
+            });
+        }
+
+        /// Returns the count scaled to a million analyzed lines, or zero if no line was analyzed.
+        pub fn count_per_million(&self, count: u64) -> f64 {
+            if self.lines > 0 {
+                count as f64 * 1_000_000.0 / self.lines as f64
+            } else {
+                0.0
+            }
+        }
+
+        /// Returns the count of each pattern together with its fully-qualified, dot separated
+        /// path, in the order the patterns are written.
+        pub fn counts(&self) -> Vec<(String, u64)> {
+            self.patterns
+                .leaf_paths()
+                .into_iter()
+                .map(|(mut path, index)| {
+                    let Inner { pattern, count } = &self.patterns[index];
+                    path.push(&pattern.name);
+                    (path.join("."), *count)
+                })
+                .collect()
+        }
+
+        /// Returns the count of the pattern with the given fully-qualified path or, if unique,
+        /// the given name.
+        pub fn count(&self, name: &str) -> Option<u64> {
+            let counts = self.counts();
+            if let Some((_, count)) = counts.iter().find(|(path, _)| path == name) {
+                return Some(*count);
+            }
+
+            let mut found = counts
+                .iter()
+                .filter(|(path, _)| path.rsplit('.').next() == Some(name));
+            match (found.next(), found.next()) {
+                (Some((_, count)), None) => Some(*count),
+                _ => None,
+            }
+        }
+
+        /// Returns the hex encoded SHA-256 hash of the fully-qualified names of the patterns and
+        /// their counts, sorted by name, so that it only changes if a count does, regardless of
+        /// the order the patterns are written in.
+        pub fn result_hash(&self) -> String {
+            let mut counts = self.counts();
+            counts.sort();
+
+            let mut hasher = Sha256::new();
+            for (path, count) in counts {
+                hasher.update(format!("{}\t{}\n", path, count));
+            }
+            hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect()
+        }
+
+        /// Returns the fully-qualified name of the pattern in each slot of the flattened patterns.
+        pub fn index_names(&self) -> Vec<String> {
+            self.patterns.index_names()
+        }
+
+        /// Returns the number of patterns.
+        pub fn len(&self) -> usize {
+            self.patterns.len()
+        }
+
+        /// Returns whether the counter has no patterns.
+        pub fn is_empty(&self) -> bool {
+            self.patterns.is_empty()
+        }
+
+        /// Sets the number of spaces each nested group is indented by.
+        pub fn indent(&mut self, indent: usize) {
+            self.indent = indent;
+        }
+
+        /// Sets the separator written between a pattern's name and its count.
+        pub fn separator(&mut self, separator: String) {
+            self.separator = separator;
+        }
+
+        /// Sets how the counts are formatted.
+        pub fn number_format(&mut self, numbers: NumberFormat) {
+            self.numbers = numbers;
+        }
+
+        /// Retains a uniform random sample of `size` matching lines for each pattern, which are
+        /// shown below the counts. Must be called before analyzing any lines.
+        pub fn sample_examples(&mut self, size: usize, seed: Option<u64>) {
+            self.examples = Some(Examples {
+                rng: sample::rng(seed),
+                reservoirs: vec![Reservoir::new(size); self.patterns.len()],
+            });
+        }
+
+        /// Retains a single randomly sampled matching line for each pattern, which is shown dimmed
+        /// below the count. Must be called before analyzing any lines.
+        pub fn sample_lines(&mut self, seed: Option<u64>) {
+            self.sample_lines = Some(Examples {
+                rng: sample::rng(seed),
+                reservoirs: vec![Reservoir::new(1); self.patterns.len()],
+            });
+        }
+
+        /// Measures how long each pattern takes to evaluate. Must be called before analyzing any
+        /// lines.
+        pub fn profile(&mut self) {
+            self.profile = Some(vec![Duration::ZERO; self.patterns.len()]);
+        }
+
+        /// Returns the time spent evaluating each pattern by its fully-qualified name, sorted in
+        /// descending order, or `None` if the patterns were not profiled.
+        pub fn timings(&self) -> Option<Vec<(String, Duration)>> {
+            let profile = self.profile.as_ref()?;
+            let mut timings: Vec<_> = self
+                .patterns
+                .index_names()
+                .into_iter()
+                .zip(profile)
+                .map(|(name, elapsed)| (name, *elapsed))
+                .collect();
+            timings.sort_by(|(a_name, a_elapsed), (b_name, b_elapsed)| {
+                b_elapsed.cmp(a_elapsed).then_with(|| a_name.cmp(b_name))
+            });
+            Some(timings)
+        }
+
+        /// Writes the time spent evaluating each pattern to the given writer, slowest first.
+        pub fn format_profile<W>(&self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            let timings = self.timings().unwrap_or_default();
+
+            let longest_name = timings
+                .iter()
+                .map(|(name, _)| name.chars().count())
+                .max()
+                .unwrap_or(0);
+
+            writeln!(writer, "Profile:")?;
+            for (name, elapsed) in timings {
+                writeln!(
+                    writer,
+                    "{: <indent$}{:<name_len$} {:.3}ms",
+                    "",
+                    format!("{}:", name),
+                    elapsed.as_secs_f64() * 1000.0,
+                    indent = self.indent,
+                    name_len = longest_name + 1
+                )?;
+            }
+            Ok(())
+        }
+
+        /// Returns the total score, which is the sum of each pattern's count multiplied by its
+        /// weight, together with the contribution of each pattern by its fully-qualified name,
+        /// sorted in descending order.
+        pub fn score(&self) -> (f64, Vec<(String, f64)>) {
+            let mut contributions: Vec<_> = self
+                .patterns
+                .index_names()
+                .into_iter()
+                .zip(&self.patterns[..])
+                .map(|(name, inner)| (name, inner.count as f64 * inner.pattern.weight))
+                .collect();
+            contributions.sort_by(|(a_name, a_score), (b_name, b_score)| {
+                b_score.total_cmp(a_score).then_with(|| a_name.cmp(b_name))
+            });
+
+            let total = contributions.iter().map(|(_, score)| score).sum();
+            (total, contributions)
+        }
+
+        /// Writes the total score followed by the contribution of each pattern to the given
+        /// writer.
+        pub fn format_score<W>(&self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            let (total, contributions) = self.score();
+
+            let longest_name = contributions
+                .iter()
+                .map(|(name, _)| name.chars().count())
+                .max()
+                .unwrap_or(0);
+
+            writeln!(writer, "Score: {:.2}", total)?;
+            for (name, score) in contributions {
+                writeln!(
+                    writer,
+                    "  {:<name_len$} {:.2}",
+                    format!("{}:", name),
+                    score,
+                    name_len = longest_name + 1
+                )?;
+            }
+            Ok(())
+        }
+
+        /// Returns the counts as a serializable report.
+        pub fn report(&self) -> CountReport {
+            fn report_tree(counter: &PatternCounter, tree: &GroupTree<usize>) -> ReportTree {
+                match tree {
+                    GroupTree::Leaf(index) => {
+                        let Inner { pattern, count } = &counter.patterns[*index];
+                        ReportTree::Leaf {
+                            name: pattern.name.clone(),
+                            count: *count,
+                        }
+                    }
+                    GroupTree::Group { name, group } => ReportTree::Group {
+                        name: name.clone(),
+                        group: group
+                            .iter()
+                            .map(|tree| report_tree(counter, tree))
+                            .collect(),
+                    },
+                }
+            }
+
+            CountReport {
+                patterns: self
+                    .patterns
+                    .inner
+                    .iter()
+                    .map(|tree| report_tree(self, tree))
+                    .collect(),
+                total_lines: self.lines,
+                total_matches: self.total_matches(),
+            }
+        }
+
+        /// Returns the sum of the counts of all patterns inside the subtree.
+        fn subtotal(&self, tree: &GroupTree<usize>) -> u64 {
+            match tree {
+                GroupTree::Leaf(index) => self.patterns[*index].count,
+                GroupTree::Group { group, .. } => {
+                    group.iter().map(|tree| self.subtotal(tree)).sum()
+                }
+            }
+        }
+
+        /// Returns the total count of each top-level group or pattern by its path, sorted in
+        /// descending order.
+        pub fn rollup(&self) -> Vec<(String, u64)> {
+            let names = self.patterns.index_names();
+            let mut totals: Vec<_> = self
+                .patterns
+                .inner
+                .iter()
+                .map(|tree| {
+                    let name = match tree {
+                        GroupTree::Leaf(index) => names[*index].clone(),
+                        GroupTree::Group { name, .. } => name.clone(),
+                    };
+                    (name, self.subtotal(tree))
+                })
+                .collect();
+            totals.sort_by(|(a_name, a_count), (b_name, b_count)| {
+                b_count.cmp(a_count).then_with(|| a_name.cmp(b_name))
+            });
+            totals
+        }
+
+        /// Writes the counts of the top-level group with the given name, or of the patterns
+        /// outside of any group if it is `None`, in the same way as [`Analyzer::format`].
+        pub fn format_group<W>(&self, writer: &mut W, group: Option<&str>) -> io::Result<()>
+        where
+            W: Write,
+        {
+            let rare_threshold = self.rare_threshold();
+            for tree in &self.patterns.inner {
+                let selected = match tree {
+                    GroupTree::Leaf(_) => group.is_none(),
+                    GroupTree::Group { name, .. } => group == Some(name.as_str()),
+                };
+                if selected {
+                    self.format_tree(writer, tree, 0, rare_threshold)?;
+                }
+            }
+            Ok(())
+        }
+
+        /// Writes the total count of each top-level group or pattern to the given writer.
+        pub fn format_rollup<W>(&self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            let totals = self.rollup();
+
+            let longest_name = totals
+                .iter()
+                .map(|(name, _)| name.chars().count())
+                .max()
+                .unwrap_or(0);
+
+            writeln!(writer, "Rollup:")?;
+            for (name, count) in totals {
+                writeln!(
+                    writer,
+                    "{: <indent$}{:<name_len$} {}",
+                    "",
+                    format!("{}:", name),
+                    self.numbers.format(count),
+                    indent = self.indent,
+                    name_len = longest_name + 1
+                )?;
+            }
+            Ok(())
+        }
+
+        /// Writes the `# HELP` and `# TYPE` header of the metric written by
+        /// [`PatternCounter::format_prometheus`] for counters counting in the given mode.
+        pub fn format_prometheus_header<W>(writer: &mut W, mode: CountMode) -> io::Result<()>
+        where
+            W: Write,
+        {
+            writeln!(writer, "# HELP analyze_pattern_count {}", mode.help())?;
+            writeln!(writer, "# TYPE analyze_pattern_count gauge")
+        }
+
+        /// Writes the count of each pattern as a Prometheus sample, labeled with the pattern's
+        /// fully-qualified, dot-separated path and the name of the analyzed file. Label values may
+        /// hold any text, so they are only escaped.
+        pub fn format_prometheus<W>(&self, writer: &mut W, file: &str) -> io::Result<()>
+        where
+            W: Write,
+        {
+            for (mut path, index) in self.patterns.leaf_paths() {
+                let Inner { pattern, count } = &self.patterns[index];
+                path.push(&pattern.name);
+
+                writeln!(
+                    writer,
+                    "analyze_pattern_count{{pattern=\"{}\",file=\"{}\"}} {}",
+                    escape_label(&path.join(".")),
+                    escape_label(file),
+                    count
+                )?;
+            }
+            Ok(())
+        }
+
+        /// Writes the count of each pattern in the folded stack format consumed by flamegraph
+        /// tools, with the groups containing the pattern as the stack, e.g. `net;ip 12`.
+        /// Semicolons inside the names are replaced by underscores, and patterns which did not
+        /// match any line are left out.
+        pub fn format_folded<W>(&self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            for (mut path, index) in self.patterns.leaf_paths() {
+                let Inner { pattern, count } = &self.patterns[index];
+                if *count == 0 {
+                    continue;
+                }
+                path.push(&pattern.name);
+
+                let frames: Vec<_> = path.iter().map(|name| name.replace(';', "_")).collect();
+                writeln!(writer, "{} {}", frames.join(";"), count)?;
+            }
+            Ok(())
+        }
+
+        /// Writes the number of analyzed lines and the count of each pattern of each analyzed file
+        /// in the OpenMetrics text format, with every sample stamped with the given Unix
+        /// timestamp. The metric names are prefixed with `prefix` and an underscore, unless it is
+        /// empty.
+        ///
+        /// Each entry holds the name of the analyzed file, its counter, and its number of lines.
+        /// All counters are expected to count in the same mode.
+        pub fn format_openmetrics<W>(
+            writer: &mut W,
+            counters: &[(String, PatternCounter, u64)],
+            prefix: &str,
+            timestamp: f64,
+        ) -> io::Result<()>
+        where
+            W: Write,
+        {
+            let metric = |name: &str| {
+                if prefix.is_empty() {
+                    name.to_string()
+                } else {
+                    format!("{}_{}", sanitize_label(prefix), name)
+                }
+            };
+
+            let lines = metric("analyze_lines");
+            writeln!(writer, "# TYPE {} counter", lines)?;
+            writeln!(writer, "# HELP {} Number of analyzed lines.", lines)?;
+            for (file, _, count) in counters {
+                writeln!(
+                    writer,
+                    "{}_total{{file=\"{}\"}} {} {:.3}",
+                    lines,
+                    escape_label(file),
+                    count,
+                    timestamp
+                )?;
+            }
+
+            let matches = metric("analyze_pattern_lines");
+            writeln!(writer, "# TYPE {} counter", matches)?;
+            let mode = counters
+                .first()
+                .map(|(_, counter, _)| counter.mode)
+                .unwrap_or_default();
+            writeln!(writer, "# HELP {} {}", matches, mode.help())?;
+            for (file, counter, _) in counters {
+                for (mut path, index) in counter.patterns.leaf_paths() {
+                    let Inner { pattern, count } = &counter.patterns[index];
+                    path.push(&pattern.name);
+
+                    writeln!(
+                        writer,
+                        "{}_total{{pattern=\"{}\",file=\"{}\"}} {} {:.3}",
+                        matches,
+                        escape_label(&path.join(".")),
+                        escape_label(file),
+                        count,
+                        timestamp
+                    )?;
+                }
+            }
+            writeln!(writer, "# EOF")
+        }
+
+        /// Writes the counts of the given subtree to the writer, indenting each nested group. The
+        /// rare threshold is computed once by the caller, since it depends on all counts.
+        fn format_tree<W>(
+            &self,
+            writer: &mut W,
+            tree: &GroupTree<usize>,
+            depth: usize,
+            rare_threshold: Option<u64>,
+        ) -> io::Result<()>
+        where
+            W: Write,
+        {
+            if !self.shows(tree) {
+                return Ok(());
+            }
+
+            let indent = depth * self.indent;
+            match tree {
+                GroupTree::Leaf(index) => {
+                    let Inner { pattern, count } = &self.patterns[*index];
+                    write!(
+                        writer,
+                        "{: <indent$}{}:{}{}",
+                        "",
+                        pattern.name,
+                        self.separator,
+                        self.numbers.format(*count),
+                        indent = indent
+                    )?;
+                    if let Some(baseline) = self.baseline {
+                        write!(writer, " ({:.3})", *count as f64 / baseline as f64)?;
+                    }
+                    if self.per_million {
+                        write!(
+                            writer,
+                            " ({:.1} per million lines)",
+                            self.count_per_million(*count)
+                        )?;
+                    }
+                    if let Some(window) = &self.window {
+                        write!(
+                            writer,
+                            " (peak {}/{} lines)",
+                            self.numbers.format(window.peaks[*index]),
+                            self.numbers.format(window.size as u64)
+                        )?;
+                    }
+                    if self.show_regex {
+                        write!(writer, "  /{}/", pattern.source)?;
+                    }
+                    let rare =
+                        rare_threshold.is_some_and(|threshold| *count > 0 && *count <= threshold);
+                    if rare {
+                        write!(writer, " {}", style("(rare)").yellow().bold())?;
+                    }
+                    writeln!(writer)?;
+
+                    if let Some(values) = self
+                        .group_by
+                        .as_ref()
+                        .and_then(|group_by| group_by.values[*index].as_ref())
+                    {
+                        let mut values: Vec<_> = values.iter().collect();
+                        values.sort_by(|(a_value, a_count), (b_value, b_count)| {
+                            b_count.cmp(a_count).then_with(|| a_value.cmp(b_value))
+                        });
+                        for (value, count) in values {
+                            writeln!(
+                                writer,
+                                "{: <indent$}{}:{}{}",
+                                "",
+                                value,
+                                self.separator,
+                                self.numbers.format(*count),
+                                indent = indent + self.indent
+                            )?;
+                        }
+                    }
+
+                    if let Some(examples) = &self.examples {
+                        for line in examples.reservoirs[*index].items() {
+                            writeln!(
+                                writer,
+                                "{: <indent$}> {}",
+                                "",
+                                line,
+                                indent = indent + self.indent
+                            )?;
+                        }
+                    }
+
+                    if let Some(sample_lines) = self
+                        .sample_lines
+                        .as_ref()
+                        .filter(|_| self.rare.is_none() || rare)
+                    {
+                        for line in sample_lines.reservoirs[*index].items() {
+                            writeln!(
+                                writer,
+                                "{: <indent$}{}",
+                                "",
+                                style(line).dim(),
+                                indent = indent + self.indent
+                            )?;
+                        }
+                    }
+                    Ok(())
+                }
+                GroupTree::Group { name, .. }
+                    if self.max_depth.is_some_and(|max_depth| depth >= max_depth) =>
+                {
+                    writeln!(
+                        writer,
+                        "{: <indent$}{} (…):{}{}",
+                        "",
+                        name,
+                        self.separator,
+                        self.numbers.format(self.subtotal(tree)),
+                        indent = indent
+                    )
+                }
+                GroupTree::Group { name, group } => {
+                    writeln!(writer, "{: <indent$}{}:", "", name, indent = indent)?;
+                    for inner_tree in group {
+                        self.format_tree(writer, inner_tree, depth + 1, rare_threshold)?;
+                    }
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    impl Merge for PatternCounter {
+        fn merge(&mut self, other: Self) {
+            for (inner, other) in self.patterns[..].iter_mut().zip(other.patterns.flattened) {
+                inner.count += other.count;
+            }
+            self.lines += other.lines;
+            if let (Some(ours), Some(theirs)) = (&mut self.window, other.window) {
+                for (peak, other) in ours.peaks.iter_mut().zip(theirs.peaks) {
+                    *peak = (*peak).max(other);
+                }
+            }
+            if let (Some(ours), Some(theirs)) = (&mut self.profile, other.profile) {
+                for (elapsed, other) in ours.iter_mut().zip(theirs) {
+                    *elapsed += other;
+                }
+            }
+            if let (Some(ours), Some(theirs)) = (&mut self.group_by, other.group_by) {
+                for (values, other) in ours.values.iter_mut().zip(theirs.values) {
+                    if let (Some(values), Some(other)) = (values, other) {
+                        for (value, count) in other {
+                            *values.entry(value).or_default() += count;
+                        }
+                    }
+                }
+            }
+
+            for (ours, theirs) in [
+                (&mut self.examples, other.examples),
+                (&mut self.sample_lines, other.sample_lines),
+            ] {
+                if let (Some(ours), Some(theirs)) = (ours, theirs) {
+                    for (reservoir, other) in ours.reservoirs.iter_mut().zip(theirs.reservoirs) {
+                        reservoir.merge(&mut ours.rng, other);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Replaces all characters which are not ASCII letters, digits, or underscores, so that the
+    /// label can be used in a metric name.
+    fn sanitize_label(label: &str) -> String {
+        label
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect()
+    }
+
+    /// Escapes a Prometheus label value.
+    fn escape_label(value: &str) -> String {
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    }
+
+    impl Analyzer<Pattern> for PatternCounter {
+        type Analysis = u64;
+
+        fn analyze(&mut self, line: String) {
+            self.lines += 1;
+            for (index, inner) in self.patterns[..].iter_mut().enumerate() {
+                let count = match &mut self.profile {
+                    Some(profile) => {
+                        let start = Instant::now();
+                        let count = self.mode.count(&inner.pattern, &line);
+                        profile[index] += start.elapsed();
+                        count
+                    }
+                    None => self.mode.count(&inner.pattern, &line),
+                };
+                if let Some(window) = &mut self.window {
+                    window.record(index, count > 0);
+                }
+                if let Some(previous) = &mut self.previous {
+                    let continues_run = mem::replace(&mut previous[index], count > 0);
+                    if continues_run {
+                        continue;
+                    }
+                }
+
+                if count > 0 {
+                    inner.count += count;
+
+                    if let Some(group_by) = &mut self.group_by {
+                        group_by.record(index, &inner.pattern, self.mode, &line);
+                    }
+                    if let Some(Examples { rng, reservoirs }) = &mut self.examples {
+                        reservoirs[index].offer(rng, || line.clone());
+                    }
+                    if let Some(Examples { rng, reservoirs }) = &mut self.sample_lines {
+                        reservoirs[index].offer(rng, || line.clone());
+                    }
+                }
+            }
+            if let Some(window) = &mut self.window {
+                window.advance();
+            }
+        }
+
+        fn format<W>(&mut self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            let rare_threshold = self.rare_threshold();
+            for group_tree in &self.patterns.inner {
+                self.format_tree(writer, group_tree, 0, rare_threshold)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    struct SyncInner {
+        pattern: Pattern,
+        count: AtomicU64,
+    }
+
+    impl group::From<Pattern> for SyncInner {
+        fn from(other: Pattern) -> Self {
+            SyncInner {
+                pattern: other,
+                count: AtomicU64::new(0),
+            }
+        }
+    }
+
+    impl Named for SyncInner {
+        fn name(&self) -> &str {
+            &self.pattern.name
+        }
+    }
+
+    /// Counts the lines matching each pattern like [`PatternCounter`], but can be shared between
+    /// threads which analyze lines concurrently, since each count is a separate atomic counter.
+    ///
+    /// The counts can be read at any time with [`SyncPatternCounter::snapshot`]. Lines which are
+    /// analyzed while the snapshot is taken may be included for some patterns but not for others.
+    #[derive(Debug)]
+    pub struct SyncPatternCounter {
+        patterns: GroupVec<SyncInner>,
+
+        /// Number of analyzed lines.
+        lines: AtomicU64,
+
+        /// Whether lines or occurrences are counted.
+        mode: CountMode,
+    }
+
+    impl SyncPatternCounter {
+        pub fn new(tree: Vec<GroupTree<Pattern>>, sort: SortMode) -> Self {
+            SyncPatternCounter {
+                patterns: GroupVec::from_tree_with_options::<Pattern>(tree, sort),
+                lines: AtomicU64::new(0),
+                mode: CountMode::default(),
+            }
+        }
+
+        /// Sets whether the lines matching each pattern or its occurrences are counted. Must be
+        /// called before analyzing any lines.
+        pub fn count_mode(&mut self, mode: CountMode) {
+            self.mode = mode;
+        }
+
+        /// Counts the patterns matching the line. Can be called from several threads at once.
+        pub fn analyze(&self, line: &str) {
+            self.lines.fetch_add(1, AtomicOrdering::Relaxed);
+            for inner in &self.patterns[..] {
+                let count = self.mode.count(&inner.pattern, line);
+                if count > 0 {
+                    inner.count.fetch_add(count, AtomicOrdering::Relaxed);
+                }
+            }
+        }
+
+        /// Returns the number of analyzed lines.
+        pub fn total_lines(&self) -> u64 {
+            self.lines.load(AtomicOrdering::Relaxed)
+        }
+
+        /// Returns the sum of the counts of all patterns, so a line matching several patterns is
+        /// counted once for each of them.
+        pub fn total_matches(&self) -> u64 {
+            self.patterns[..]
+                .iter()
+                .map(|inner| inner.count.load(AtomicOrdering::Relaxed))
+                .sum()
+        }
+
+        /// Returns the current counts, in the same form as [`PatternCounter::report`].
+        pub fn snapshot(&self) -> CountReport {
+            fn report_tree(counter: &SyncPatternCounter, tree: &GroupTree<usize>) -> ReportTree {
+                match tree {
+                    GroupTree::Leaf(index) => {
+                        let SyncInner { pattern, count } = &counter.patterns[*index];
+                        ReportTree::Leaf {
+                            name: pattern.name.clone(),
+                            count: count.load(AtomicOrdering::Relaxed),
+                        }
+                    }
+                    GroupTree::Group { name, group } => ReportTree::Group {
+                        name: name.clone(),
+                        group: group
+                            .iter()
+                            .map(|tree| report_tree(counter, tree))
+                            .collect(),
+                    },
+                }
+            }
+
+            let patterns: Vec<_> = self
+                .patterns
+                .inner
+                .iter()
+                .map(|tree| report_tree(self, tree))
+                .collect();
+
+            // Sum the snapshot rather than the atomics, so that the total matches the counts.
+            fn sum(trees: &[ReportTree]) -> u64 {
+                trees
+                    .iter()
+                    .map(|tree| match tree {
+                        ReportTree::Leaf { count, .. } => *count,
+                        ReportTree::Group { group, .. } => sum(group),
+                    })
+                    .sum()
+            }
+
+            CountReport {
+                total_matches: sum(&patterns),
+                patterns,
+                total_lines: self.total_lines(),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn leaf(name: &str, count: u64) -> ReportTree {
+            ReportTree::Leaf {
+                name: name.to_string(),
+                count,
+            }
+        }
+
+        fn report(patterns: Vec<ReportTree>) -> CountReport {
+            CountReport {
+                patterns,
+                total_lines: 1,
+                total_matches: 1,
+            }
+        }
+
+        #[test]
+        fn merge_adds_counts_of_the_same_tree() {
+            let tree = |count| {
+                vec![
+                    leaf("a", count),
+                    ReportTree::Group {
+                        name: "g".to_string(),
+                        group: vec![leaf("b", count)],
+                    },
+                ]
+            };
+            let mut ours = report(tree(1));
+            ours.merge(report(tree(2))).unwrap();
+            assert_eq!(ours.patterns, tree(3));
+        }
+
+        fn pattern(name: &str, source: &str) -> GroupTree<Pattern> {
+            GroupTree::Leaf(Pattern::new(
+                name.to_string(),
+                source.to_string(),
+                Regex::new(source).unwrap(),
+            ))
+        }
+
+        fn formatted(tree: Vec<GroupTree<Pattern>>, lines: &[&str]) -> String {
+            let mut counter = PatternCounter::new(tree, SortMode::Alphabetical);
+            for line in lines {
+                counter.analyze(line.to_string());
+            }
+            let mut output = Vec::new();
+            counter.format(&mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        }
+
+        #[test]
+        fn format_orders_duplicate_names_by_source() {
+            let tree = |sources: [&str; 3]| {
+                vec![
+                    pattern("zeta", "z"),
+                    GroupTree::Group {
+                        name: "group".to_string(),
+                        group: sources
+                            .iter()
+                            .map(|source| pattern("dup", source))
+                            .collect(),
+                    },
+                    pattern("dup", "d"),
+                ]
+            };
+            // Each source in the group matches a different number of lines.
+            let lines = ["a", "b", "b", "c", "c", "c", "zed", "dd"];
+
+            let first = formatted(tree(["a+", "b+", "c+"]), &lines);
+            let again = formatted(tree(["a+", "b+", "c+"]), &lines);
+            assert_eq!(first.as_bytes(), again.as_bytes());
+
+            // The order in the file does not matter, only the names and then the sources.
+            for sources in [["c+", "a+", "b+"], ["b+", "c+", "a+"]] {
+                let reordered = formatted(tree(sources), &lines);
+                assert_eq!(first.as_bytes(), reordered.as_bytes());
+            }
+
+            let counts: Vec<&str> = first
+                .lines()
+                .filter(|line| line.starts_with(' ') && line.trim_start().starts_with("dup:"))
+                .map(|line| line.rsplit(':').next().unwrap().trim())
+                .collect();
+            assert_eq!(counts, ["1", "2", "3"]);
+        }
+
+        #[test]
+        fn merge_rejects_a_group_in_place_of_a_pattern() {
+            let mut ours = report(vec![leaf("a", 1)]);
+            let theirs = report(vec![ReportTree::Group {
+                name: "a".to_string(),
+                group: Vec::new(),
+            }]);
+            assert!(ours.merge(theirs).is_err());
+            assert_eq!(ours.patterns, vec![leaf("a", 1)]);
+        }
+
+        #[test]
+        fn format_prometheus_labels_patterns_by_their_path() {
+            let leaf = |name: &str| {
+                GroupTree::Leaf(Pattern::new(
+                    name.to_string(),
+                    "x".to_string(),
+                    Regex::new("x").unwrap(),
+                ))
+            };
+            let group = |name: &str, group| GroupTree::Group {
+                name: name.to_string(),
+                group,
+            };
+            let tree = vec![
+                group("a", vec![leaf("b_c")]),
+                group("a_b", vec![leaf("c")]),
+                leaf("say \"x\""),
+            ];
+            let mut counter = PatternCounter::new(tree, SortMode::Alphabetical);
+            counter.analyze("x".to_string());
+
+            let mut output = Vec::new();
+            counter.format_prometheus(&mut output, "f").unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("pattern=\"a.b_c\""));
+            assert!(output.contains("pattern=\"a_b.c\""));
+            assert!(output.contains("pattern=\"say \\\"x\\\"\""));
+        }
+
+        #[test]
+        fn format_prometheus_header_describes_the_count_mode() {
+            let mut output = Vec::new();
+            PatternCounter::format_prometheus_header(&mut output, CountMode::Occurrences).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("Number of occurrences of each pattern."));
+        }
+
+        #[test]
+        fn format_openmetrics_labels_patterns_by_their_path() {
+            let tree = vec![GroupTree::Group {
+                name: "a".to_string(),
+                group: vec![GroupTree::Leaf(Pattern::new(
+                    "b_c".to_string(),
+                    "x".to_string(),
+                    Regex::new("x").unwrap(),
+                ))],
+            }];
+            let counter = PatternCounter::new(tree, SortMode::Alphabetical);
+
+            let mut output = Vec::new();
+            let counters = [("f".to_string(), counter, 0)];
+            PatternCounter::format_openmetrics(&mut output, &counters, "my-app", 0.0).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("my_app_analyze_pattern_lines_total{pattern=\"a.b_c\""));
+        }
+    }
+}
+
+pub mod matcher {
+    use std::borrow::Cow;
+    use std::cmp::{Ordering, Reverse};
+    use std::collections::BinaryHeap;
+    use std::mem;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::Analyzer;
+    use super::*;
+    use crate::GroupTree;
+
+    #[derive(Debug, Clone)]
+    struct Inner {
+        pub pattern: Pattern,
+        pub matches: HashMap<String, u64>,
+
+        /// Values of each of the pattern's counted capture groups, keyed by the capture's name.
+        pub captures: HashMap<String, HashMap<String, u64>>,
+
+        /// Shortest and longest match seen so far.
+        pub extremes: Option<(String, String)>,
+
+        /// Sum of the lengths of all matches in bytes.
+        pub covered: u64,
+
+        /// Number of matches which were truncated before being counted.
+        pub truncated: u64,
+
+        /// Number of matches in which each capture group captured a non-empty string, indexed
+        /// by the group's number. The first entry counts all matches.
+        pub participation: Vec<u64>,
+
+        /// Sums of the summed capture group, only tracked if the pattern declares it.
+        pub sums: Option<Sums>,
+    }
+
+    /// Sums of the numeric values a capture group of a pattern takes.
+    #[derive(Debug, Clone, Default)]
+    struct Sums {
+        /// Sum of the values per match.
+        matches: HashMap<String, f64>,
+
+        /// Sum of the values per value of each counted capture group, keyed by the capture's
+        /// name.
+        captures: HashMap<String, HashMap<String, f64>>,
+
+        /// Number of matches in which the capture was missing or not a number.
+        skipped: u64,
+    }
+
+    impl group::From<Pattern> for Inner {
+        fn from(other: Pattern) -> Self {
+            Inner {
+                participation: vec![0; other.regex.captures_len()],
+                pattern: other,
+                matches: HashMap::new(),
+                captures: HashMap::new(),
+                extremes: None,
+                covered: 0,
+                truncated: 0,
+                sums: None,
+            }
+        }
+    }
+
+    impl group::Named for Inner {
+        fn name(&self) -> &str {
+            &self.pattern.name
+        }
+    }
+
+    /// Replaces the shortest or longest match if the match is shorter or longer. Ties are broken
+    /// by keeping the lexicographically smaller match.
+    fn track_extremes(extremes: &mut Option<(String, String)>, mat: &str) {
+        let len = mat.chars().count();
+        match extremes {
+            Some((shortest, longest)) => {
+                if (len, mat) < (shortest.chars().count(), shortest.as_str()) {
+                    *shortest = mat.to_string();
+                }
+                let longest_len = longest.chars().count();
+                if len > longest_len || (len == longest_len && mat < longest.as_str()) {
+                    *longest = mat.to_string();
+                }
+            }
+            None => *extremes = Some((mat.to_string(), mat.to_string())),
+        }
+    }
+
+    /// Top matches of a single pattern, followed by the top values of each counted capture group.
+    pub(crate) type PatternMatches<'a> = (
+        &'a str,
+        Vec<(&'a String, &'a u64)>,
+        Vec<(&'a str, Vec<(&'a String, &'a u64)>)>,
+    );
+
+    #[derive(Debug, Clone)]
+    pub struct PatternMatcher {
+        patterns: GroupVec<Inner>,
+        top: usize,
+
+        /// Whether to prefix each match with the name of the pattern that produced it.
+        show_pattern: bool,
+
+        /// Estimate of the number of bytes used to store the matches.
+        memory: usize,
+
+        /// Maximum number of bytes that may be used to store the matches.
+        memory_limit: Option<usize>,
+
+        /// Name of the pattern whose match exceeded the memory limit.
+        memory_exceeded_by: Option<String>,
+
+        /// Whether only the shortest and longest match of each pattern is tracked.
+        extremes: bool,
+
+        /// Whether only the number of bytes covered by the matches of each pattern is tracked.
+        bytes: bool,
+
+        /// Whether only how often each capture group participates in the matches is tracked.
+        count_captures: bool,
+
+        /// Number of matches which were found but not counted as distinct matches, since only
+        /// the extremes, the covered bytes, or the participation of the capture groups is tracked.
+        uncounted: u64,
+
+        /// Number of bytes of all analyzed lines.
+        input_bytes: u64,
+
+        /// Number of analyzed lines.
+        lines: u64,
+
+        /// Number of characters after which matches and capture values are truncated.
+        max_match_len: Option<usize>,
+
+        /// Template each match is written with instead of the table.
+        template: Option<Template>,
+
+        /// Order of the matches, if not sorted by descending count.
+        sort: Option<SortSpec>,
+
+        /// Whether the cumulative percentage of each pattern's matches is written after each
+        /// count.
+        cumulative: bool,
+
+        /// Maximum number of matches of each pattern that are counted per line.
+        max_per_line: usize,
+
+        /// Name of the capture group whose numeric value is summed per match, next to counting
+        /// the matches.
+        sum_capture: Option<String>,
+
+        /// Position of each pattern in the patterns file, in the same order as the flattened
+        /// patterns. Only tracked if overlapping matches are attributed to a single pattern.
+        priority: Option<Vec<usize>>,
+
+        /// Formats the counts.
+        numbers: NumberFormat,
+    }
+
+    /// Serializable matches of a [`PatternMatcher`]. The patterns are in the order of the tree and
+    /// the matches are sorted by descending count and then by the matched string, so that the
+    /// same input always results in the same report.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct MatchReport {
+        pub patterns: Vec<MatchReportTree>,
+
+        /// Number of analyzed lines.
+        #[serde(rename = "_total_lines", default)]
+        pub total_lines: u64,
+
+        /// Number of matches of all patterns, including those beyond the top matches.
+        #[serde(rename = "_total_matches", default)]
+        pub total_matches: u64,
+    }
+
+    /// A single node of a [`MatchReport`].
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(untagged)]
+    pub enum MatchReportTree {
+        Leaf {
+            name: String,
+            matches: Vec<MatchCount>,
+
+            /// Values of each counted capture group, in the order of the pattern's captures.
+            #[serde(default, skip_serializing_if = "Vec::is_empty")]
+            captures: Vec<CaptureReport>,
+
+            /// Number of matches which were truncated, since they were too long.
+            #[serde(default, skip_serializing_if = "is_zero")]
+            truncated: u64,
+        },
+        Group {
+            name: String,
+            group: Vec<MatchReportTree>,
+        },
+    }
+
+    /// The values of a single capture group of a [`MatchReportTree::Leaf`].
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct CaptureReport {
+        pub name: String,
+        pub values: Vec<MatchCount>,
+    }
+
+    /// A distinct match together with how often it was found.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct MatchCount {
+        pub value: String,
+        pub count: u64,
+    }
+
+    /// Number of distinct matches of a pattern, named by its fully-qualified path, as reported by
+    /// [`PatternMatcher::cardinality`].
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct Cardinality {
+        pub pattern: String,
+        pub distinct: usize,
+    }
+
+    /// Part of a [`Template`].
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum Segment {
+        Text(String),
+        Pattern,
+        Match,
+        Count,
+        Rank,
+    }
+
+    /// Line written for each match, in which `{pattern}`, `{match}`, `{count}`, and `{rank}` are
+    /// replaced by the name of the pattern, the match, its count, and its 1-based position among
+    /// the matches of the pattern. Literal braces are written as `{{` and `}}`.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Template {
+        segments: Vec<Segment>,
+    }
+
+    impl Template {
+        /// Returns the line for the given match.
+        pub fn render(&self, pattern: &str, mat: &str, count: &str, rank: usize) -> String {
+            let mut line = String::new();
+            for segment in &self.segments {
+                match segment {
+                    Segment::Text(text) => line.push_str(text),
+                    Segment::Pattern => line.push_str(pattern),
+                    Segment::Match => line.push_str(mat),
+                    Segment::Count => line.push_str(count),
+                    Segment::Rank => line.push_str(&rank.to_string()),
+                }
+            }
+            line
+        }
+    }
+
+    impl FromStr for Template {
+        type Err = String;
+
+        /// Parses the template, failing on unknown placeholders and unbalanced braces.
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let mut segments = Vec::new();
+            let mut text = String::new();
+            let mut chars = s.chars().peekable();
+            while let Some(c) = chars.next() {
+                match c {
+                    '{' if chars.peek() == Some(&'{') => {
+                        chars.next();
+                        text.push('{');
+                    }
+                    '}' if chars.peek() == Some(&'}') => {
+                        chars.next();
+                        text.push('}');
+                    }
+                    '{' => {
+                        let mut name = String::new();
+                        loop {
+                            match chars.next() {
+                                Some('}') => break,
+                                Some(c) => name.push(c),
+                                None => {
+                                    return Err(String::from(
+                                        "Unclosed '{', write '{{' for a literal '{'",
+                                    ))
+                                }
+                            }
+                        }
+                        let segment = match name.as_str() {
+                            "pattern" => Segment::Pattern,
+                            "match" => Segment::Match,
+                            "count" => Segment::Count,
+                            "rank" => Segment::Rank,
+                            _ => {
+                                return Err(format!(
+                                    "Unknown placeholder '{{{}}}', expected {{pattern}}, \
+                                    {{match}}, {{count}}, or {{rank}}",
+                                    name
+                                ))
+                            }
+                        };
+                        if !text.is_empty() {
+                            segments.push(Segment::Text(mem::take(&mut text)));
+                        }
+                        segments.push(segment);
+                    }
+                    '}' => return Err(String::from("Unmatched '}', write '}}' for a literal '}'")),
+                    _ => text.push(c),
+                }
+            }
+            if !text.is_empty() {
+                segments.push(Segment::Text(text));
+            }
+            Ok(Template { segments })
+        }
+    }
+
+    /// Key the matches can be sorted by.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum SortKey {
+        Count,
+        Match,
+    }
+
+    /// Order in which the matches are sorted, as a comma separated list of keys with an optional
+    /// direction, e.g. `count:desc,match:asc`. Counts default to descending and matches to
+    /// ascending order. Matches which are equal in all keys are sorted by the matched string.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct SortSpec {
+        /// Each key together with whether it is sorted in descending order.
+        keys: Vec<(SortKey, bool)>,
+    }
+
+    impl FromStr for SortSpec {
+        type Err = String;
+
+        /// Parses the sort spec, failing on unknown keys and directions.
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let keys = s
+                .split(',')
+                .map(|part| {
+                    let (key, direction) = match part.trim().split_once(':') {
+                        Some((key, direction)) => (key, Some(direction)),
+                        None => (part.trim(), None),
+                    };
+                    let key = match key {
+                        "count" => SortKey::Count,
+                        "match" => SortKey::Match,
+                        _ => {
+                            return Err(format!(
+                                "Unknown sort key '{}', expected count or match",
+                                key
+                            ))
+                        }
+                    };
+                    let descending = match direction {
+                        Some("asc") => false,
+                        Some("desc") => true,
+                        None => key == SortKey::Count,
+                        Some(direction) => {
+                            return Err(format!(
+                                "Unknown sort direction '{}', expected asc or desc",
+                                direction
+                            ))
+                        }
+                    };
+                    Ok((key, descending))
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(SortSpec { keys })
+        }
+    }
+
+    impl SortSpec {
+        /// Returns the `top` entries of the given matches in the order of the spec.
+        pub fn sorted<'a>(
+            &self,
+            matches: &'a HashMap<String, u64>,
+            top: usize,
+        ) -> Vec<(&'a String, &'a u64)> {
+            let mut sorted: Vec<_> = matches.iter().collect();
+            sorted.sort_by(|(a_match, a_count), (b_match, b_count)| {
+                self.keys
+                    .iter()
+                    .map(|(key, descending)| {
+                        let ordering = match key {
+                            SortKey::Count => a_count.cmp(b_count),
+                            SortKey::Match => a_match.cmp(b_match),
+                        };
+                        if *descending {
+                            ordering.reverse()
+                        } else {
+                            ordering
+                        }
+                    })
+                    .fold(Ordering::Equal, Ordering::then)
+                    .then_with(|| a_match.cmp(b_match))
+            });
+            sorted.truncate(top);
+            sorted
+        }
+    }
+
+    fn is_zero(count: &u64) -> bool {
+        *count == 0
+    }
+
+    /// Estimated number of bytes a single entry in a match or sum map uses besides the key's
+    /// bytes.
+    const ENTRY_OVERHEAD: usize = mem::size_of::<String>() + mem::size_of::<u64>() + 16;
+
+    impl PatternMatcher {
+        pub fn new(tree: Vec<GroupTree<Pattern>>, top: usize, sort: SortMode) -> Self {
+            PatternMatcher {
+                priority: None,
+                patterns: GroupVec::from_tree_with_options::<Pattern>(tree, sort),
+                top,
+                show_pattern: false,
+                memory: 0,
+                memory_limit: None,
+                memory_exceeded_by: None,
+                extremes: false,
+                bytes: false,
+                count_captures: false,
+                uncounted: 0,
+                input_bytes: 0,
+                lines: 0,
+                max_match_len: None,
+                template: None,
+                sort: None,
+                cumulative: false,
+                max_per_line: usize::MAX,
+                sum_capture: None,
+                numbers: NumberFormat::default(),
+            }
+        }
+
+        /// Sets whether only the shortest and longest match of each pattern is tracked, instead
+        /// of counting every distinct match. Must be called before analyzing any lines.
+        pub fn extremes(&mut self, extremes: bool) {
+            self.extremes = extremes;
+        }
+
+        /// Sets whether only the number of bytes covered by the matches of each pattern is
+        /// tracked, instead of counting every distinct match. Must be called before analyzing any
+        /// lines.
+        pub fn bytes(&mut self, bytes: bool) {
+            self.bytes = bytes;
+        }
+
+        /// Sets the number of characters after which matches and capture values are truncated and
+        /// marked with a trailing `…`, so that overly broad patterns do not store huge keys.
+        pub fn max_match_len(&mut self, max_match_len: Option<usize>) {
+            self.max_match_len = max_match_len;
+        }
+
+        /// Sets whether the percentage of all matches of the pattern that the shown matches up to
+        /// and including each match make up is written after its count.
+        pub fn cumulative(&mut self, cumulative: bool) {
+            self.cumulative = cumulative;
+        }
+
+        /// Sets the template each match is written with, instead of writing the matches as a
+        /// table below the name of each pattern.
+        pub fn template(&mut self, template: Option<Template>) {
+            self.template = template;
+        }
+
+        /// Sets the order of the matches and capture values, instead of by descending count and
+        /// then by the matched string.
+        pub fn sort_by(&mut self, sort: Option<SortSpec>) {
+            self.sort = sort;
+        }
+
+        /// Returns the number of matches shown for the pattern, which is its own top if set and
+        /// the global top otherwise.
+        fn top(&self, pattern: &Pattern) -> usize {
+            pattern.top.unwrap_or(self.top)
+        }
+
+        /// Returns the `top` entries of the given matches in the order set by
+        /// [`PatternMatcher::sort_by`].
+        fn sorted<'a>(
+            &self,
+            matches: &'a HashMap<String, u64>,
+            top: usize,
+        ) -> Vec<(&'a String, &'a u64)> {
+            match &self.sort {
+                Some(sort) => sort.sorted(matches, top),
+                None => sorted_matches(matches, top),
+            }
+        }
+
+        /// Sets how many matches of each pattern are counted per line at most, where 0 counts all
+        /// matches.
+        pub fn max_per_line(&mut self, max_per_line: usize) {
+            self.max_per_line = if max_per_line == 0 {
+                usize::MAX
+            } else {
+                max_per_line
+            };
+        }
+
+        /// Sets whether only the number of matches in which each capture group captured a
+        /// non-empty string is tracked, instead of counting every distinct match. Must be called
+        /// before analyzing any lines.
+        pub fn count_captures(&mut self, count_captures: bool) {
+            self.count_captures = count_captures;
+        }
+
+        /// Sums the numeric value of the named capture group per match and per value of each
+        /// counted capture group, next to counting them, for the patterns which declare the
+        /// capture. Matches in which the capture is missing or not a number are counted, but
+        /// skipped by the sums. Must be called before analyzing any lines.
+        pub fn sum_capture(&mut self, capture: &str) {
+            for inner in &mut self.patterns[..] {
+                inner.sums = inner
+                    .pattern
+                    .regex
+                    .capture_names()
+                    .any(|name| name == Some(capture))
+                    .then(Sums::default);
+            }
+            self.sum_capture = Some(capture.to_string());
+        }
+
+        /// Attributes each part of a line to at most one pattern: of any overlapping matches, only
+        /// the match of the pattern which comes first in the given patterns, i.e. in the patterns
+        /// file, is counted, and matches of the same pattern are kept from left to right. Must be
+        /// called with the same patterns the matcher was created from, before analyzing any
+        /// lines.
+        ///
+        /// The priority decides regardless of where the matches start, so a match of an earlier
+        /// pattern wins even over an overlapping match of a later pattern which starts before it.
+        /// Matches which start at the same position, including empty matches, are also decided by
+        /// the priority, so at most one of them is counted.
+        pub fn exclusive(&mut self, tree: &[GroupTree<Pattern>], sort: SortMode) {
+            self.priority = Some(group::source_positions(tree, sort));
+        }
+
+        /// Sets the maximum number of bytes that may be used to store the matches. Once the
+        /// estimate exceeds the limit, [`Analyzer::status`] returns an error.
+        pub fn limit_memory(&mut self, bytes: usize) {
+            self.memory_limit = Some(bytes);
+        }
+
+        /// Sets whether each match is prefixed with the name of the pattern that produced it.
+        pub fn show_pattern(&mut self, show_pattern: bool) {
+            self.show_pattern = show_pattern;
+        }
+
+        /// Sets how the counts are formatted.
+        pub fn number_format(&mut self, numbers: NumberFormat) {
+            self.numbers = numbers;
+        }
+
+        /// Returns the fully-qualified name of the pattern in each slot of the flattened patterns.
+        pub fn index_names(&self) -> Vec<String> {
+            self.patterns.index_names()
+        }
+
+        /// Returns the number of patterns.
+        pub fn len(&self) -> usize {
+            self.patterns.len()
+        }
+
+        /// Returns whether the matcher has no patterns.
+        pub fn is_empty(&self) -> bool {
+            self.patterns.is_empty()
+        }
+
+        /// Returns the fully-qualified name of each pattern together with its top matches, in the
+        /// order set by [`PatternMatcher::sort_by`].
+        pub fn top_matches(&self) -> Vec<(String, Vec<(&String, &u64)>)> {
+            self.patterns
+                .index_names()
+                .into_iter()
+                .zip(&self.patterns[..])
+                .map(|(name, inner)| {
+                    let top = self.top(&inner.pattern);
+                    (name, self.sorted(&inner.matches, top))
+                })
+                .collect()
+        }
+
+        /// Returns the top matches of each pattern as a serializable report.
+        pub fn report(&self) -> MatchReport {
+            fn match_counts(
+                matcher: &PatternMatcher,
+                matches: &HashMap<String, u64>,
+                top: usize,
+            ) -> Vec<MatchCount> {
+                matcher
+                    .sorted(matches, top)
+                    .into_iter()
+                    .map(|(value, count)| MatchCount {
+                        value: value.clone(),
+                        count: *count,
+                    })
+                    .collect()
+            }
+
+            fn report_tree(matcher: &PatternMatcher, tree: &GroupTree<usize>) -> MatchReportTree {
+                match tree {
+                    GroupTree::Leaf(index) => {
+                        let inner = &matcher.patterns[*index];
+                        let top = matcher.top(&inner.pattern);
+                        MatchReportTree::Leaf {
+                            name: inner.pattern.name.clone(),
+                            matches: match_counts(matcher, &inner.matches, top),
+                            captures: inner
+                                .pattern
+                                .captures
+                                .iter()
+                                .filter_map(|name| {
+                                    let values = inner.captures.get(name)?;
+                                    Some(CaptureReport {
+                                        name: name.clone(),
+                                        values: match_counts(matcher, values, top),
+                                    })
+                                })
+                                .collect(),
+                            truncated: inner.truncated,
+                        }
+                    }
+                    GroupTree::Group { name, group } => MatchReportTree::Group {
+                        name: name.clone(),
+                        group: group
+                            .iter()
+                            .map(|tree| report_tree(matcher, tree))
+                            .collect(),
+                    },
+                }
+            }
+
+            MatchReport {
+                patterns: self
+                    .patterns
+                    .inner
+                    .iter()
+                    .map(|tree| report_tree(self, tree))
+                    .collect(),
+                total_lines: self.lines,
+                total_matches: self.total_matches(),
+            }
+        }
+
+        /// Returns the number of analyzed lines.
+        pub fn total_lines(&self) -> u64 {
+            self.lines
+        }
+
+        /// Returns the number of matches of all patterns, including the matches which are not
+        /// counted as distinct matches, e.g. when only the extremes are tracked.
+        pub fn total_matches(&self) -> u64 {
+            self.patterns[..]
+                .iter()
+                .flat_map(|inner| inner.matches.values())
+                .sum::<u64>()
+                + self.uncounted
+        }
+
+        /// Returns the number of distinct matches for each pattern by its fully-qualified name,
+        /// sorted by descending count and then by name.
+        pub fn cardinality(&self) -> Vec<(String, usize)> {
+            let mut cardinality: Vec<_> = self
+                .patterns
+                .index_names()
+                .into_iter()
+                .zip(&self.patterns[..])
+                .map(|(name, inner)| (name, inner.matches.len()))
+                .collect();
+            cardinality.sort_by(|(a_name, a_len), (b_name, b_len)| {
+                b_len.cmp(a_len).then_with(|| a_name.cmp(b_name))
+            });
+            cardinality
+        }
+
+        /// Writes only the number of distinct matches of each pattern to the given writer.
+        pub fn format_cardinality<W>(&self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            let cardinality = self.cardinality();
+
+            let longest_name = cardinality
+                .iter()
+                .map(|(name, _)| name.chars().count())
+                .max()
+                .unwrap_or(0);
+
+            for (name, distinct) in cardinality {
+                writeln!(
+                    writer,
+                    "{:<name_len$} {}",
+                    format!("{}:", name),
+                    self.numbers.format(distinct),
+                    name_len = longest_name + 1
+                )?;
+            }
+            Ok(())
+        }
+
+        /// Returns the number of distinct matches and the Shannon entropy in bits of the
+        /// distribution of the matches of each pattern, sorted by descending entropy and then by
+        /// name. The entropy is zero if a single value makes up all matches, and `log2(n)` if all
+        /// `n` distinct values occur equally often.
+        pub fn entropy(&self) -> Vec<(&str, usize, f64)> {
+            let mut entropy: Vec<_> = self.patterns[..]
+                .iter()
+                .map(|inner| {
+                    let total: u64 = inner.matches.values().sum();
+                    let entropy = inner
+                        .matches
+                        .values()
+                        .map(|count| {
+                            let p = *count as f64 / total as f64;
+                            p * (1.0 / p).log2()
+                        })
+                        // Unlike `sum`, which starts at -0.0, patterns without matches yield 0.0.
+                        .fold(0.0, |sum, bits| sum + bits);
+                    (inner.pattern.name.as_str(), inner.matches.len(), entropy)
+                })
+                .collect();
+            entropy.sort_by(|(a_name, _, a_entropy), (b_name, _, b_entropy)| {
+                b_entropy
+                    .total_cmp(a_entropy)
+                    .then_with(|| a_name.cmp(b_name))
+            });
+            entropy
+        }
+
+        /// Writes the number of distinct matches and the entropy of the distribution of the
+        /// matches of each pattern to the given writer.
+        pub fn format_entropy<W>(&self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            let entropy = self.entropy();
+
+            let longest_name = entropy
+                .iter()
+                .map(|(name, _, _)| name.chars().count())
+                .max()
+                .unwrap_or(0);
+
+            for (name, distinct, bits) in entropy {
+                writeln!(
+                    writer,
+                    "{:<name_len$} {} distinct, {:.3} bits",
+                    format!("{}:", name),
+                    self.numbers.format(distinct),
+                    bits,
+                    name_len = longest_name + 1
+                )?;
+            }
+            Ok(())
+        }
+
+        /// Returns the median, the 90th and 99th percentile, and the maximum of how often each
+        /// distinct match of each pattern occurs, using the nearest-rank method, or `None` for
+        /// patterns without matches.
+        pub fn distribution(&self) -> Vec<(&str, Option<[u64; 4]>)> {
+            self.patterns[..]
+                .iter()
+                .map(|inner| {
+                    let mut counts: Vec<_> = inner.matches.values().copied().collect();
+                    counts.sort_unstable();
+                    let quantile = |q: f64| {
+                        let rank = (q * counts.len() as f64).ceil() as usize;
+                        counts[rank.clamp(1, counts.len()) - 1]
+                    };
+                    let quantiles = (!counts.is_empty())
+                        .then(|| [quantile(0.5), quantile(0.9), quantile(0.99), quantile(1.0)]);
+                    (inner.pattern.name.as_str(), quantiles)
+                })
+                .collect()
+        }
+
+        /// Returns for each pattern the distinct matches which only the other matcher found, with
+        /// their count there, and the distinct matches which only this matcher found, with their
+        /// count here, both sorted by descending count and then by the match. Both matchers must
+        /// have been created from the same patterns.
+        #[allow(clippy::type_complexity)]
+        pub fn diff<'a>(
+            &'a self,
+            other: &'a PatternMatcher,
+        ) -> Vec<(
+            &'a Pattern,
+            Vec<(&'a String, &'a u64)>,
+            Vec<(&'a String, &'a u64)>,
+        )> {
+            fn only<'a>(
+                matches: &'a HashMap<String, u64>,
+                others: &HashMap<String, u64>,
+            ) -> Vec<(&'a String, &'a u64)> {
+                let mut only: Vec<_> = matches
+                    .iter()
+                    .filter(|(mat, _)| !others.contains_key(*mat))
+                    .collect();
+                only.sort_by(|(a_match, a_count), (b_match, b_count)| {
+                    b_count.cmp(a_count).then_with(|| a_match.cmp(b_match))
+                });
+                only
+            }
+
+            self.patterns[..]
+                .iter()
+                .zip(&other.patterns[..])
+                .map(|(ours, theirs)| {
+                    (
+                        &ours.pattern,
+                        only(&theirs.matches, &ours.matches),
+                        only(&ours.matches, &theirs.matches),
+                    )
+                })
+                .collect()
+        }
+
+        /// Writes the distinct matches of each pattern which only the other matcher found,
+        /// prefixed by `+`, and which only this matcher found, prefixed by `-`, to the given
+        /// writer. At most the `top` most frequent matches of each kind are written.
+        pub fn format_diff<W>(&self, writer: &mut W, other: &PatternMatcher) -> io::Result<()>
+        where
+            W: Write,
+        {
+            let diff = self.diff(other);
+
+            let shown = |pattern: &Pattern, matches: &[(&'_ String, &'_ u64)]| {
+                matches.len().min(self.top(pattern))
+            };
+            let mut longest_match = 0;
+            let mut longest_count = 0;
+            for (pattern, added, removed) in &diff {
+                for matches in [added, removed] {
+                    for (mat, count) in &matches[..shown(pattern, matches)] {
+                        longest_match = longest_match.max(mat.chars().count() + 1);
+                        longest_count =
+                            longest_count.max(self.numbers.format(**count).chars().count());
+                    }
+                }
+            }
+
+            for (pattern, added, removed) in &diff {
+                writeln!(
+                    writer,
+                    "{} ({} new, {} gone)",
+                    pattern.name,
+                    self.numbers.format(added.len() as u64),
+                    self.numbers.format(removed.len() as u64)
+                )?;
+                for (sign, matches) in [('+', added), ('-', removed)] {
+                    for (mat, count) in &matches[..shown(pattern, matches)] {
+                        writeln!(
+                            writer,
+                            "\t{} {:<match_len$} {:>count_len$}",
+                            sign,
+                            format!("{}:", mat),
+                            self.numbers.format(**count),
+                            match_len = longest_match,
+                            count_len = longest_count
+                        )?;
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// Writes the distribution of how often each distinct match of each pattern occurs to the
+        /// given writer.
+        pub fn format_distribution<W>(&self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            let distribution = self.distribution();
+
+            let longest_name = distribution
+                .iter()
+                .map(|(name, _)| name.chars().count())
+                .max()
+                .unwrap_or(0);
+
+            for (name, quantiles) in distribution {
+                let name = format!("{}:", name);
+                match quantiles {
+                    Some([median, p90, p99, max]) => writeln!(
+                        writer,
+                        "{:<name_len$} median {}, p90 {}, p99 {}, max {}",
+                        name,
+                        self.numbers.format(median),
+                        self.numbers.format(p90),
+                        self.numbers.format(p99),
+                        self.numbers.format(max),
+                        name_len = longest_name + 1
+                    )?,
+                    None => writeln!(
+                        writer,
+                        "{:<name_len$} no matches",
+                        name,
+                        name_len = longest_name + 1
+                    )?,
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl PatternMatcher {
+        /// Writes the shortest and longest match of each pattern to the given writer.
+        pub fn format_extremes<W>(&self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            for inner in &self.patterns[..] {
+                writeln!(writer, "{}", inner.pattern.name)?;
+                if let Some((shortest, longest)) = &inner.extremes {
+                    for (label, mat) in [("shortest", shortest), ("longest", longest)] {
+                        writeln!(
+                            writer,
+                            "\t{:<9} {:?} ({})",
+                            format!("{}:", label),
+                            mat,
+                            mat.chars().count()
+                        )?;
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// Writes the top matches of each pattern with their count and the sum of the summed
+        /// capture group, sorted by descending sum, followed by the sums of the values of each
+        /// counted capture group. Matches without a numeric value are written with a sum of zero,
+        /// and the patterns without the capture are written with their counts only.
+        pub fn format_sums<W>(&self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            /// Returns the `top` counted entries sorted by descending sum and then by the key,
+            /// together with their count. Entries without any numeric value have a sum of zero.
+            fn by_sum<'a>(
+                counts: &'a HashMap<String, u64>,
+                sums: Option<&HashMap<String, f64>>,
+                top: usize,
+            ) -> Vec<(&'a String, u64, f64)> {
+                let mut sorted: Vec<_> = counts
+                    .iter()
+                    .map(|(key, count)| {
+                        let sum = sums.and_then(|sums| sums.get(key)).copied();
+                        (key, *count, sum.unwrap_or(0.0))
+                    })
+                    .collect();
+                sorted.sort_by(|(a_key, _, a_sum), (b_key, _, b_sum)| {
+                    b_sum.total_cmp(a_sum).then_with(|| a_key.cmp(b_key))
+                });
+                sorted.truncate(top);
+                sorted
+            }
+
+            let summed = self.sum_capture.as_deref().unwrap_or_default();
+            let format_sum = |sum: f64| {
+                if sum.fract() == 0.0 && sum.abs() < (1_u64 << f64::MANTISSA_DIGITS) as f64 {
+                    self.numbers.format(sum as i64)
+                } else {
+                    format!("{:.3}", sum)
+                }
+            };
+
+            // Each row is its depth, label, count, and sum, if any.
+            let mut rows: Vec<(usize, String, String, String)> = Vec::new();
+            for inner in &self.patterns[..] {
+                let top = self.top(&inner.pattern);
+                let sums = match &inner.sums {
+                    Some(sums) => sums,
+                    None => {
+                        rows.push((
+                            0,
+                            format!("{} (no {} capture)", inner.pattern.name, summed),
+                            String::new(),
+                            String::new(),
+                        ));
+                        for (mat, count) in sorted_matches(&inner.matches, top) {
+                            rows.push((
+                                1,
+                                format!("{}:", mat),
+                                self.numbers.format(*count),
+                                String::new(),
+                            ));
+                        }
+                        continue;
+                    }
+                };
+
+                let total = sums.matches.values().sum();
+                rows.push((
+                    0,
+                    format!("{} ({} total)", inner.pattern.name, format_sum(total)),
+                    String::new(),
+                    String::new(),
+                ));
+                for (mat, count, sum) in by_sum(&inner.matches, Some(&sums.matches), top) {
+                    rows.push((
+                        1,
+                        format!("{}:", mat),
+                        self.numbers.format(count),
+                        format_sum(sum),
+                    ));
+                }
+                for name in &inner.pattern.captures {
+                    if let Some(values) = inner.captures.get(name) {
+                        rows.push((1, format!("{}:", name), String::new(), String::new()));
+                        for (value, count, sum) in by_sum(values, sums.captures.get(name), top) {
+                            rows.push((
+                                2,
+                                format!("{}:", value),
+                                self.numbers.format(count),
+                                format_sum(sum),
+                            ));
+                        }
+                    }
+                }
+            }
+
+            let width = |column: fn(&(usize, String, String, String)) -> &String| {
+                rows.iter()
+                    .filter(|row| row.0 > 0)
+                    .map(|row| column(row).chars().count())
+                    .max()
+                    .unwrap_or(0)
+            };
+            let longest_label = width(|row| &row.1);
+            let longest_count = width(|row| &row.2);
+            let longest_sum = width(|row| &row.3);
+            for (depth, label, count, sum) in &rows {
+                if count.is_empty() {
+                    writeln!(writer, "{:\t<depth$}{}", "", label, depth = *depth)?;
+                    continue;
+                }
+                let row = format!(
+                    "{:\t<depth$}{:<label_len$} {:>count_len$} {:>sum_len$}",
+                    "",
+                    label,
+                    count,
+                    sum,
+                    depth = *depth,
+                    label_len = longest_label,
+                    count_len = longest_count,
+                    sum_len = longest_sum
+                );
+                writeln!(writer, "{}", row.trim_end())?;
+            }
+
+            let skipped: Vec<_> = self.patterns[..]
+                .iter()
+                .filter_map(|inner| Some((&inner.pattern.name, inner.sums.as_ref()?.skipped)))
+                .filter(|(_, skipped)| *skipped > 0)
+                .collect();
+            if !skipped.is_empty() {
+                writeln!(writer, "Matches without a numeric {} capture:", summed)?;
+                for (name, skipped) in skipped {
+                    writeln!(writer, "\t{}: {}", name, self.numbers.format(skipped))?;
+                }
+            }
+            Ok(())
+        }
+
+        /// Writes for each capture group of each pattern in how many of the pattern's matches it
+        /// captured a non-empty string. Groups are labeled by their name or, if unnamed, by their
+        /// number.
+        pub fn format_capture_counts<W>(&self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            for inner in &self.patterns[..] {
+                let total = inner.participation[0];
+                writeln!(
+                    writer,
+                    "{} ({} matches)",
+                    inner.pattern.name,
+                    self.numbers.format(total)
+                )?;
+
+                let labels: Vec<_> = inner
+                    .pattern
+                    .regex
+                    .capture_names()
+                    .enumerate()
+                    .skip(1)
+                    .map(|(index, name)| match name {
+                        Some(name) => format!("{}:", name),
+                        None => format!("#{}:", index),
+                    })
+                    .collect();
+                let longest_label = labels.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+
+                for (label, count) in labels.iter().zip(&inner.participation[1..]) {
+                    writeln!(
+                        writer,
+                        "\t{:<label_len$} {}",
+                        label,
+                        self.numbers.format(*count),
+                        label_len = longest_label
+                    )?;
+                }
+            }
+            Ok(())
+        }
+
+        /// Writes the number of bytes covered by the matches of each pattern and which fraction
+        /// of the analyzed bytes they make up to the given writer.
+        pub fn format_bytes<W>(&self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            let longest_name = self.patterns[..]
+                .iter()
+                .map(|inner| inner.pattern.name.chars().count())
+                .max()
+                .unwrap_or(0);
+            let longest_count = self.patterns[..]
+                .iter()
+                .map(|inner| self.numbers.format(inner.covered).chars().count())
+                .max()
+                .unwrap_or(0);
+
+            for inner in &self.patterns[..] {
+                let fraction = if self.input_bytes > 0 {
+                    inner.covered as f64 / self.input_bytes as f64
+                } else {
+                    0.0
+                };
+                writeln!(
+                    writer,
+                    "{:<name_len$} {:>count_len$} bytes ({:.2}%)",
+                    format!("{}:", inner.pattern.name),
+                    self.numbers.format(inner.covered),
+                    fraction * 100.0,
+                    name_len = longest_name + 1,
+                    count_len = longest_count
+                )?;
+            }
+            writeln!(
+                writer,
+                "Total: {} bytes",
+                self.numbers.format(self.input_bytes)
+            )
+        }
+    }
+
+    impl Analyzer<Pattern> for PatternMatcher {
+        type Analysis = HashMap<String, u64>;
+
+        fn analyze(&mut self, line: String) {
+            self.input_bytes += line.len() as u64;
+            self.lines += 1;
+            if self.priority.is_some() {
+                self.analyze_exclusive(&line);
+                return;
+            }
+            for inner in &mut self.patterns[..] {
+                if inner.pattern.excludes(&line) {
+                    continue;
+                }
+
+                let matches = inner.pattern.regex.find_iter(&line).take(self.max_per_line);
+                if self.count_captures {
+                    let captures_iter = inner.pattern.regex.captures_iter(&line);
+                    for captures in captures_iter.take(self.max_per_line) {
+                        self.uncounted += 1;
+                        for (group, count) in inner.participation.iter_mut().enumerate() {
+                            if captures
+                                .get(group)
+                                .is_some_and(|mat| !mat.as_str().is_empty())
+                            {
+                                *count += 1;
+                            }
+                        }
+                    }
+                } else if self.bytes {
+                    for mat in matches {
+                        self.uncounted += 1;
+                        inner.covered += (mat.end() - mat.start()) as u64;
+                    }
+                } else if self.extremes {
+                    for mat in matches {
+                        self.uncounted += 1;
+                        track_extremes(&mut inner.extremes, mat.as_str());
+                    }
+                } else if let (Some(sums), Some(summed)) = (&mut inner.sums, &self.sum_capture) {
+                    let captures_iter = inner.pattern.regex.captures_iter(&line);
+                    for captures in captures_iter.take(self.max_per_line) {
+                        let mat = captures.get(0).unwrap().as_str();
+                        let mat = truncate(mat, self.max_match_len);
+                        inner.truncated += matches!(mat, Cow::Owned(_)) as u64;
+                        count(&mut inner.matches, &mat, &mut self.memory);
+
+                        let value = captures
+                            .name(summed)
+                            .and_then(|value| value.as_str().parse::<f64>().ok())
+                            .filter(|value| value.is_finite());
+                        match value {
+                            Some(value) => add(&mut sums.matches, &mat, value, &mut self.memory),
+                            None => sums.skipped += 1,
+                        }
+                        for name in &inner.pattern.captures {
+                            if let Some(captured) = captures.name(name) {
+                                let captured = truncate(captured.as_str(), self.max_match_len);
+                                let values = inner.captures.entry(name.clone()).or_default();
+                                count(values, &captured, &mut self.memory);
+                                if let Some(value) = value {
+                                    let sums = sums.captures.entry(name.clone()).or_default();
+                                    add(sums, &captured, value, &mut self.memory);
+                                }
+                            }
+                        }
+                    }
+                } else if inner.pattern.captures.is_empty() {
+                    for mat in matches {
+                        let mat = truncate(mat.as_str(), self.max_match_len);
+                        inner.truncated += matches!(mat, Cow::Owned(_)) as u64;
+                        count(&mut inner.matches, &mat, &mut self.memory);
+                    }
+                } else {
+                    let captures_iter = inner.pattern.regex.captures_iter(&line);
+                    for captures in captures_iter.take(self.max_per_line) {
+                        let mat = captures.get(0).unwrap().as_str();
+                        let mat = truncate(mat, self.max_match_len);
+                        inner.truncated += matches!(mat, Cow::Owned(_)) as u64;
+                        count(&mut inner.matches, &mat, &mut self.memory);
+                        for name in &inner.pattern.captures {
+                            if let Some(value) = captures.name(name) {
+                                let value = truncate(value.as_str(), self.max_match_len);
+                                let values = inner.captures.entry(name.clone()).or_default();
+                                count(values, &value, &mut self.memory);
+                            }
+                        }
+                    }
+                }
+
+                if self.memory_exceeded_by.is_none()
+                    && self.memory_limit.is_some_and(|limit| self.memory > limit)
+                {
+                    self.memory_exceeded_by = Some(inner.pattern.name.clone());
+                }
+            }
+        }
+
+        fn status(&self) -> Result<(), String> {
+            match &self.memory_exceeded_by {
+                Some(name) => Err(format!(
+                    "Memory limit of {} MB exceeded while storing the matches of pattern '{}'. \
+                    Consider making the pattern more specific or raising the limit.",
+                    self.memory_limit.unwrap_or(0) / (1024 * 1024),
+                    name
+                )),
+                None => Ok(()),
+            }
+        }
+
+        fn format<W>(&mut self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            let sorted: Vec<_> = self.patterns[..]
+                .iter()
+                .map(|inner| {
+                    let top = self.top(&inner.pattern);
+                    let captures = inner
+                        .pattern
+                        .captures
+                        .iter()
+                        .filter_map(|name| {
+                            let values = inner.captures.get(name)?;
+                            Some((name.as_str(), self.sorted(values, top)))
+                        })
+                        .collect();
+                    (
+                        inner.pattern.name.as_str(),
+                        self.sorted(&inner.matches, top),
+                        captures,
+                    )
+                })
+                .collect();
+
+            match &self.template {
+                Some(template) => {
+                    for (name, matches, _) in &sorted {
+                        for (rank, (mat, count)) in matches.iter().enumerate() {
+                            let count = self.numbers.format(**count);
+                            writeln!(writer, "{}", template.render(name, mat, &count, rank + 1))?;
+                        }
+                    }
+                }
+                None => {
+                    let totals: Option<Vec<u64>> = self.cumulative.then(|| {
+                        self.patterns[..]
+                            .iter()
+                            .map(|inner| inner.matches.values().sum())
+                            .collect()
+                    });
+                    format_matches(
+                        writer,
+                        &sorted,
+                        totals.as_deref(),
+                        self.show_pattern,
+                        self.numbers,
+                    )?
+                }
+            }
+
+            let truncated: Vec<_> = self.patterns[..]
+                .iter()
+                .filter(|inner| inner.truncated > 0)
+                .collect();
+            if let (Some(max_len), false) = (self.max_match_len, truncated.is_empty()) {
+                writeln!(writer, "Truncated after {} characters:", max_len)?;
+                for inner in truncated {
+                    writeln!(
+                        writer,
+                        "\t{}: {}",
+                        inner.pattern.name,
+                        self.numbers.format(inner.truncated)
+                    )?;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl PatternMatcher {
+        /// Counts the matches of the line like [`Analyzer::analyze`] does, but resolves
+        /// overlapping matches of different patterns by their priority first.
+        fn analyze_exclusive(&mut self, line: &str) {
+            let priority = match &self.priority {
+                Some(priority) => priority,
+                None => return,
+            };
+
+            let mut spans = Vec::new();
+            for (index, inner) in self.patterns[..].iter().enumerate() {
+                if inner.pattern.excludes(line) {
+                    continue;
+                }
+                let captures_iter = inner.pattern.regex.captures_iter(line);
+                spans.extend(captures_iter.take(self.max_per_line).map(|captures| {
+                    let mat = captures.get(0).unwrap();
+                    Span {
+                        start: mat.start(),
+                        end: mat.end(),
+                        index,
+                        value: captures,
+                    }
+                }));
+            }
+
+            // Matches of the same pattern are already ordered from left to right, and sorting is
+            // stable.
+            for span in resolve_overlaps(spans, |span| priority[span.index]) {
+                let captures = span.value;
+                let inner = &mut self.patterns[span.index];
+                let mat = truncate(captures.get(0).unwrap().as_str(), self.max_match_len);
+                inner.truncated += matches!(mat, Cow::Owned(_)) as u64;
+                count(&mut inner.matches, &mat, &mut self.memory);
+                for name in &inner.pattern.captures {
+                    if let Some(value) = captures.name(name) {
+                        let value = truncate(value.as_str(), self.max_match_len);
+                        let values = inner.captures.entry(name.clone()).or_default();
+                        count(values, &value, &mut self.memory);
+                    }
+                }
+
+                if self.memory_exceeded_by.is_none()
+                    && self.memory_limit.is_some_and(|limit| self.memory > limit)
+                {
+                    self.memory_exceeded_by = Some(inner.pattern.name.clone());
+                }
+            }
+        }
+    }
+
+    /// Cuts the match after `max_len` characters and appends `…`. Only truncated matches are
+    /// owned.
+    fn truncate(mat: &str, max_len: Option<usize>) -> Cow<'_, str> {
+        match max_len.and_then(|max_len| mat.char_indices().nth(max_len)) {
+            Some((end, _)) => Cow::Owned(format!("{}…", &mat[..end])),
+            None => Cow::Borrowed(mat),
+        }
+    }
+
+    /// Increments the count of the match, adding the size of a new entry to the memory estimate.
+    fn count(matches: &mut HashMap<String, u64>, mat: &str, memory: &mut usize) {
+        let entry = matches.entry(mat.to_string()).or_insert_with(|| {
+            *memory += mat.len() + ENTRY_OVERHEAD;
+            0
+        });
+        *entry += 1;
+    }
+
+    /// Adds the value to the sum of the key, adding the size of a new entry to the memory
+    /// estimate.
+    fn add(sums: &mut HashMap<String, f64>, key: &str, value: f64, memory: &mut usize) {
+        let entry = sums.entry(key.to_string()).or_insert_with(|| {
+            *memory += key.len() + ENTRY_OVERHEAD;
+            0.0
+        });
+        *entry += value;
+    }
+
+    impl Merge for PatternMatcher {
+        fn merge(&mut self, other: Self) {
+            for (inner, other) in self.patterns[..].iter_mut().zip(other.patterns.flattened) {
+                for (mat, count) in other.matches {
+                    *inner.matches.entry(mat).or_insert(0) += count;
+                }
+                for (name, values) in other.captures {
+                    let ours = inner.captures.entry(name).or_default();
+                    for (value, count) in values {
+                        *ours.entry(value).or_insert(0) += count;
+                    }
+                }
+                if let Some((shortest, longest)) = other.extremes {
+                    track_extremes(&mut inner.extremes, &shortest);
+                    track_extremes(&mut inner.extremes, &longest);
+                }
+                if let (Some(ours), Some(theirs)) = (&mut inner.sums, other.sums) {
+                    for (mat, sum) in theirs.matches {
+                        *ours.matches.entry(mat).or_default() += sum;
+                    }
+                    for (name, values) in theirs.captures {
+                        let sums = ours.captures.entry(name).or_default();
+                        for (value, sum) in values {
+                            *sums.entry(value).or_default() += sum;
+                        }
+                    }
+                    ours.skipped += theirs.skipped;
+                }
+                inner.covered += other.covered;
+                inner.truncated += other.truncated;
+                for (count, other) in inner.participation.iter_mut().zip(other.participation) {
+                    *count += other;
+                }
+            }
+            self.input_bytes += other.input_bytes;
+            self.lines += other.lines;
+            self.uncounted += other.uncounted;
+            self.memory += other.memory;
+            if self.memory_exceeded_by.is_none() {
+                self.memory_exceeded_by = other.memory_exceeded_by;
+            }
+        }
+    }
+
+    /// Writes the matches of each pattern below the pattern's name, optionally prefixing each
+    /// match with the pattern's name as well. The values of each capture group are written below
+    /// the matches, nested under the capture's name.
+    pub(crate) fn format_matches<W>(
+        writer: &mut W,
+        patterns: &[PatternMatches],
+        totals: Option<&[u64]>,
+        show_pattern: bool,
+        numbers: NumberFormat,
+    ) -> io::Result<()>
+    where
+        W: Write,
+    {
+        let match_label = |name: &str, mat: &str| {
+            if show_pattern {
+                format!("[{}] {}:", name, mat)
+            } else {
+                format!("{}:", mat)
+            }
+        };
+
+        // Find longest match and count
+        let mut longest_match = 0;
+        let mut longest_count = 0;
+        for (name, matches, captures) in patterns {
+            let values = captures.iter().flat_map(|(_, values)| values);
+            for (mat, count) in matches.iter().chain(values) {
+                let count_len = numbers.format(**count).chars().count();
+                if longest_count < count_len {
+                    longest_count = count_len;
+                }
+                let match_len = match_label(name, mat).chars().count();
+                if longest_match < match_len {
+                    longest_match = match_len;
+                }
+            }
+        }
+
+        for (index, (name, matches, captures)) in patterns.iter().enumerate() {
+            writeln!(writer, "{}", name)?;
+            let mut running = 0;
+            for (mat, count) in matches {
+                write!(
+                    writer,
+                    "\t{:<match_len$} {:>count_len$}",
+                    match_label(name, mat),
+                    numbers.format(**count),
+                    match_len = longest_match,
+                    count_len = longest_count
+                )?;
+                match totals {
+                    Some(totals) => {
+                        running += **count;
+                        let percent = running as f64 / totals[index] as f64 * 100.0;
+                        writeln!(writer, " {:>5.1}%", percent)?;
+                    }
+                    None => writeln!(writer)?,
+                }
+            }
+            for (capture, values) in captures {
+                writeln!(writer, "\t{}:", capture)?;
+                for (value, count) in values {
+                    writeln!(
+                        writer,
+                        "\t\t{:<match_len$} {:>count_len$}",
+                        match_label(name, value),
+                        numbers.format(**count),
+                        match_len = longest_match,
+                        count_len = longest_count
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the `top` entries of the given matches, sorted by descending count and then by the
+    /// matched string.
+    ///
+    /// If only a few of the entries are shown, they are selected with a bounded heap instead of
+    /// sorting all entries.
+    pub(crate) fn sorted_matches<K>(matches: &HashMap<K, u64>, top: usize) -> Vec<(&K, &u64)>
+    where
+        K: Ord,
+    {
+        if top < matches.len() / 2 {
+            // The heap's maximum is the worst of the kept entries, i.e. the one with the lowest
+            // count and, among equal counts, the greatest key.
+            let mut heap = BinaryHeap::with_capacity(top + 1);
+            for (key, count) in matches {
+                heap.push((Reverse(count), key));
+                if heap.len() > top {
+                    heap.pop();
+                }
+            }
+            return heap
+                .into_sorted_vec()
+                .into_iter()
+                .map(|(Reverse(count), key)| (key, count))
+                .collect();
+        }
+
+        let mut sorted: Vec<_> = matches.iter().collect();
+        sorted.sort_by(|(a_key, a_count), (b_key, b_count)| {
+            b_count.cmp(a_count).then_with(|| a_key.cmp(b_key))
+        });
+        sorted.truncate(top);
+        sorted
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn report_json(lines: &[String]) -> String {
+            let pattern = Pattern {
+                captures: vec!["first".to_string()],
+                ..Pattern::new(
+                    "words".to_string(),
+                    r"(?P<first>\w)\w*".to_string(),
+                    Regex::new(r"(?P<first>\w)\w*").unwrap(),
+                )
+            };
+            let tree = vec![GroupTree::Group {
+                name: "group".to_string(),
+                group: vec![GroupTree::Leaf(pattern)],
+            }];
+            let mut matcher = PatternMatcher::new(tree, 5, SortMode::SourceOrder);
+            for line in lines {
+                matcher.analyze(line.clone());
+            }
+            serde_json::to_string(&matcher.report()).unwrap()
+        }
+
+        #[test]
+        fn report_is_identical_across_runs() {
+            // Every word occurs equally often, as do several first letters, so counts are tied.
+            let words = [
+                "pear", "apple", "fig", "kiwi", "plum", "lime", "date", "kale",
+            ];
+            let lines: Vec<_> = (0..3).flat_map(|_| words.map(String::from)).collect();
+
+            let first = report_json(&lines);
+            assert_eq!(first, report_json(&lines));
+
+            let reversed: Vec<_> = lines.iter().rev().cloned().collect();
+            assert_eq!(first, report_json(&reversed));
+            assert!(first.find("apple").unwrap() < first.find("date").unwrap());
+        }
+
+        #[test]
+        fn report_keeps_the_order_of_the_patterns_file() {
+            let tree: Vec<_> = ["zulu", "alpha", "mike"]
+                .iter()
+                .map(|name| {
+                    GroupTree::Leaf(Pattern::new(
+                        name.to_string(),
+                        "a".to_string(),
+                        Regex::new("a").unwrap(),
+                    ))
+                })
+                .collect();
+            let matcher = PatternMatcher::new(tree, 5, SortMode::SourceOrder);
+            let names: Vec<_> = matcher
+                .report()
+                .patterns
+                .into_iter()
+                .map(|tree| match tree {
+                    MatchReportTree::Leaf { name, .. } | MatchReportTree::Group { name, .. } => {
+                        name
+                    }
+                })
+                .collect();
+            assert_eq!(names, ["zulu", "alpha", "mike"]);
+        }
+
+        #[test]
+        fn cardinality_names_patterns_by_their_path() {
+            let group = |name: &str| GroupTree::Group {
+                name: name.to_string(),
+                group: vec![GroupTree::Leaf(Pattern::new(
+                    "w".to_string(),
+                    r"\w+".to_string(),
+                    Regex::new(r"\w+").unwrap(),
+                ))],
+            };
+            let mut matcher =
+                PatternMatcher::new(vec![group("b"), group("a")], 5, SortMode::Alphabetical);
+            matcher.analyze("x y".to_string());
+            assert_eq!(
+                matcher.cardinality(),
+                vec![("a.w".to_string(), 2), ("b.w".to_string(), 2)]
+            );
+        }
+
+        #[test]
+        fn format_sums_lists_matches_without_a_number() {
+            let regex = r"(?P<key>\w+)=(?P<value>\S+)";
+            let pattern = Pattern {
+                captures: vec!["key".to_string()],
+                ..Pattern::new(
+                    "pairs".to_string(),
+                    regex.to_string(),
+                    Regex::new(regex).unwrap(),
+                )
+            };
+            let analyzed = |sum_capture: Option<&str>| {
+                let tree = vec![GroupTree::Leaf(pattern.clone())];
+                let mut matcher = PatternMatcher::new(tree, 10, SortMode::Alphabetical);
+                if let Some(capture) = sum_capture {
+                    matcher.sum_capture(capture);
+                }
+                for line in ["a=2", "a=x", "b=x"] {
+                    matcher.analyze(line.to_string());
+                }
+                matcher
+            };
+            let matcher = analyzed(Some("value"));
+            // Only the match `a=2` and the key `a` have a sum, each of which takes up memory.
+            assert_eq!(
+                matcher.memory,
+                analyzed(None).memory + "a=2".len() + "a".len() + 2 * ENTRY_OVERHEAD
+            );
+
+            let mut output = Vec::new();
+            matcher.format_sums(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            let rows: Vec<Vec<_>> = output
+                .lines()
+                .map(|line| line.split_whitespace().collect())
+                .collect();
+            for row in [
+                vec!["a=2:", "1", "2"],
+                vec!["a=x:", "1", "0"],
+                vec!["b=x:", "1", "0"],
+                vec!["a:", "2", "2"],
+                vec!["b:", "1", "0"],
+            ] {
+                assert!(rows.contains(&row), "{:?} missing from:\n{}", row, output);
+            }
+        }
+
+        #[test]
+        fn exclusive_counts_each_span_once() {
+            let tree: Vec<_> = [("word", r"\w+"), ("empty_x", "x*"), ("empty_y", "y*")]
+                .iter()
+                .map(|(name, regex)| {
+                    GroupTree::Leaf(Pattern::new(
+                        name.to_string(),
+                        regex.to_string(),
+                        Regex::new(regex).unwrap(),
+                    ))
+                })
+                .collect();
+            let mut matcher = PatternMatcher::new(tree.clone(), 5, SortMode::Alphabetical);
+            matcher.exclusive(&tree, SortMode::Alphabetical);
+            matcher.analyze("ab cd".to_string());
+
+            let counts = |name: &str| {
+                let inner = matcher.patterns[..]
+                    .iter()
+                    .find(|inner| inner.pattern.name == name)
+                    .unwrap();
+                let mut matches: Vec<_> = inner.matches.iter().collect();
+                matches.sort();
+                matches
+            };
+            assert_eq!(
+                counts("word"),
+                vec![(&"ab".to_string(), &1), (&"cd".to_string(), &1)]
+            );
+            // Only the empty matches right after each word remain, and only once per position.
+            assert_eq!(counts("empty_x"), vec![(&String::new(), &2)]);
+            assert!(counts("empty_y").is_empty());
+        }
+
+        #[test]
+        fn sorted_matches_agrees_with_a_full_sort() {
+            // Few distinct counts, so that most entries are tied and only the key decides.
+            let matches: HashMap<String, u64> = (0..1000)
+                .map(|i| (format!("match-{}", (i * 7919) % 1000), i % 4))
+                .collect();
+
+            let mut expected: Vec<_> = matches.iter().collect();
+            expected.sort_by(|(a_key, a_count), (b_key, b_count)| {
+                b_count.cmp(a_count).then_with(|| a_key.cmp(b_key))
+            });
+
+            for top in [
+                0, 1, 2, 3, 10, 249, 250, 251, 499, 500, 501, 999, 1000, 5000,
+            ] {
+                let selected = sorted_matches(&matches, top);
+                assert_eq!(selected, expected[..top.min(expected.len())], "top {}", top);
+            }
+        }
+    }
+}
+
+pub mod extract {
+    //! Contains the analyzer which streams the matches instead of counting them.
+    //!
+    use super::group::*;
+    use super::*;
+
+    impl From<Pattern> for Pattern {
+        fn from(other: Pattern) -> Self {
+            other
+        }
+    }
+
+    /// Writes every match of every pattern to the writer as soon as it is found, one per line.
+    pub struct PatternExtractor<W> {
+        patterns: GroupVec<Pattern>,
+        writer: W,
+
+        /// Whether to prefix each match with the name of the pattern that produced it.
+        show_pattern: bool,
+
+        /// File name each match is prefixed with.
+        file_name: Option<String>,
+
+        /// Whether to prefix each match with the 1-based number of its line.
+        line_numbers: bool,
+
+        /// Number of lines analyzed so far.
+        line: u64,
+
+        /// The first error that occurred while writing.
+        error: Option<io::Error>,
+    }
+
+    impl<W> PatternExtractor<W>
+    where
+        W: Write,
+    {
+        pub fn new(tree: Vec<GroupTree<Pattern>>, sort: SortMode, writer: W) -> Self {
+            PatternExtractor {
+                patterns: GroupVec::from_tree_with_options::<Pattern>(tree, sort),
+                writer,
+                show_pattern: false,
+                file_name: None,
+                line_numbers: false,
+                line: 0,
+                error: None,
+            }
+        }
+
+        /// Sets the file name each match is prefixed with, like `grep -H`.
+        pub fn file_name(&mut self, file_name: Option<String>) {
+            self.file_name = file_name;
+        }
+
+        /// Sets whether each match is prefixed with the 1-based number of its line, like
+        /// `grep -n`.
+        pub fn line_numbers(&mut self, line_numbers: bool) {
+            self.line_numbers = line_numbers;
+        }
+
+        /// Sets whether each match is prefixed with the name of the pattern that produced it.
+        pub fn show_pattern(&mut self, show_pattern: bool) {
+            self.show_pattern = show_pattern;
+        }
+
+        /// Writes all matches of the line.
+        fn extract(&mut self, line: &str) -> io::Result<()> {
+            for pattern in &self.patterns[..] {
+                if pattern.excludes(line) {
+                    continue;
+                }
+
+                for mat in pattern.regex.find_iter(line) {
+                    if let Some(file_name) = &self.file_name {
+                        write!(self.writer, "{}:", file_name)?;
+                    }
+                    if self.line_numbers {
+                        write!(self.writer, "{}:", self.line)?;
+                    }
+                    if self.show_pattern {
+                        write!(self.writer, "{}: ", pattern.name)?;
+                    }
+                    writeln!(self.writer, "{}", mat.as_str())?;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl<W> Analyzer<Pattern> for PatternExtractor<W>
+    where
+        W: Write,
+    {
+        type Analysis = ();
+
+        fn analyze(&mut self, line: String) {
+            self.line += 1;
+            if self.error.is_none() {
+                self.error = self.extract(&line).err();
+            }
+        }
+
+        /// Flushes the matches, since they have already been written during the analysis.
+        fn format<V>(&mut self, _writer: &mut V) -> io::Result<()>
+        where
+            V: Write,
+        {
+            self.writer.flush()
+        }
+
+        fn status(&self) -> Result<(), String> {
+            match &self.error {
+                Some(e) => Err(format!("{}", e)),
+                None => Ok(()),
+            }
+        }
+    }
+}
+
+pub mod clean {
+    //! Contains the analyzer which rewrites the lines instead of collecting statistics.
+    //!
+    use std::borrow::Cow;
+    use std::cmp::Reverse;
+
+    use super::group::*;
+    use super::*;
+
+    /// Decides which match is replaced if the matches of several patterns overlap.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum OverlapPolicy {
+        /// The match of the pattern which comes first in the patterns file wins.
+        #[default]
+        TreeOrder,
+
+        /// The longest match wins, and matches of the same length are decided by the order of
+        /// the patterns file.
+        Longest,
+    }
+
+    impl FromStr for OverlapPolicy {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "tree-order" => Ok(OverlapPolicy::TreeOrder),
+                "longest" => Ok(OverlapPolicy::Longest),
+                _ => Err(format!(
+                    "Invalid overlap policy '{}', expected tree-order or longest",
+                    s
+                )),
+            }
+        }
+    }
+
+    /// Replaces or removes the matches of every pattern and writes each cleaned line to the
+    /// writer as soon as it is read.
+    pub struct PatternCleaner<W> {
+        patterns: GroupVec<Pattern>,
+        writer: W,
+
+        /// Decides which match is replaced if matches overlap.
+        policy: OverlapPolicy,
+
+        /// Number of matches replaced by each pattern, in the same order as the flattened
+        /// patterns.
+        replaced: Vec<u64>,
+
+        /// Maximum number of changed lines that are retained.
+        max_examples: usize,
+
+        /// The first changed lines, before and after cleaning them.
+        examples: Vec<(String, String)>,
+
+        /// The first error that occurred while writing.
+        error: Option<io::Error>,
+    }
+
+    impl<W> PatternCleaner<W>
+    where
+        W: Write,
+    {
+        /// Creates a cleaner, which applies the patterns in the order of the patterns file.
+        pub fn new(tree: Vec<GroupTree<Pattern>>, writer: W) -> Self {
+            let patterns = GroupVec::from_tree_with_options::<Pattern>(tree, SortMode::SourceOrder);
+            PatternCleaner {
+                replaced: vec![0; patterns.len()],
+                patterns,
+                writer,
+                policy: OverlapPolicy::default(),
+                max_examples: 0,
+                examples: Vec::new(),
+                error: None,
+            }
+        }
+
+        /// Returns the writer the cleaned lines were written to.
+        pub fn into_writer(self) -> W {
+            self.writer
+        }
+
+        /// Retains the first `max_examples` lines which are changed by cleaning them.
+        pub fn examples(&mut self, max_examples: usize) {
+            self.max_examples = max_examples;
+        }
+
+        /// Sets which match is replaced if the matches of several patterns overlap.
+        pub fn overlap_policy(&mut self, policy: OverlapPolicy) {
+            self.policy = policy;
+        }
+
+        /// Returns the line with the matches of each pattern replaced. All patterns are matched
+        /// against the original line, and of any overlapping matches only the one chosen by the
+        /// [`OverlapPolicy`] is replaced, so that the result does not depend on the order in
+        /// which the replacements are applied. Overlaps are resolved like [`resolve_overlaps`]
+        /// does, so only one of several matches starting at the same position is replaced.
+        ///
+        /// The line is only owned if a replacement differs from the text it replaces.
+        pub fn clean<'a>(&mut self, line: &'a str) -> Cow<'a, str> {
+            let mut spans = Vec::new();
+            for (index, pattern) in self.patterns[..].iter().enumerate() {
+                if pattern.excludes(line) {
+                    continue;
+                }
+
+                let replace = pattern.replace.as_deref().unwrap_or("");
+                for captures in pattern.regex.captures_iter(line) {
+                    let mat = captures.get(0).unwrap();
+                    let mut replacement = String::new();
+                    captures.expand(replace, &mut replacement);
+                    spans.push(Span {
+                        start: mat.start(),
+                        end: mat.end(),
+                        index,
+                        value: replacement,
+                    });
+                }
+            }
+            if spans.is_empty() {
+                return Cow::Borrowed(line);
+            }
+
+            // Matches of the same pattern are already ordered from left to right, and sorting is
+            // stable.
+            let kept = match self.policy {
+                OverlapPolicy::TreeOrder => resolve_overlaps(spans, |span| span.index),
+                OverlapPolicy::Longest => {
+                    resolve_overlaps(spans, |span| (Reverse(span.end - span.start), span.index))
+                }
+            };
+
+            let mut changed = false;
+            let mut cleaned = String::with_capacity(line.len());
+            let mut position = 0;
+            for span in kept {
+                self.replaced[span.index] += 1;
+                changed |= span.value != line[span.start..span.end];
+                cleaned.push_str(&line[position..span.start]);
+                cleaned.push_str(&span.value);
+                position = span.end;
+            }
+            if !changed {
+                return Cow::Borrowed(line);
+            }
+            cleaned.push_str(&line[position..]);
+            Cow::Owned(cleaned)
+        }
+
+        /// Writes how many matches each pattern replaced, followed by the retained changed lines
+        /// before and after cleaning them.
+        pub fn format_changes<V>(&self, writer: &mut V, numbers: NumberFormat) -> io::Result<()>
+        where
+            V: Write,
+        {
+            let longest_name = self.patterns[..]
+                .iter()
+                .map(|pattern| pattern.name.chars().count())
+                .max()
+                .unwrap_or(0);
+
+            for (pattern, replaced) in self.patterns[..].iter().zip(&self.replaced) {
+                writeln!(
+                    writer,
+                    "{:<name_len$} {} replaced",
+                    format!("{}:", pattern.name),
+                    numbers.format(*replaced),
+                    name_len = longest_name + 1
+                )?;
+            }
+            for (before, after) in &self.examples {
+                writeln!(writer)?;
+                writeln!(writer, "- {}", before)?;
+                writeln!(writer, "+ {}", after)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl<W> Analyzer<Pattern> for PatternCleaner<W>
+    where
+        W: Write,
+    {
+        type Analysis = ();
+
+        fn analyze(&mut self, line: String) {
+            if self.error.is_none() {
+                let cleaned = self.clean(&line);
+                if let (Cow::Owned(cleaned), true) =
+                    (&cleaned, self.examples.len() < self.max_examples)
+                {
+                    self.examples.push((line.clone(), cleaned.clone()));
+                }
+                self.error = writeln!(self.writer, "{}", cleaned).err();
+            }
+        }
+
+        /// Flushes the cleaned lines, since they have already been written during the analysis.
+        fn format<V>(&mut self, _writer: &mut V) -> io::Result<()>
+        where
+            V: Write,
+        {
+            self.writer.flush()
+        }
+
+        fn status(&self) -> Result<(), String> {
+            match &self.error {
+                Some(e) => Err(format!("{}", e)),
+                None => Ok(()),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn new_cleaner(patterns: &[(&str, &str, Option<&str>)]) -> PatternCleaner<io::Sink> {
+            let tree = patterns
+                .iter()
+                .map(|(name, regex, replace)| {
+                    GroupTree::Leaf(Pattern {
+                        replace: replace.map(String::from),
+                        ..Pattern::new(
+                            name.to_string(),
+                            regex.to_string(),
+                            Regex::new(regex).unwrap(),
+                        )
+                    })
+                })
+                .collect();
+            PatternCleaner::new(tree, io::sink())
+        }
+
+        const EMAIL: (&str, &str, Option<&str>) = ("email", r"[\w.]+@[\w.]+", Some("<email>"));
+        const DOMAIN: (&str, &str, Option<&str>) = ("domain", r"\w+\.com", Some("<domain>"));
+
+        #[test]
+        fn tree_order_prefers_the_first_pattern() {
+            let line = "mail bob@example.com or visit example.com";
+
+            let mut domain_first = new_cleaner(&[DOMAIN, EMAIL]);
+            assert_eq!(
+                domain_first.clean(line),
+                "mail bob@<domain> or visit <domain>"
+            );
+            assert_eq!(domain_first.replaced, vec![2, 0]);
+
+            let mut email_first = new_cleaner(&[EMAIL, DOMAIN]);
+            assert_eq!(email_first.clean(line), "mail <email> or visit <domain>");
+            assert_eq!(email_first.replaced, vec![1, 1]);
+        }
+
+        #[test]
+        fn longest_prefers_the_longest_match() {
+            let line = "mail bob@example.com or visit example.com";
+            for patterns in [[DOMAIN, EMAIL], [EMAIL, DOMAIN]] {
+                let mut cleaner = new_cleaner(&patterns);
+                cleaner.overlap_policy(OverlapPolicy::Longest);
+                assert_eq!(cleaner.clean(line), "mail <email> or visit <domain>");
+            }
+        }
+
+        #[test]
+        fn empty_matches_at_the_same_position_conflict() {
+            let mut cleaner = new_cleaner(&[("a", "x*", Some("[a]")), ("b", "y*", Some("[b]"))]);
+            assert_eq!(cleaner.clean("abc"), "[a]a[a]b[a]c[a]");
+            assert_eq!(cleaner.replaced, vec![4, 0]);
+
+            // An empty match conflicts with a match starting at its position, but not with one
+            // ending there.
+            let mut cleaner = new_cleaner(&[("a", "x*", Some("[a]")), ("b", "b", Some("B"))]);
+            cleaner.overlap_policy(OverlapPolicy::Longest);
+            assert_eq!(cleaner.clean("abc"), "[a]aB[a]c[a]");
+        }
+
+        #[test]
+        fn unchanged_lines_are_borrowed() {
+            let mut cleaner = new_cleaner(&[("a", "a", Some("a")), ("empty", "x*", None)]);
+            assert!(matches!(cleaner.clean("abc"), Cow::Borrowed("abc")));
+            assert_eq!(cleaner.replaced, vec![1, 3]);
+
+            let mut cleaner = new_cleaner(&[("a", "a", Some("A"))]);
+            assert!(matches!(cleaner.clean("abc"), Cow::Owned(_)));
+        }
+    }
+}
+
+pub mod cooccur {
+    //! Contains the analyzers which correlate the matches of two or more patterns.
+    //!
+    use super::group::*;
+    use super::*;
+
+    /// Counts the 2x2 contingency table of two patterns over all lines.
+    #[derive(Debug, Clone)]
+    pub struct CooccurrenceCounter {
+        a: Pattern,
+        b: Pattern,
+
+        /// Number of lines indexed by whether they match `a` and whether they match `b`.
+        table: [[u64; 2]; 2],
+
+        /// Formats the counts.
+        numbers: NumberFormat,
+    }
+
+    impl CooccurrenceCounter {
+        pub fn new(a: Pattern, b: Pattern) -> Self {
+            CooccurrenceCounter {
+                a,
+                b,
+                table: [[0; 2]; 2],
+                numbers: NumberFormat::default(),
+            }
+        }
+
+        /// Sets how the counts are formatted.
+        pub fn number_format(&mut self, numbers: NumberFormat) {
+            self.numbers = numbers;
+        }
+
+        /// Returns the number of lines by whether they match `a` and whether they match `b`.
+        pub fn count(&self, a: bool, b: bool) -> u64 {
+            self.table[a as usize][b as usize]
+        }
+    }
+
+    impl Merge for CooccurrenceCounter {
+        fn merge(&mut self, other: Self) {
+            for (row, other) in self.table.iter_mut().zip(other.table) {
+                for (count, other) in row.iter_mut().zip(other) {
+                    *count += other;
+                }
+            }
+        }
+    }
+
+    impl Analyzer<Pattern> for CooccurrenceCounter {
+        type Analysis = [[u64; 2]; 2];
+
+        fn analyze(&mut self, line: String) {
+            let a = self.a.regex.is_match(&line) && !self.a.excludes(&line);
+            let b = self.b.regex.is_match(&line) && !self.b.excludes(&line);
+            self.table[a as usize][b as usize] += 1;
+        }
+
+        fn format<W>(&mut self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            let a_name = self.a.name.as_str();
+            let b_name = self.b.name.as_str();
+            let labels = [
+                [format!("!{}", a_name), a_name.to_string()],
+                [format!("!{}", b_name), b_name.to_string()],
+            ];
+            let cells: Vec<Vec<String>> = [true, false]
+                .iter()
+                .map(|a| {
+                    [true, false]
+                        .iter()
+                        .map(|b| self.numbers.format(self.count(*a, *b)))
+                        .collect()
+                })
+                .collect();
+
+            let first_width = labels[0].iter().map(|l| l.chars().count()).max().unwrap();
+            let width = labels[1]
+                .iter()
+                .map(|l| l.chars().count())
+                .chain(cells.iter().flatten().map(|c| c.len()))
+                .max()
+                .unwrap();
+
+            writeln!(
+                writer,
+                "{: <first_width$}  {: >width$}  {: >width$}",
+                "",
+                labels[1][1],
+                labels[1][0],
+                first_width = first_width,
+                width = width
+            )?;
+            for (a, row) in [true, false].iter().zip(&cells) {
+                writeln!(
+                    writer,
+                    "{: <first_width$}  {: >width$}  {: >width$}",
+                    labels[0][*a as usize],
+                    row[0],
+                    row[1],
+                    first_width = first_width,
+                    width = width
+                )?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Counts for every pair of patterns how many lines both of them match, to find patterns
+    /// which are redundant with each other.
+    #[derive(Debug, Clone)]
+    pub struct OverlapCounter {
+        /// Patterns together with their fully-qualified, dot separated paths.
+        patterns: Vec<(String, Pattern)>,
+
+        /// Number of lines matching each pattern.
+        counts: Vec<u64>,
+
+        /// Number of lines matching both patterns, keyed by the indices of the patterns with the
+        /// smaller index first. Pairs which never matched the same line are missing.
+        overlaps: HashMap<(usize, usize), u64>,
+
+        /// Maximum number of pairs that are shown.
+        top: usize,
+
+        /// Formats the counts.
+        numbers: NumberFormat,
+    }
+
+    impl OverlapCounter {
+        pub fn new(tree: &[GroupTree<Pattern>], top: usize) -> Self {
+            let patterns: Vec<_> = leaves(tree)
+                .into_iter()
+                .map(|(path, pattern)| (path, pattern.clone()))
+                .collect();
+            OverlapCounter {
+                counts: vec![0; patterns.len()],
+                patterns,
+                overlaps: HashMap::new(),
+                top,
+                numbers: NumberFormat::default(),
+            }
+        }
+
+        /// Sets how the counts are formatted.
+        pub fn number_format(&mut self, numbers: NumberFormat) {
+            self.numbers = numbers;
+        }
+
+        /// Returns the paths of the pairs of patterns which matched the same lines together with
+        /// the number of those lines, sorted by descending overlap and then by the paths.
+        pub fn overlaps(&self) -> Vec<(&str, &str, u64)> {
+            let mut overlaps: Vec<_> = self
+                .overlaps
+                .iter()
+                .map(|((a, b), count)| {
+                    (
+                        self.patterns[*a].0.as_str(),
+                        self.patterns[*b].0.as_str(),
+                        *count,
+                    )
+                })
+                .collect();
+            overlaps.sort_by(
+                |(a_first, a_second, a_count), (b_first, b_second, b_count)| {
+                    b_count
+                        .cmp(a_count)
+                        .then_with(|| (a_first, a_second).cmp(&(b_first, b_second)))
+                },
+            );
+            overlaps
+        }
+
+        /// Returns the number of lines matching the pattern with the given path.
+        fn count(&self, path: &str) -> u64 {
+            self.patterns
+                .iter()
+                .position(|(other, _)| other == path)
+                .map_or(0, |index| self.counts[index])
+        }
+    }
+
+    impl Merge for OverlapCounter {
+        fn merge(&mut self, other: Self) {
+            for (count, other) in self.counts.iter_mut().zip(other.counts) {
+                *count += other;
+            }
+            for (pair, count) in other.overlaps {
+                *self.overlaps.entry(pair).or_insert(0) += count;
+            }
+        }
+    }
+
+    impl Analyzer<Pattern> for OverlapCounter {
+        type Analysis = HashMap<(usize, usize), u64>;
+
+        fn analyze(&mut self, line: String) {
+            let matched: Vec<_> = self
+                .patterns
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, pattern))| {
+                    pattern.regex.is_match(&line) && !pattern.excludes(&line)
+                })
+                .map(|(index, _)| index)
+                .collect();
+
+            for (i, a) in matched.iter().enumerate() {
+                self.counts[*a] += 1;
+                for b in &matched[i + 1..] {
+                    *self.overlaps.entry((*a, *b)).or_insert(0) += 1;
+                }
+            }
+        }
+
+        /// Writes the `top` pairs with the most overlap, together with which fraction of the
+        /// lines of the less frequent pattern the overlap makes up.
+        fn format<W>(&mut self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            let overlaps: Vec<_> = self.overlaps().into_iter().take(self.top).collect();
+            let labels: Vec<_> = overlaps
+                .iter()
+                .map(|(a, b, _)| format!("{} & {}:", a, b))
+                .collect();
+
+            let longest_label = labels.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+            let longest_count = overlaps
+                .iter()
+                .map(|(_, _, count)| self.numbers.format(*count).chars().count())
+                .max()
+                .unwrap_or(0);
+
+            for (label, (a, b, count)) in labels.iter().zip(&overlaps) {
+                let (smaller, name) = (self.count(a), a).min((self.count(b), b));
+                writeln!(
+                    writer,
+                    "{:<label_len$} {:>count_len$} ({:.1}% of {})",
+                    label,
+                    self.numbers.format(*count),
+                    *count as f64 / smaller as f64 * 100.0,
+                    name,
+                    label_len = longest_label,
+                    count_len = longest_count
+                )?;
+            }
+            Ok(())
+        }
+    }
+}
+
+pub mod bytes {
+    //! Contains the analyzers which match raw bytes instead of UTF-8 strings.
+    //!
+    use super::group::*;
+    use super::matcher::{format_matches, sorted_matches};
+    use super::*;
+
+    /// Pattern which matches raw bytes, which holds its name and its regex.
+    #[derive(Debug, Clone)]
+    pub struct BytePattern {
+        pub name: String,
+        pub regex: regex::bytes::Regex,
+    }
+
+    impl Named for BytePattern {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn source(&self) -> &str {
+            self.regex.as_str()
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct Inner {
+        pattern: BytePattern,
+
+        /// Matches with all non-printable bytes escaped.
+        matches: HashMap<String, u64>,
+    }
+
+    impl group::From<BytePattern> for Inner {
+        fn from(other: BytePattern) -> Self {
+            Inner {
+                pattern: other,
+                matches: HashMap::new(),
+            }
+        }
+    }
+
+    /// Finds and counts the actual matches of each pattern inside binary data.
+    #[derive(Debug, Clone)]
+    pub struct BytePatternMatcher {
+        patterns: GroupVec<Inner>,
+        top: usize,
+
+        /// Whether to prefix each match with the name of the pattern that produced it.
+        show_pattern: bool,
+
+        /// Formats the counts.
+        numbers: NumberFormat,
+    }
+
+    impl BytePatternMatcher {
+        pub fn new(tree: Vec<GroupTree<BytePattern>>, top: usize, sort: SortMode) -> Self {
+            BytePatternMatcher {
+                patterns: GroupVec::from_tree_with_options::<BytePattern>(tree, sort),
+                top,
+                show_pattern: false,
+                numbers: NumberFormat::default(),
+            }
+        }
+
+        /// Sets whether each match is prefixed with the name of the pattern that produced it.
+        pub fn show_pattern(&mut self, show_pattern: bool) {
+            self.show_pattern = show_pattern;
+        }
+
+        /// Sets how the counts are formatted.
+        pub fn number_format(&mut self, numbers: NumberFormat) {
+            self.numbers = numbers;
+        }
+
+        /// Matches all patterns against the given bytes.
+        pub fn analyze_bytes(&mut self, data: &[u8]) {
+            for inner in &mut self.patterns[..] {
+                for mat in inner.pattern.regex.find_iter(data) {
+                    let entry = inner
+                        .matches
+                        .entry(escape_bytes(mat.as_bytes()))
+                        .or_insert(0);
+                    *entry += 1;
+                }
+            }
+        }
+    }
+
+    impl Analyzer<BytePattern> for BytePatternMatcher {
+        type Analysis = HashMap<String, u64>;
+
+        fn analyze(&mut self, line: String) {
+            self.analyze_bytes(line.as_bytes());
+        }
+
+        fn format<W>(&mut self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            let sorted: Vec<_> = self.patterns[..]
+                .iter()
+                .map(|inner| {
+                    (
+                        inner.pattern.name.as_str(),
+                        sorted_matches(&inner.matches, self.top),
+                        Vec::new(),
+                    )
+                })
+                .collect();
+
+            format_matches(writer, &sorted, None, self.show_pattern, self.numbers)
+        }
+    }
+
+    /// Escapes all bytes which are not printable ASCII characters, e.g. as `\x00` or `\n`.
+    fn escape_bytes(bytes: &[u8]) -> String {
+        bytes
+            .iter()
+            .flat_map(|byte| std::ascii::escape_default(*byte))
+            .map(char::from)
+            .collect()
+    }
+}
+
+pub mod vocab {
+    use super::matcher::sorted_matches;
+    use super::*;
 
     #[derive(Debug, Clone)]
     pub struct Vocabulizer {
-        vocab: HashMap<String, usize>,
+        vocab: HashMap<String, u64>,
         top: usize,
+
+        /// Formats the counts.
+        numbers: NumberFormat,
     }
 
     impl Vocabulizer {
@@ -310,45 +4903,361 @@ pub mod vocab {
             Vocabulizer {
                 vocab: HashMap::new(),
                 top,
+                numbers: NumberFormat::default(),
             }
         }
 
-        pub fn analyze(&mut self, line: String) {
+        /// Sets how the counts are formatted.
+        pub fn number_format(&mut self, numbers: NumberFormat) {
+            self.numbers = numbers;
+        }
+    }
+
+    impl Analyzer<String> for Vocabulizer {
+        type Analysis = HashMap<String, u64>;
+
+        fn analyze(&mut self, line: String) {
             for word in line.split_whitespace() {
                 let entry = self.vocab.entry(word.to_string()).or_insert(0);
                 *entry += 1;
             }
         }
 
-        fn format<W>(&mut self, _writer: &mut W) where W: std::io::Write {
-            // Find longest name and count
-            let mut longest_name = 0;
+        fn format<W>(&mut self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            let sorted = sorted_matches(&self.vocab, self.top);
+
+            // Find longest word and count
+            let mut longest_word = 0;
             let mut longest_count = 0;
-            for (word, count) in self.vocab.iter() {
-                let name_len = word.chars().count();
-                if longest_name < name_len {
-                    longest_name = name_len;
+            for (word, count) in &sorted {
+                let word_len = word.chars().count();
+                if longest_word < word_len {
+                    longest_word = word_len;
                 }
 
-                if longest_count < *count {
-                    longest_count *= count;
+                let count_len = self.numbers.format(**count).chars().count();
+                if longest_count < count_len {
+                    longest_count = count_len;
                 }
             }
 
+            for (word, count) in &sorted {
+                writeln!(
+                    writer,
+                    "{:<word_len$} {:>count_len$}",
+                    format!("{}:", word),
+                    self.numbers.format(**count),
+                    word_len = longest_word + 1,
+                    count_len = longest_count
+                )?;
+            }
+            Ok(())
+        }
+    }
+}
 
-            for inner in &self.patterns[..] {
-                println!("{}", inner.pattern.name);
-                // TODO: Sort data before printing.
-                for (mat, count) in inner.matches.iter().take(self.top) {
-                    println!(
-                        "\t{:<match_len$} {} {:>count_len$}",
-                        format!("{}:", mat),
-                        count.to_formatted_string(&Locale::en),
-                        match_len = longest_match + 1,
-                        count_len = longest_count
-                    );
+pub mod suggest {
+    //! Contains the analyzer which suggests patterns for the most common shapes of the lines.
+    //!
+    use lazy_static::lazy_static;
+
+    use super::matcher::sorted_matches;
+    use super::*;
+
+    /// Regex of the timestamps which are replaced in the shapes, e.g. `2021-12-24 18:30:00.123Z`.
+    const TIMESTAMP: &str =
+        r"\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(?:[.,]\d+)?(?:Z|[+-]\d{2}:?\d{2})?";
+
+    /// Returns the shape of the line as an anchored regex, which replaces timestamps, hex strings
+    /// of at least six digits and letters, and digit runs with a regex matching any of them, and
+    /// escapes everything else.
+    pub fn shape(line: &str) -> String {
+        lazy_static! {
+            static ref TOKEN: Regex = Regex::new(&format!(
+                r"(?P<timestamp>{})|\b[0-9a-fA-F]{{6,}}\b|\d+",
+                TIMESTAMP
+            ))
+            .unwrap();
+        }
+
+        let mut shape = String::from("^");
+        let mut last = 0;
+        for captures in TOKEN.captures_iter(line) {
+            let token = captures.get(0).unwrap();
+            let replacement = if captures.name("timestamp").is_some() {
+                TIMESTAMP
+            } else if token.as_str().bytes().all(|byte| byte.is_ascii_digit()) {
+                r"\d+"
+            } else if token.as_str().bytes().any(|byte| byte.is_ascii_digit()) {
+                r"[0-9a-fA-F]+"
+            } else {
+                // Words like `deadbeef` are more likely text than hex.
+                continue;
+            };
+            shape.push_str(&regex::escape(&line[last..token.start()]));
+            shape.push_str(replacement);
+            last = token.end();
+        }
+        shape.push_str(&regex::escape(&line[last..]));
+        shape.push('$');
+        shape
+    }
+
+    /// Counts the shapes of the lines, and writes the most common ones as a starter patterns
+    /// file.
+    #[derive(Debug, Clone)]
+    pub struct ShapeSuggester {
+        shapes: HashMap<String, u64>,
+        top: usize,
+
+        /// Number of analyzed lines.
+        lines: u64,
+
+        /// Formats the counts.
+        numbers: NumberFormat,
+    }
+
+    impl ShapeSuggester {
+        pub fn new(top: usize) -> Self {
+            ShapeSuggester {
+                shapes: HashMap::new(),
+                top,
+                lines: 0,
+                numbers: NumberFormat::default(),
+            }
+        }
+
+        /// Sets how the counts are formatted.
+        pub fn number_format(&mut self, numbers: NumberFormat) {
+            self.numbers = numbers;
+        }
+
+        /// Returns the `top` most common shapes with the number of lines of each shape, sorted by
+        /// descending count and then by the shape.
+        pub fn shapes(&self) -> Vec<(&String, &u64)> {
+            sorted_matches(&self.shapes, self.top)
+        }
+    }
+
+    impl Merge for ShapeSuggester {
+        fn merge(&mut self, other: Self) {
+            for (shape, count) in other.shapes {
+                *self.shapes.entry(shape).or_insert(0) += count;
+            }
+            self.lines += other.lines;
+        }
+    }
+
+    impl Analyzer<String> for ShapeSuggester {
+        type Analysis = HashMap<String, u64>;
+
+        fn analyze(&mut self, line: String) {
+            self.lines += 1;
+            *self.shapes.entry(shape(&line)).or_insert(0) += 1;
+        }
+
+        /// Writes the most common shapes as a patterns file, with the number of lines of each
+        /// shape in a comment.
+        fn format<W>(&mut self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            let shapes = self.shapes();
+            writeln!(
+                writer,
+                "# The {} most common of {} line shapes in {} lines.",
+                self.numbers.format(shapes.len()),
+                self.numbers.format(self.shapes.len()),
+                self.numbers.format(self.lines)
+            )?;
+            for (rank, (shape, count)) in shapes.into_iter().enumerate() {
+                // A JSON string is a valid double-quoted YAML scalar.
+                writeln!(
+                    writer,
+                    "shape_{}: {}  # {} lines ({:.1}%)",
+                    rank + 1,
+                    serde_json::to_string(shape).unwrap(),
+                    self.numbers.format(*count),
+                    *count as f64 / self.lines as f64 * 100.0
+                )?;
+            }
+            Ok(())
+        }
+    }
+}
+
+pub mod timeline {
+    //! Contains the analyzer which counts the matches of each pattern per time bucket.
+    //!
+    use std::collections::BTreeMap;
+
+    use super::group::*;
+    use super::*;
+
+    /// Glyphs of a sparkline, from the lowest to the highest count.
+    const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    /// Counts the lines matching each pattern per time bucket, where the bucket of a line is
+    /// extracted from its timestamp.
+    #[derive(Debug, Clone)]
+    pub struct TimelineCounter {
+        /// Each pattern together with its fully-qualified, dot separated path.
+        patterns: Vec<(String, Pattern)>,
+
+        /// Extracts the bucket of a line, which is the first capture group or else the whole
+        /// match.
+        bucket: Regex,
+
+        /// Number of matching lines of each pattern, keyed by the bucket.
+        buckets: BTreeMap<String, Vec<u64>>,
+
+        /// Bucket of the last line which had one. Lines without a bucket are counted in it.
+        current: Option<String>,
+
+        /// Whether the counts are written as one sparkline per pattern.
+        sparkline: bool,
+
+        /// Formats the counts.
+        numbers: NumberFormat,
+    }
+
+    impl TimelineCounter {
+        pub fn new(tree: Vec<GroupTree<Pattern>>, bucket: Regex) -> Self {
+            TimelineCounter {
+                patterns: leaves(&tree)
+                    .into_iter()
+                    .map(|(path, pattern)| (path, pattern.clone()))
+                    .collect(),
+                bucket,
+                buckets: BTreeMap::new(),
+                current: None,
+                sparkline: false,
+                numbers: NumberFormat::default(),
+            }
+        }
+
+        /// Sets whether the counts are written as one sparkline per pattern, instead of listing
+        /// the counts of each bucket.
+        pub fn sparkline(&mut self, sparkline: bool) {
+            self.sparkline = sparkline;
+        }
+
+        /// Sets how the counts are formatted.
+        pub fn number_format(&mut self, numbers: NumberFormat) {
+            self.numbers = numbers;
+        }
+
+        /// Writes a sparkline of the counts across all buckets for each pattern, scaled to the
+        /// pattern's highest count.
+        pub fn format_sparkline<W>(&self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            let (first, last) = match (self.buckets.keys().next(), self.buckets.keys().last()) {
+                (Some(first), Some(last)) => (first, last),
+                _ => return Ok(()),
+            };
+            writeln!(
+                writer,
+                "{} .. {} ({} buckets)",
+                first,
+                last,
+                self.numbers.format(self.buckets.len())
+            )?;
+
+            let longest_name = self
+                .patterns
+                .iter()
+                .map(|(path, _)| path.chars().count())
+                .max()
+                .unwrap_or(0);
+            for (index, (path, _)) in self.patterns.iter().enumerate() {
+                let counts: Vec<u64> = self.buckets.values().map(|counts| counts[index]).collect();
+                let max = counts.iter().copied().max().unwrap_or(0);
+                writeln!(
+                    writer,
+                    "{:<name_len$} {} max {}",
+                    format!("{}:", path),
+                    spark(&counts, max),
+                    self.numbers.format(max),
+                    name_len = longest_name + 1
+                )?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Maps each count to a glyph, where zero is the lowest glyph and `max` the highest one. Any
+    /// count above zero is shown at least one glyph above the lowest one.
+    fn spark(counts: &[u64], max: u64) -> String {
+        let top = SPARKS.len() as u64 - 1;
+        counts
+            .iter()
+            .map(|count| match max {
+                0 => SPARKS[0],
+                _ => SPARKS[(count * top).div_ceil(max) as usize],
+            })
+            .collect()
+    }
+
+    impl Analyzer<Pattern> for TimelineCounter {
+        type Analysis = BTreeMap<String, Vec<u64>>;
+
+        fn analyze(&mut self, line: String) {
+            if let Some(captures) = self.bucket.captures(&line) {
+                let bucket = captures.get(1).or_else(|| captures.get(0)).unwrap();
+                if self.current.as_deref() != Some(bucket.as_str()) {
+                    self.current = Some(bucket.as_str().to_string());
+                }
+            }
+
+            // Lines before the first timestamp do not belong to any bucket.
+            let bucket = match &self.current {
+                Some(bucket) => bucket,
+                None => return,
+            };
+            let len = self.patterns.len();
+            let counts = self
+                .buckets
+                .entry(bucket.clone())
+                .or_insert_with(|| vec![0; len]);
+            for (count, (_, pattern)) in counts.iter_mut().zip(&self.patterns) {
+                if pattern.regex.is_match(&line) && !pattern.excludes(&line) {
+                    *count += 1;
+                }
+            }
+        }
+
+        fn format<W>(&mut self, writer: &mut W) -> io::Result<()>
+        where
+            W: Write,
+        {
+            if self.sparkline {
+                return self.format_sparkline(writer);
+            }
+
+            let longest_name = self
+                .patterns
+                .iter()
+                .map(|(path, _)| path.chars().count())
+                .max()
+                .unwrap_or(0);
+            for (bucket, counts) in &self.buckets {
+                writeln!(writer, "{}", bucket)?;
+                for ((path, _), count) in self.patterns.iter().zip(counts) {
+                    writeln!(
+                        writer,
+                        "  {:<name_len$} {}",
+                        format!("{}:", path),
+                        self.numbers.format(*count),
+                        name_len = longest_name + 1
+                    )?;
                 }
             }
+            Ok(())
         }
     }
 }
@@ -0,0 +1,67 @@
+//! Contains the progress bar which is written to stderr while an input is analyzed.
+//!
+use std::fs;
+use std::path::Path;
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::archive::ArchiveKind;
+use crate::STDIN;
+
+/// Progress of the analysis of a single input. Regular files show which percentage of their bytes
+/// was analyzed and the estimated remaining time, while the standard input and archives, whose
+/// size is unknown, only show the number of analyzed lines.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    bar: ProgressBar,
+
+    /// Whether the bar counts bytes instead of lines.
+    bytes: bool,
+}
+
+impl Progress {
+    pub fn new<P>(path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let len = if path == Path::new(STDIN) || ArchiveKind::detect(path).is_some() {
+            None
+        } else {
+            fs::metadata(path)
+                .ok()
+                .filter(|metadata| metadata.is_file())
+                .map(|metadata| metadata.len())
+        };
+
+        match len {
+            Some(len) => {
+                let bar = ProgressBar::new(len);
+                bar.set_style(ProgressStyle::default_bar().template(
+                    "{bar:40} {percent:>3}% {bytes}/{total_bytes} ({bytes_per_sec}, {eta} left)",
+                ));
+                Progress { bar, bytes: true }
+            }
+            None => {
+                let bar = ProgressBar::new_spinner();
+                bar.set_style(ProgressStyle::default_spinner().template("{spinner} {pos} lines"));
+                Progress { bar, bytes: false }
+            }
+        }
+    }
+
+    /// Advances the progress by the number of bytes and lines read since the last call.
+    pub fn advance(&self, bytes: u64, lines: u64) {
+        self.bar.inc(if self.bytes { bytes } else { lines });
+    }
+
+    /// Removes the bar from stderr, so that the results can be written.
+    pub fn clear(&self) {
+        self.bar.finish_and_clear();
+    }
+
+    /// Shows the bar again after it was cleared, counting from zero.
+    pub fn restart(&self) {
+        self.bar.reset();
+    }
+}
@@ -0,0 +1,392 @@
+//! Contains the logic to split an input into the records which are analyzed.
+//!
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use serde_json::Value;
+
+use crate::decode::Decoder;
+use crate::progress::Progress;
+
+/// Separates the records of an input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordSeparator {
+    /// Split on `\n`, removing a trailing `\r` from each record unless
+    /// [`ReadOptions::keep_cr`] is set.
+    #[default]
+    Newline,
+
+    /// Split only on `\r\n`, keeping single `\n` bytes inside the records.
+    CrLf,
+
+    /// Split on an arbitrary byte, e.g. `\0`.
+    Byte(u8),
+}
+
+impl FromStr for RecordSeparator {
+    type Err = String;
+
+    /// Parses `\n`, `\r\n`, `\0`, `\t`, a hexadecimal byte like `0x1e`, or a single ASCII
+    /// character.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "\\n" | "\n" => Ok(RecordSeparator::Newline),
+            "\\r\\n" | "\r\n" => Ok(RecordSeparator::CrLf),
+            "\\0" | "\0" => Ok(RecordSeparator::Byte(0)),
+            "\\t" | "\t" => Ok(RecordSeparator::Byte(b'\t')),
+            _ => {
+                if let Some(hex) = s.strip_prefix("0x") {
+                    u8::from_str_radix(hex, 16)
+                        .map(RecordSeparator::Byte)
+                        .map_err(|e| format!("Invalid record separator '{}': {}", s, e))
+                } else if s.len() == 1 && s.is_ascii() {
+                    Ok(RecordSeparator::Byte(s.as_bytes()[0]))
+                } else {
+                    Err(format!(
+                        "Invalid record separator '{}', expected \\n, \\r\\n, \\0, a hex byte \
+                        like 0x1e, or a single character",
+                        s
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// Options controlling how an input is split into records.
+#[derive(Debug, Clone, Default)]
+pub struct ReadOptions {
+    pub separator: RecordSeparator,
+
+    /// Keep the trailing `\r` of records separated by `\n`, e.g. of files with CRLF line
+    /// endings.
+    pub keep_cr: bool,
+
+    /// Parse each record as JSON and analyze only the value of this field.
+    pub json_field: Option<JsonField>,
+
+    /// Skip records which are not valid JSON or lack the [`ReadOptions::json_field`], instead
+    /// of failing.
+    pub skip_invalid_json: bool,
+
+    /// Analyze the decoded payload of each record instead, skipping records without a payload
+    /// or whose payload cannot be decoded.
+    pub decoder: Option<Decoder>,
+
+    /// Stop reading once this instant has passed, analyzing only the records read so far.
+    pub deadline: Option<Instant>,
+
+    /// Truncate records which are longer than the limit, so that a huge record never has to fit
+    /// into memory.
+    pub line_limit: Option<LineLimit>,
+
+    /// Progress which is advanced while the records are read.
+    pub progress: Option<Progress>,
+}
+
+impl ReadOptions {
+    /// Returns true if the [`ReadOptions::deadline`] has passed.
+    pub fn timed_out(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// Maximum length of a record in bytes. Longer records are truncated while they are read, without
+/// splitting a UTF-8 sequence.
+#[derive(Debug, Clone)]
+pub struct LineLimit {
+    max_bytes: usize,
+
+    /// Number of truncated records, shared by all clones.
+    truncated: Arc<AtomicU64>,
+}
+
+impl LineLimit {
+    pub fn new(max_bytes: usize) -> Self {
+        LineLimit {
+            max_bytes,
+            truncated: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Returns the maximum length of a record in bytes.
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    /// Returns the number of records which were truncated so far.
+    pub fn truncated(&self) -> u64 {
+        self.truncated.load(Ordering::Relaxed)
+    }
+}
+
+/// Dot separated path to a field of a JSON object, e.g. `.message` or `.request.path`. Array
+/// elements are selected by their index, e.g. `.tags.0`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonField(Vec<String>);
+
+impl FromStr for JsonField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let path = s.strip_prefix('.').unwrap_or(s);
+        let keys: Vec<_> = path.split('.').map(str::to_string).collect();
+        if keys.iter().any(String::is_empty) {
+            return Err(format!(
+                "Invalid JSON field '{}', expected a dot separated path like .message",
+                s
+            ));
+        }
+        Ok(JsonField(keys))
+    }
+}
+
+impl JsonField {
+    /// Parses the record as JSON and returns the value of the field. Strings are returned
+    /// without their quotes, while all other values are returned as JSON.
+    pub fn extract(&self, record: &str) -> Result<String, String> {
+        let root: Value =
+            serde_json::from_str(record).map_err(|e| format!("Invalid JSON record: {}", e))?;
+
+        let mut value = &root;
+        for key in &self.0 {
+            let next = match value {
+                Value::Array(values) => key.parse::<usize>().ok().and_then(|i| values.get(i)),
+                _ => value.get(key),
+            };
+            value =
+                next.ok_or_else(|| format!("JSON record has no field .{}", self.0.join(".")))?;
+        }
+
+        match value {
+            Value::String(s) => Ok(s.clone()),
+            other => Ok(other.to_string()),
+        }
+    }
+}
+
+/// Iterator over the records of a reader, which are decoded as UTF-8, replacing invalid
+/// sequences.
+pub struct Records<R> {
+    reader: R,
+    options: ReadOptions,
+    buf: Vec<u8>,
+
+    /// Number of bytes read so far, including the separators.
+    bytes_read: u64,
+
+    /// Whether bytes of the current record were skipped because of the line limit.
+    truncated: bool,
+
+    /// Whether the byte before the last delimiter was a skipped `\r`.
+    skipped_cr: bool,
+}
+
+impl<R> Records<R>
+where
+    R: BufRead,
+{
+    pub fn new(reader: R, options: ReadOptions) -> Self {
+        Records {
+            reader,
+            options,
+            buf: Vec::new(),
+            bytes_read: 0,
+            truncated: false,
+            skipped_cr: false,
+        }
+    }
+
+    /// Returns the number of bytes read so far, including the separators.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Reads until and including the delimiter into the buffer, like [`BufRead::read_until`].
+    /// With a line limit, the buffer only grows up to the limit and the remaining bytes of the
+    /// record are skipped, except for the delimiter.
+    fn read_until(&mut self, delimiter: u8) -> io::Result<usize> {
+        let max_bytes = match &self.options.line_limit {
+            Some(limit) => limit.max_bytes,
+            None => return self.reader.read_until(delimiter, &mut self.buf),
+        };
+
+        let mut read = 0;
+        loop {
+            let available = match self.reader.fill_buf() {
+                Ok(available) => available,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if available.is_empty() {
+                return Ok(read);
+            }
+
+            let (used, found) = match available.iter().position(|byte| *byte == delimiter) {
+                Some(i) => (i + 1, true),
+                None => (available.len(), false),
+            };
+            let content = &available[..used - found as usize];
+            let kept = content.len().min(max_bytes.saturating_sub(self.buf.len()));
+            self.buf.extend_from_slice(&content[..kept]);
+            if kept < content.len() {
+                self.truncated = true;
+                self.skipped_cr = content.last() == Some(&b'\r');
+            } else if !content.is_empty() {
+                self.skipped_cr = false;
+            }
+            if found {
+                self.buf.push(delimiter);
+            }
+
+            self.reader.consume(used);
+            read += used;
+            if found {
+                return Ok(read);
+            }
+        }
+    }
+
+    /// Reads the next record into the buffer without its separator. Returns false at the end of
+    /// the input. A trailing record without a separator is still returned.
+    fn read_record(&mut self) -> io::Result<bool> {
+        self.buf.clear();
+        self.truncated = false;
+        self.skipped_cr = false;
+        let more = self.read_separated()?;
+
+        if let (true, Some(limit)) = (self.truncated, &self.options.line_limit) {
+            limit.truncated.fetch_add(1, Ordering::Relaxed);
+            trim_partial_char(&mut self.buf);
+        }
+        Ok(more)
+    }
+
+    /// Reads the next record into the buffer and removes its separator.
+    fn read_separated(&mut self) -> io::Result<bool> {
+        match self.options.separator {
+            RecordSeparator::Newline => {
+                let read = self.read_until(b'\n')?;
+                if read == 0 {
+                    return Ok(false);
+                }
+                self.bytes_read += read as u64;
+                if self.buf.last() == Some(&b'\n') {
+                    self.buf.pop();
+                    if !self.options.keep_cr && self.buf.last() == Some(&b'\r') {
+                        self.buf.pop();
+                    }
+                }
+            }
+            RecordSeparator::CrLf => {
+                loop {
+                    let read = self.read_until(b'\n')?;
+                    if read == 0 {
+                        break;
+                    }
+                    self.bytes_read += read as u64;
+                    if self.buf.ends_with(b"\r\n") {
+                        self.buf.truncate(self.buf.len() - 2);
+                        return Ok(true);
+                    }
+                    if self.skipped_cr && self.buf.last() == Some(&b'\n') {
+                        self.buf.pop();
+                        return Ok(true);
+                    }
+                }
+                if self.buf.is_empty() {
+                    return Ok(false);
+                }
+            }
+            RecordSeparator::Byte(separator) => {
+                let read = self.read_until(separator)?;
+                if read == 0 {
+                    return Ok(false);
+                }
+                self.bytes_read += read as u64;
+                if self.buf.last() == Some(&separator) {
+                    self.buf.pop();
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Removes an incomplete UTF-8 sequence from the end of a truncated record.
+fn trim_partial_char(buf: &mut Vec<u8>) {
+    // A sequence is at most four bytes long, so its first byte is among the last four.
+    let tail = buf.len().saturating_sub(4);
+    if let Some(start) = (tail..buf.len()).rev().find(|&i| buf[i] & 0xC0 != 0x80) {
+        let width = match buf[start] {
+            0xF0..=0xFF => 4,
+            0xE0..=0xEF => 3,
+            0xC0..=0xDF => 2,
+            _ => 1,
+        };
+        if start + width > buf.len() {
+            buf.truncate(start);
+        }
+    }
+}
+
+impl<R> Iterator for Records<R>
+where
+    R: BufRead,
+{
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_record() {
+            Ok(true) => Some(Ok(String::from_utf8_lossy(&self.buf).into_owned())),
+            Ok(false) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Splits the file into at most `count` byte ranges of roughly equal size, each of which starts
+/// at the beginning of a record.
+pub fn chunks<P>(path: P, count: usize, separator: RecordSeparator) -> io::Result<Vec<(u64, u64)>>
+where
+    P: AsRef<Path>,
+{
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let count = count.max(1) as u64;
+
+    let mut starts = vec![0];
+    for i in 1..count {
+        let target = len * i / count;
+        if target <= *starts.last().unwrap() {
+            continue;
+        }
+
+        // Begin each chunk directly after the first separator following the target offset.
+        file.seek(SeekFrom::Start(target))?;
+        let mut reader = BufReader::new(&mut file);
+        let mut skipped = Vec::new();
+        let start = match separator {
+            RecordSeparator::Newline => target + reader.read_until(b'\n', &mut skipped)? as u64,
+            RecordSeparator::Byte(byte) => target + reader.read_until(byte, &mut skipped)? as u64,
+            RecordSeparator::CrLf => loop {
+                if reader.read_until(b'\n', &mut skipped)? == 0 || skipped.ends_with(b"\r\n") {
+                    break target + skipped.len() as u64;
+                }
+            },
+        };
+
+        if start < len && start > *starts.last().unwrap() {
+            starts.push(start);
+        }
+    }
+
+    let ends = starts.iter().skip(1).copied().chain([len]);
+    Ok(starts.iter().copied().zip(ends).collect())
+}
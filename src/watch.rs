@@ -0,0 +1,166 @@
+//! Contains the logic to continuously analyze the files inside a watched directory.
+//!
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use notify::event::{CreateKind, EventKind, ModifyKind};
+use notify::{RecursiveMode, Watcher};
+
+use crate::Analyzer;
+
+/// Feeds all files that are created or appended to inside a directory into a single analyzer.
+pub struct DirectoryWatcher<A> {
+    analyzer: A,
+
+    /// Number of bytes already analyzed for each file.
+    offsets: HashMap<PathBuf, u64>,
+
+    /// Whether the trailing `\r` of each line is kept.
+    keep_cr: bool,
+}
+
+impl<A> DirectoryWatcher<A> {
+    pub fn new(analyzer: A) -> Self {
+        DirectoryWatcher {
+            analyzer,
+            offsets: HashMap::new(),
+            keep_cr: false,
+        }
+    }
+
+    /// Sets whether the trailing `\r` of each line is kept, instead of removing it from lines
+    /// ending in `\r\n`.
+    pub fn keep_cr(&mut self, keep_cr: bool) {
+        self.keep_cr = keep_cr;
+    }
+
+    /// Returns the analyzer holding the aggregated results.
+    pub fn analyzer(&mut self) -> &mut A {
+        &mut self.analyzer
+    }
+
+    /// Consumes the watcher and returns the analyzer holding the aggregated results.
+    pub fn into_analyzer(self) -> A {
+        self.analyzer
+    }
+
+    /// Analyzes all complete lines of the file which have been appended since the last call.
+    ///
+    /// A trailing line without a newline is left for the next call, since the file might still be
+    /// in the process of being written. Returns whether any new lines were analyzed.
+    pub fn process<T, P>(&mut self, path: P) -> Result<bool, String>
+    where
+        A: Analyzer<T>,
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let offset = self.offsets.entry(path.to_path_buf()).or_insert(0);
+
+        let mut file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        if file.metadata().map(|m| m.len()).unwrap_or(0) < *offset {
+            // The file was truncated, so start reading it from the beginning again.
+            *offset = 0;
+        }
+        file.seek(SeekFrom::Start(*offset))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let mut reader = BufReader::new(file);
+        let mut buf = Vec::new();
+        let mut changed = false;
+        loop {
+            buf.clear();
+            let read = reader
+                .read_until(b'\n', &mut buf)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            if read == 0 || buf.last() != Some(&b'\n') {
+                break;
+            }
+
+            *offset += read as u64;
+            buf.pop();
+            if !self.keep_cr && buf.last() == Some(&b'\r') {
+                buf.pop();
+            }
+            self.analyzer
+                .analyze(String::from_utf8_lossy(&buf).into_owned());
+            changed = true;
+        }
+
+        Ok(changed)
+    }
+
+    /// Watches the directory for new and modified files until `stop` is set, analyzing each of
+    /// them, and calls `render` every `interval` if new lines were analyzed.
+    ///
+    /// Files which already exist when the watch starts are ignored, unless they are modified.
+    pub fn watch<T, P, F>(
+        &mut self,
+        dir: P,
+        interval: Duration,
+        stop: &AtomicBool,
+        mut render: F,
+    ) -> Result<(), String>
+    where
+        A: Analyzer<T>,
+        P: AsRef<Path>,
+        F: FnMut(&mut A),
+    {
+        let (tx, rx) = channel();
+        let mut watcher =
+            notify::recommended_watcher(tx).map_err(|e| format!("Unable to watch: {}", e))?;
+        watcher
+            .watch(dir.as_ref(), RecursiveMode::NonRecursive)
+            .map_err(|e| format!("Unable to watch {}: {}", dir.as_ref().display(), e))?;
+
+        for entry in std::fs::read_dir(dir.as_ref()).map_err(|e| format!("{}", e))? {
+            let path = entry.map_err(|e| format!("{}", e))?.path();
+            let len = path.metadata().map(|m| m.len()).unwrap_or(0);
+            self.offsets.insert(path, len);
+        }
+
+        let mut changed = false;
+        let mut last_render = Instant::now();
+        while !stop.load(Ordering::SeqCst) {
+            match rx.recv_timeout(Duration::from_millis(100)) {
+                Ok(Ok(event)) => match event.kind {
+                    EventKind::Create(CreateKind::File)
+                    | EventKind::Create(CreateKind::Any)
+                    | EventKind::Modify(ModifyKind::Data(_))
+                    | EventKind::Modify(ModifyKind::Any) => {
+                        for path in event.paths.iter().filter(|path| path.is_file()) {
+                            match self.process::<T, _>(path) {
+                                Ok(processed) => changed |= processed,
+                                Err(e) => eprintln!("{}", e),
+                            }
+                        }
+                    }
+                    _ => {}
+                },
+                Ok(Err(e)) => eprintln!("Watch error: {}", e),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            if changed && last_render.elapsed() >= interval {
+                render(&mut self.analyzer);
+                changed = false;
+                last_render = Instant::now();
+            }
+        }
+
+        // Pick up anything that was written since the last event before shutting down.
+        let paths: Vec<_> = self.offsets.keys().cloned().collect();
+        for path in paths.iter().filter(|path| path.is_file()) {
+            if let Err(e) = self.process::<T, _>(path) {
+                eprintln!("{}", e);
+            }
+        }
+
+        Ok(())
+    }
+}